@@ -0,0 +1,81 @@
+//! Round trips of one byte over many connections at once, between two interfaces on a loopback
+//! link.
+//!
+//! Every round trip blocks a reader on each side until its byte arrives, so this measures how
+//! quickly the packet loop gets data to the right blocked `TcpStream::read` while many others
+//! are waiting too. It needs no tun device, so it can run anywhere:
+//!
+//! ```text
+//! cargo bench --bench wakeups
+//! ```
+
+use std::{
+    io::{Read, Write},
+    net::{Ipv4Addr, SocketAddrV4},
+    thread,
+    time::{Duration, Instant},
+};
+
+use thunder::{Interface, Loopback};
+
+const SERVER: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 1);
+const CLIENT: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 2);
+const ROUND_TRIPS_PER_STREAM: usize = 200;
+
+fn run(streams: usize) -> Duration {
+    let (a, b) = Loopback::pair().unwrap();
+    let mut server = Interface::with_device(a, SERVER).unwrap();
+    let mut client = Interface::with_device(b, CLIENT).unwrap();
+    let mut listener = server.bind(7000).unwrap();
+
+    let mut echoes = Vec::new();
+    let mut pingers = Vec::new();
+    for _ in 0..streams {
+        pingers.push(client.connect(SocketAddrV4::new(SERVER, 7000)).unwrap());
+        let mut stream = listener.accept().unwrap();
+        echoes.push(thread::spawn(move || {
+            let mut byte = [0u8];
+            while stream.read(&mut byte).unwrap() == 1 {
+                stream.write_all(&byte).unwrap();
+            }
+        }));
+    }
+
+    let start = Instant::now();
+    let pingers: Vec<_> = pingers
+        .into_iter()
+        .map(|mut stream| {
+            thread::spawn(move || {
+                let mut byte = [0u8];
+                for _ in 0..ROUND_TRIPS_PER_STREAM {
+                    stream.write_all(&byte).unwrap();
+                    stream.read_exact(&mut byte).unwrap();
+                }
+                stream
+            })
+        })
+        .collect();
+    let streams: Vec<_> = pingers.into_iter().map(|t| t.join().unwrap()).collect();
+    let elapsed = start.elapsed();
+
+    // closing the pinging ends lets the echoing ends see the end of their streams
+    drop(streams);
+    for echo in echoes {
+        echo.join().unwrap();
+    }
+    elapsed
+}
+
+fn main() {
+    println!("{:>8} {:>12} {:>15}", "streams", "elapsed", "round trips/s");
+    for streams in [1, 16, 64] {
+        let elapsed = run(streams);
+        let round_trips = (streams * ROUND_TRIPS_PER_STREAM) as f64;
+        println!(
+            "{:>8} {:>10.1?} {:>15.0}",
+            streams,
+            elapsed,
+            round_trips / elapsed.as_secs_f64()
+        );
+    }
+}
@@ -0,0 +1,111 @@
+use std::{
+    future::Future,
+    io,
+    pin::Pin,
+    sync::{mpsc, Arc, Mutex},
+    task::{Context, Wake, Waker},
+};
+
+type BoxFuture = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// A task is rescheduled by sending itself back to the executor's queue when woken.
+struct Task {
+    future: Mutex<Option<BoxFuture>>,
+    queue: mpsc::Sender<Arc<Task>>,
+}
+
+impl Wake for Task {
+    fn wake(self: Arc<Self>) {
+        let queue = self.queue.clone();
+        let _ = queue.send(self);
+    }
+}
+
+/// Minimal single-threaded executor; thunder's futures only need a `Waker`, so any runtime
+/// would do just as well.
+struct Executor {
+    queue: mpsc::Sender<Arc<Task>>,
+    ready: mpsc::Receiver<Arc<Task>>,
+}
+
+impl Executor {
+    fn new() -> Self {
+        let (queue, ready) = mpsc::channel();
+        Self { queue, ready }
+    }
+
+    fn spawner(&self) -> Spawner {
+        Spawner {
+            queue: self.queue.clone(),
+        }
+    }
+
+    fn run(self) {
+        drop(self.queue);
+        while let Ok(task) = self.ready.recv() {
+            let mut slot = task.future.lock().unwrap();
+            if let Some(mut future) = slot.take() {
+                let waker = Waker::from(task.clone());
+                let mut cx = Context::from_waker(&waker);
+                if future.as_mut().poll(&mut cx).is_pending() {
+                    *slot = Some(future);
+                }
+            }
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Spawner {
+    queue: mpsc::Sender<Arc<Task>>,
+}
+
+impl Spawner {
+    fn spawn(&self, future: impl Future<Output = ()> + Send + 'static) {
+        let task = Arc::new(Task {
+            future: Mutex::new(Some(Box::pin(future))),
+            queue: self.queue.clone(),
+        });
+        let _ = self.queue.send(task);
+    }
+}
+
+async fn serve(mut stream: thunder::TcpStream) -> io::Result<()> {
+    let greeting = b"hello from thunder";
+    let mut written = 0;
+    while written < greeting.len() {
+        written += stream.write_async(&greeting[written..]).await?;
+    }
+
+    loop {
+        let mut buf = [0u8; 512];
+        let n = stream.read_async(&mut buf).await?;
+        eprintln!("read {}bytes of data", n);
+        if n == 0 {
+            eprintln!("no more data");
+            return Ok(());
+        }
+        println!("{}", String::from_utf8_lossy(&buf[..n]));
+    }
+}
+
+fn main() -> io::Result<()> {
+    let mut i = thunder::Interface::new()?;
+    let mut listener = i.bind(9000)?;
+
+    let executor = Executor::new();
+    let spawner = executor.spawner();
+    executor.spawner().spawn(async move {
+        while let Ok(stream) = listener.accept_async().await {
+            eprintln!("got connection!");
+            spawner.spawn(async move {
+                if let Err(e) = serve(stream).await {
+                    eprintln!("connection failed: {e}");
+                }
+            });
+        }
+    });
+    executor.run();
+
+    Ok(())
+}
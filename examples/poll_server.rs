@@ -0,0 +1,74 @@
+use std::{
+    collections::HashMap,
+    io::{self, Read, Write},
+    time::Duration,
+};
+
+use thunder::{Available, Poller, Token};
+
+const LISTENER: Token = Token(0);
+
+/// Echo server that serves every connection from a single thread.
+fn main() -> io::Result<()> {
+    let mut i = thunder::Interface::new()?;
+    let mut listener = i.bind(9000)?;
+    listener.set_nonblocking(true)?;
+
+    let poller = Poller::new();
+    poller.register(&listener, LISTENER, Available::READ)?;
+
+    let mut streams = HashMap::new();
+    let mut next_token = 1;
+    let mut events = Vec::new();
+
+    loop {
+        poller.wait(&mut events, Some(Duration::from_secs(1)))?;
+
+        for event in &events {
+            if event.token() == LISTENER {
+                loop {
+                    match listener.accept() {
+                        Ok(mut stream) => {
+                            eprintln!("got connection!");
+                            stream.set_nonblocking(true)?;
+                            let token = Token(next_token);
+                            next_token += 1;
+                            poller.register(&stream, token, Available::READ)?;
+                            streams.insert(token, stream);
+                        }
+                        Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                        Err(e) => return Err(e),
+                    }
+                }
+                continue;
+            }
+
+            let Some(stream) = streams.get_mut(&event.token()) else {
+                continue;
+            };
+
+            let mut buf = [0u8; 512];
+            let done = loop {
+                match stream.read(&mut buf) {
+                    Ok(0) => break true,
+                    Ok(n) => {
+                        // a full send queue just drops the echo; this is only an example
+                        let _ = stream.write(&buf[..n]);
+                    }
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => break false,
+                    Err(e) => {
+                        eprintln!("connection failed: {e}");
+                        break true;
+                    }
+                }
+            };
+
+            if done {
+                eprintln!("no more data");
+                if let Some(stream) = streams.remove(&event.token()) {
+                    poller.deregister(&stream)?;
+                }
+            }
+        }
+    }
+}
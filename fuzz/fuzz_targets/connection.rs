@@ -0,0 +1,94 @@
+//! One connection, opened either way, fed segments and timer ticks through its sans-IO
+//! interface. Malformed input may be refused, but nothing may panic.
+
+#![no_main]
+
+use std::{
+    net::SocketAddrV4,
+    time::{Duration, Instant},
+};
+
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+use thunder::{Connection, Interface};
+use thunder_fuzz::{Peer, Segment, REMOTE};
+
+const LOCAL: SocketAddrV4 = SocketAddrV4::new(std::net::Ipv4Addr::new(10, 0, 0, 1), 8080);
+
+#[derive(Arbitrary, Debug)]
+enum Op {
+    Segment(Segment),
+    /// whatever bytes, as a packet
+    Raw(Vec<u8>),
+    /// milliseconds until the timers are looked at
+    Timer(u16),
+    Write(Vec<u8>),
+    Read(u16),
+    Close,
+}
+
+#[derive(Arbitrary, Debug)]
+struct Input {
+    /// accept a SYN built from `syn` instead of connecting
+    passive: bool,
+    syn: Segment,
+    ops: Vec<Op>,
+}
+
+fuzz_target!(|input: Input| {
+    let config = Interface::builder()
+        .initial_rtt(Duration::from_millis(100))
+        .rto(Duration::from_millis(200), Duration::from_secs(10))
+        .config()
+        .unwrap();
+    let mut now = Instant::now();
+    let mut peer = Peer::new(40000);
+    let remote = SocketAddrV4::new(REMOTE, peer.port);
+
+    let (mut conn, output) = if input.passive {
+        let mut syn = input.syn;
+        syn.flags = (syn.flags | 0b10) & !0b10100;
+        let packet = peer.packet(&syn, (*LOCAL.ip(), LOCAL.port()));
+        match Connection::accept(&packet, &config, now).unwrap() {
+            Some(accepted) => accepted,
+            None => return,
+        }
+    } else {
+        Connection::connect(LOCAL, remote, &config, now).unwrap()
+    };
+    let mut sent = output.segments;
+
+    for op in input.ops {
+        for packet in sent.drain(..) {
+            peer.observe(&packet);
+        }
+
+        let output = match op {
+            Op::Segment(segment) => {
+                let packet = peer.packet(&segment, (*LOCAL.ip(), LOCAL.port()));
+                conn.on_segment(&packet, now).unwrap()
+            }
+            Op::Raw(bytes) => match conn.on_segment(&bytes, now) {
+                Ok(output) => output,
+                Err(_) => continue,
+            },
+            Op::Timer(ms) => {
+                now += Duration::from_millis(ms.into());
+                conn.on_timer(now).unwrap()
+            }
+            Op::Write(data) => {
+                conn.try_write(&data);
+                conn.send_pending(now).unwrap()
+            }
+            Op::Read(n) => {
+                conn.try_read(&mut vec![0; n.into()]);
+                continue;
+            }
+            Op::Close => {
+                let _ = conn.close();
+                conn.send_pending(now).unwrap()
+            }
+        };
+        sent = output.segments;
+    }
+});
@@ -0,0 +1,52 @@
+//! Segments from a few peers, the passage of time and application calls, thrown at a whole
+//! stack listening on a port. Whatever comes in, handling it must not fail.
+
+#![no_main]
+
+use std::time::Duration;
+
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+use thunder::fuzz::Stack;
+use thunder_fuzz::{Peer, Segment};
+
+#[derive(Arbitrary, Debug)]
+enum Op {
+    Segment {
+        peer: u8,
+        segment: Segment,
+    },
+    /// whatever bytes, as a packet
+    Raw(Vec<u8>),
+    /// milliseconds
+    Advance(u16),
+    Write(Vec<u8>),
+    Read,
+    Close,
+}
+
+fuzz_target!(|ops: Vec<Op>| {
+    let mut stack = Stack::new().unwrap();
+    let mut peers: Vec<_> = (40000..40004).map(Peer::new).collect();
+
+    for op in ops {
+        match op {
+            Op::Segment { peer, segment } => {
+                let peer = &peers[peer as usize % peers.len()];
+                let packet = peer.packet(&segment, (Stack::ADDR, Stack::PORT));
+                stack.receive(&packet).unwrap();
+            }
+            Op::Raw(bytes) => stack.receive(&bytes).unwrap(),
+            Op::Advance(ms) => stack.advance(Duration::from_millis(ms.into())).unwrap(),
+            Op::Write(data) => stack.write(&data).unwrap(),
+            Op::Read => stack.read().unwrap(),
+            Op::Close => stack.close().unwrap(),
+        }
+
+        for packet in stack.sent() {
+            for peer in &mut peers {
+                peer.observe(&packet);
+            }
+        }
+    }
+});
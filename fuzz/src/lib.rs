@@ -0,0 +1,90 @@
+//! Turning fuzz input into segments a stack has a chance of taking seriously: numbers relative
+//! to what the stack has sent, so that most of them land in its window.
+
+use std::net::Ipv4Addr;
+
+use arbitrary::Arbitrary;
+
+/// Where the peers live.
+pub const REMOTE: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 2);
+/// Payloads are cut short so that a segment still fits in a packet.
+const MAX_PAYLOAD: usize = 1400;
+
+#[derive(Arbitrary, Debug)]
+pub struct Segment {
+    /// FIN, SYN, RST, PSH, ACK and URG, from the lowest bit up
+    pub flags: u8,
+    /// relative to the last ACK the stack sent the peer
+    pub seq: i16,
+    /// relative to the end of the last segment the stack sent the peer
+    pub ack: i16,
+    pub window: u16,
+    pub urgent: u16,
+    pub payload: Vec<u8>,
+}
+
+/// One end talking to the stack from [`REMOTE`], and what it has heard back.
+#[derive(Debug, Clone, Copy)]
+pub struct Peer {
+    pub port: u16,
+    /// the sequence number the stack expects from us next
+    next: u32,
+    /// the end of what the stack has sent us
+    end: u32,
+}
+
+impl Peer {
+    pub fn new(port: u16) -> Self {
+        Self {
+            port,
+            next: 0,
+            end: 0,
+        }
+    }
+
+    /// Follow a packet the stack sent, if it was to this peer.
+    pub fn observe(&mut self, packet: &[u8]) {
+        let Ok((_, rest)) = etherparse::Ipv4Header::from_slice(packet) else {
+            return;
+        };
+        let Ok((tcph, payload)) = etherparse::TcpHeader::from_slice(rest) else {
+            return;
+        };
+        if tcph.destination_port != self.port {
+            return;
+        }
+
+        if tcph.ack {
+            self.next = tcph.acknowledgment_number;
+        }
+        let len = payload.len() as u32 + tcph.syn as u32 + tcph.fin as u32;
+        self.end = tcph.sequence_number.wrapping_add(len);
+    }
+
+    /// The IPv4 packet carrying `segment` from this peer to `to`.
+    pub fn packet(&self, segment: &Segment, to: (Ipv4Addr, u16)) -> Vec<u8> {
+        let seq = self.next.wrapping_add(segment.seq as u32);
+        let mut tcph = etherparse::TcpHeader::new(self.port, to.1, seq, segment.window);
+        let flag = |bit: u8| segment.flags & (1 << bit) != 0;
+        tcph.fin = flag(0);
+        tcph.syn = flag(1);
+        tcph.rst = flag(2);
+        tcph.psh = flag(3);
+        if flag(4) {
+            tcph.ack = true;
+            tcph.acknowledgment_number = self.end.wrapping_add(segment.ack as u32);
+        }
+        if flag(5) {
+            tcph.urg = true;
+            tcph.urgent_pointer = segment.urgent;
+        }
+
+        let payload = &segment.payload[..segment.payload.len().min(MAX_PAYLOAD)];
+        let mut packet = Vec::new();
+        etherparse::PacketBuilder::ipv4(REMOTE.octets(), to.0.octets(), 64)
+            .tcp_header(tcph)
+            .write(&mut packet, payload)
+            .expect("segment fits in a packet");
+        packet
+    }
+}
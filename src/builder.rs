@@ -0,0 +1,291 @@
+use std::{io, net::Ipv4Addr, sync::Arc, time::Duration};
+
+use crate::{
+    config::Config, device::Tun, isn::IsnGenerator, link, Clock, Ethernet, Interface, NetDevice,
+};
+
+/// Room for the Ethernet header on a tap device.
+const ETHERNET_HEADER: usize = 14;
+/// Smallest MTU every IPv4 link has to support (RFC 791).
+const MIN_MTU: usize = 68;
+
+/// Configures and starts an [`Interface`], so several differently set up stacks can run on one
+/// host:
+///
+/// ```no_run
+/// use std::{net::Ipv4Addr, time::Duration};
+///
+/// let iface = thunder::Interface::builder()
+///     .name("tun1")
+///     .addr(Ipv4Addr::new(10, 1, 0, 2), 24)
+///     .host_addr(Ipv4Addr::new(10, 1, 0, 1))
+///     .mtu(1400)
+///     .send_buffer(64 * 1024)
+///     .delayed_ack(Duration::from_millis(10))
+///     .build()?;
+/// # Ok::<(), std::io::Error>(())
+/// ```
+///
+/// Anything left unset keeps the defaults of [`Interface::new`].
+pub struct InterfaceBuilder {
+    name: String,
+    /// run on a tap device with this MAC address instead of a tun device
+    mac: Option<[u8; 6]>,
+    addr: Ipv4Addr,
+    prefix: u8,
+    host_addr: Option<Ipv4Addr>,
+    mtu: Option<usize>,
+    /// key initial sequence numbers from this instead of a random secret
+    isn_seed: Option<u64>,
+    config: Config,
+}
+
+impl Default for InterfaceBuilder {
+    fn default() -> Self {
+        Self {
+            name: "tun0".to_string(),
+            mac: None,
+            addr: Ipv4Addr::new(192, 168, 0, 2),
+            prefix: 24,
+            host_addr: None,
+            mtu: None,
+            isn_seed: None,
+            config: Config::default(),
+        }
+    }
+}
+
+impl InterfaceBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Name of the tun or tap device to open.
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = name.into();
+        self
+    }
+
+    /// Open a tap device instead of a tun device, answering ARP as `mac`.
+    pub fn tap(mut self, mac: [u8; 6]) -> Self {
+        self.mac = Some(mac);
+        self
+    }
+
+    /// The stack's own address, and the length of the prefix of the link's subnet.
+    pub fn addr(mut self, addr: Ipv4Addr, prefix: u8) -> Self {
+        self.addr = addr;
+        self.prefix = prefix;
+        self
+    }
+
+    /// Assign `addr` to the host's end of the device and bring the link up, which needs
+    /// `CAP_NET_ADMIN`. Without it, the link has to be set up from outside, e.g. with `ip addr`
+    /// and `ip link`.
+    pub fn host_addr(mut self, addr: Ipv4Addr) -> Self {
+        self.host_addr = Some(addr);
+        self
+    }
+
+    /// Largest IP packet on the link; also set on the device when `build` opens it.
+    pub fn mtu(mut self, mtu: usize) -> Self {
+        self.mtu = Some(mtu);
+        self
+    }
+
+    /// Bytes of written data each connection holds until they are acknowledged.
+    pub fn send_buffer(mut self, size: usize) -> Self {
+        self.config.send_buffer = size;
+        self
+    }
+
+    /// Window each connection advertises to its peer.
+    pub fn recv_window(mut self, window: u16) -> Self {
+        self.config.recv_window = window;
+        self
+    }
+
+    /// Initial sequence number of every connection, instead of a different, unpredictable one
+    /// for each (RFC 6528). Easy to follow in a capture, but just as easy for an off-path
+    /// attacker to guess.
+    pub fn initial_sequence(mut self, iss: u32) -> Self {
+        self.config.iss = Some(iss);
+        self
+    }
+
+    /// Derive the secret behind the initial sequence numbers from `seed` rather than drawing it
+    /// at random, so that tests see the same numbers on every run.
+    pub fn isn_seed(mut self, seed: u64) -> Self {
+        self.isn_seed = Some(seed);
+        self
+    }
+
+    /// Half-open connections a listener keeps; SYNs beyond them are answered with a SYN cookie
+    /// (RFC 4987 S3.6) and nothing is kept until the peer's ACK brings it back, so a SYN flood
+    /// cannot use up memory. Connections opened this way only remember the peer's MSS, rounded
+    /// down.
+    pub fn syn_backlog(mut self, backlog: usize) -> Self {
+        self.config.syn_backlog = backlog;
+        self
+    }
+
+    /// Connections a listener keeps that have completed the handshake but not been accepted
+    /// yet. Beyond them, SYNs are dropped, so that the peer tries again later, and ACKs that
+    /// bring back a SYN cookie are answered with a reset.
+    pub fn accept_backlog(mut self, backlog: usize) -> Self {
+        self.config.accept_backlog = backlog;
+        self
+    }
+
+    /// `seed` as the [`isn_seed`](Self::isn_seed), unless one has been set already.
+    pub(crate) fn default_isn_seed(mut self, seed: u64) -> Self {
+        self.isn_seed.get_or_insert(seed);
+        self
+    }
+
+    /// How long an ACK may wait for data to piggyback on.
+    pub fn delayed_ack(mut self, delay: Duration) -> Self {
+        self.config.delayed_ack = delay;
+        self
+    }
+
+    /// Maximum segment lifetime; connections linger in TIME-WAIT for twice as long.
+    pub fn msl(mut self, msl: Duration) -> Self {
+        self.config.msl = msl;
+        self
+    }
+
+    /// Round-trip time assumed until the first one is measured.
+    pub fn initial_rtt(mut self, rtt: Duration) -> Self {
+        self.config.initial_rtt = rtt;
+        self
+    }
+
+    /// Bounds of the retransmission timeout.
+    pub fn rto(mut self, min: Duration, max: Duration) -> Self {
+        self.config.min_rto = min;
+        self.config.max_rto = max;
+        self
+    }
+
+    /// Retransmissions of an unanswered SYN, and of unacknowledged data, after which the
+    /// connection times out (RFC 1122 S4.2.3.5). With the default timeouts, 6 and 15 give up
+    /// after about two minutes and a quarter of an hour.
+    pub fn retries(mut self, syn: u32, data: u32) -> Self {
+        self.config.syn_retries = syn;
+        self.config.data_retries = data;
+        self
+    }
+
+    /// Time between keepalive probes, and how many may go unanswered before the connection is
+    /// dropped.
+    pub fn keepalive(mut self, interval: Duration, probes: u32) -> Self {
+        self.config.keepalive_interval = interval;
+        self.config.keepalive_probes = probes;
+        self
+    }
+
+    /// Whether to drop received packets whose IPv4 header or TCP checksum is wrong (the
+    /// default); turn it off for devices that guarantee integrity themselves.
+    pub fn verify_checksums(mut self, verify: bool) -> Self {
+        self.config.verify_checksums = verify;
+        self
+    }
+
+    /// Read the time from `clock`, e.g. a [`MockClock`](crate::MockClock) to test timers
+    /// without waiting for them.
+    pub fn clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.config.clock = Arc::new(clock);
+        self
+    }
+
+    /// Open the tun (or tap) device, configure it, and start the stack on it.
+    pub fn build(self) -> io::Result<Interface> {
+        self.check()?;
+
+        let mode = match self.mac {
+            Some(_) => tun_tap::Mode::Tap,
+            None => tun_tap::Mode::Tun,
+        };
+        let iface = tun_tap::Iface::without_packet_info(&self.name, mode)?;
+        if let Some(mtu) = self.mtu {
+            link::set_mtu(&self.name, mtu)?;
+        }
+        if let Some(host_addr) = self.host_addr {
+            link::configure(&self.name, host_addr, self.prefix)?;
+        }
+
+        let mtu = self.mtu.unwrap_or(1500);
+        match self.mac {
+            Some(mac) => {
+                let nic = Tun::new(iface, mtu + ETHERNET_HEADER);
+                let ethernet =
+                    Ethernet::new(nic, mac, self.addr).with_clock(self.config.clock.clone());
+                self.build_with_device(ethernet)
+            }
+            None => self.build_with_device(Tun::new(iface, mtu)),
+        }
+    }
+
+    /// Start the stack on `nic`; the device name and host address are ignored, and the MTU
+    /// can only lower the device's.
+    pub fn build_with_device<D: NetDevice + Send + 'static>(self, nic: D) -> io::Result<Interface> {
+        let (addr, config) = self.finish(nic.mtu())?;
+        Interface::start(nic, addr, config)
+    }
+
+    /// The settings of the stack, for driving [`Connection`](crate::Connection)s directly;
+    /// the MTU defaults to 1500, and the device name, addresses and clock are ignored.
+    pub fn config(self) -> io::Result<Config> {
+        let mtu = self.mtu.unwrap_or(1500);
+        Ok(self.finish(mtu)?.1)
+    }
+
+    /// Check the settings, and resolve the MTU against that of the device the stack will run
+    /// on.
+    pub(crate) fn finish(self, device_mtu: usize) -> io::Result<(Ipv4Addr, Config)> {
+        self.check()?;
+
+        let mut config = self.config;
+        if let Some(seed) = self.isn_seed {
+            config.isn = IsnGenerator::seeded(seed);
+        }
+        config.mtu = self.mtu.map_or(device_mtu, |mtu| mtu.min(device_mtu));
+        if config.mtu < MIN_MTU {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "mtu is too small",
+            ));
+        }
+
+        Ok((self.addr, config))
+    }
+
+    fn check(&self) -> io::Result<()> {
+        let invalid = |msg| Err(io::Error::new(io::ErrorKind::InvalidInput, msg));
+
+        if self.prefix > 32 {
+            return invalid("prefix is longer than 32 bits");
+        }
+        if self.mtu.is_some_and(|mtu| mtu < MIN_MTU) {
+            return invalid("mtu is too small");
+        }
+        if self.config.send_buffer == 0 {
+            return invalid("send buffer is empty");
+        }
+        if self.config.min_rto > self.config.max_rto {
+            return invalid("minimum retransmission timeout is above the maximum");
+        }
+        if let Some(host_addr) = self.host_addr {
+            let mask = u32::from(link::netmask(self.prefix));
+            if host_addr == self.addr {
+                return invalid("host and stack have the same address");
+            }
+            if u32::from(host_addr) & mask != u32::from(self.addr) & mask {
+                return invalid("host address is not in the stack's subnet");
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,80 @@
+use std::{
+    io::{self, Write},
+    os::fd::{AsRawFd, RawFd},
+    sync::Arc,
+    time::{Instant, SystemTime},
+};
+
+use crate::{clock::Clock, device::NetDevice, pcap::PcapWriter};
+
+pub(crate) type Sink = Box<dyn Write + Send>;
+
+/// The packet thread's view of the device: every packet that goes through it is copied to the
+/// capture sink, if one has been set with `Interface::capture`.
+pub(crate) struct Captured<D> {
+    device: D,
+    sink: Option<PcapWriter<Sink>>,
+    /// the interface's clock, which packets are stamped with
+    clock: Arc<dyn Clock>,
+    /// the wall-clock time of an instant of `clock`, to turn its instants into timestamps
+    epoch: (Instant, SystemTime),
+}
+
+impl<D: NetDevice> Captured<D> {
+    pub(crate) fn new(device: D, clock: Arc<dyn Clock>) -> Self {
+        let epoch = (clock.now(), SystemTime::now());
+        Self {
+            device,
+            sink: None,
+            clock,
+            epoch,
+        }
+    }
+
+    /// Replace the capture sink, flushing the old one.
+    pub(crate) fn set_sink(&mut self, sink: Option<Sink>) -> io::Result<()> {
+        if let Some(mut old) = self.sink.take() {
+            old.flush()?;
+        }
+        self.sink = sink.map(PcapWriter::new).transpose()?;
+        Ok(())
+    }
+
+    fn record(&mut self, packet: &[u8]) {
+        let Some(sink) = self.sink.as_mut() else {
+            return;
+        };
+        let (at, wall) = self.epoch;
+        let timestamp = wall + self.clock.now().saturating_duration_since(at);
+        if let Err(e) = sink.write_packet(timestamp, packet) {
+            // losing the capture is no reason to stop the stack
+            eprintln!("stopping packet capture: {e}");
+            self.sink = None;
+        }
+    }
+}
+
+impl<D: AsRawFd> AsRawFd for Captured<D> {
+    fn as_raw_fd(&self) -> RawFd {
+        self.device.as_raw_fd()
+    }
+}
+
+impl<D: NetDevice> NetDevice for Captured<D> {
+    fn send(&mut self, packet: &[u8]) -> io::Result<usize> {
+        self.record(packet);
+        self.device.send(packet)
+    }
+
+    fn recv(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.device.recv(buf)?;
+        if n > 0 {
+            self.record(&buf[..n]);
+        }
+        Ok(n)
+    }
+
+    fn mtu(&self) -> usize {
+        self.device.mtu()
+    }
+}
@@ -0,0 +1,133 @@
+use std::{
+    fmt,
+    sync::{Arc, Mutex, Weak},
+    time::{Duration, Instant},
+};
+
+use crate::{poll, Foobar, InterfaceRequest};
+
+/// Where the stack reads the time from: every timer, timeout and round-trip measurement goes
+/// through the interface's clock.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> Instant;
+
+    /// The clock's [`MockClock`], if time only moves when a test advances it; the packet thread
+    /// then sleeps until it is advanced instead of until the next timer.
+    #[doc(hidden)]
+    fn as_mock(&self) -> Option<&MockClock> {
+        None
+    }
+}
+
+/// The real time, which is what interfaces use unless told otherwise.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+struct MockState {
+    now: Instant,
+    /// interfaces whose timers follow this clock
+    interfaces: Vec<Weak<Foobar>>,
+    /// pollers whose timeouts follow this clock
+    pollers: Vec<Weak<poll::Shared>>,
+}
+
+/// A clock that stands still until [`advance`](MockClock::advance)d, so timer behaviour can be
+/// tested without sleeping:
+///
+/// ```
+/// use std::{net::Ipv4Addr, time::Duration};
+///
+/// let clock = thunder::MockClock::new();
+/// let (a, _b) = thunder::Loopback::pair()?;
+/// let iface = thunder::Interface::builder()
+///     .addr(Ipv4Addr::new(10, 0, 0, 1), 24)
+///     .clock(clock.clone())
+///     .build_with_device(a)?;
+///
+/// // every retransmission, delayed ACK or timeout due in the next minute happens now
+/// clock.advance(Duration::from_secs(60));
+/// # Ok::<(), std::io::Error>(())
+/// ```
+///
+/// Clones share the same time, so one clock can drive several interfaces.
+#[derive(Clone)]
+pub struct MockClock {
+    state: Arc<Mutex<MockState>>,
+}
+
+impl MockClock {
+    /// A clock starting at the current time.
+    pub fn new() -> Self {
+        Self {
+            state: Arc::new(Mutex::new(MockState {
+                now: Instant::now(),
+                interfaces: Vec::new(),
+                pollers: Vec::new(),
+            })),
+        }
+    }
+
+    /// Move the clock forward, and wait for every interface using it to handle the timers that
+    /// came due.
+    ///
+    /// Packets those timers send may still be on their way to another interface when this
+    /// returns.
+    pub fn advance(&self, by: Duration) {
+        let (interfaces, pollers): (Vec<_>, Vec<_>) = {
+            let mut state = self.state.lock().unwrap();
+            state.now += by;
+            state.interfaces.retain(|ih| ih.strong_count() > 0);
+            state.pollers.retain(|p| p.strong_count() > 0);
+            (
+                state.interfaces.iter().filter_map(Weak::upgrade).collect(),
+                state.pollers.iter().filter_map(Weak::upgrade).collect(),
+            )
+        };
+
+        for ih in interfaces {
+            // fails only once the interface has shut down, when there are no timers left
+            let _ = ih.call(|ack| InterfaceRequest::Tick { ack });
+        }
+        for poller in pollers {
+            poller.wake();
+        }
+    }
+
+    pub(crate) fn attach(&self, ih: Weak<Foobar>) {
+        self.state.lock().unwrap().interfaces.push(ih);
+    }
+
+    pub(crate) fn attach_poller(&self, poller: Weak<poll::Shared>) {
+        self.state.lock().unwrap().pollers.push(poller);
+    }
+}
+
+impl Default for MockClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for MockClock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MockClock")
+            .field("now", &self.state.lock().unwrap().now)
+            .finish()
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        self.state.lock().unwrap().now
+    }
+
+    fn as_mock(&self) -> Option<&MockClock> {
+        Some(self)
+    }
+}
@@ -0,0 +1,74 @@
+use std::{sync::Arc, time::Duration};
+
+use crate::{
+    clock::{Clock, SystemClock},
+    isn::IsnGenerator,
+};
+
+/// Parameters of one stack, set through [`InterfaceBuilder`](crate::InterfaceBuilder) and
+/// shared by all of its connections.
+///
+/// [`InterfaceBuilder::config`](crate::InterfaceBuilder::config) hands them out, for driving
+/// [`Connection`](crate::Connection)s without an interface.
+#[derive(Clone)]
+pub struct Config {
+    /// largest packet we send, headers included
+    pub(crate) mtu: usize,
+    /// bytes of written data held until the peer acknowledges them
+    pub(crate) send_buffer: usize,
+    /// window advertised to the peer
+    pub(crate) recv_window: u16,
+    /// initial send sequence number of every connection, instead of generating them
+    pub(crate) iss: Option<u32>,
+    /// where the initial send sequence numbers come from otherwise
+    pub(crate) isn: IsnGenerator,
+    /// half-open connections a listener keeps before it answers SYNs with cookies
+    pub(crate) syn_backlog: usize,
+    /// established connections a listener keeps until they are accepted
+    pub(crate) accept_backlog: usize,
+    /// how long an ACK may be held back waiting for data to piggyback on (RFC 1122 S4.2.3.2)
+    pub(crate) delayed_ack: Duration,
+    /// maximum segment lifetime; TIME-WAIT lasts twice as long
+    pub(crate) msl: Duration,
+    /// round-trip time assumed until the first measurement
+    pub(crate) initial_rtt: Duration,
+    pub(crate) min_rto: Duration,
+    pub(crate) max_rto: Duration,
+    /// retransmissions of an unanswered SYN after which the connection is given up on
+    pub(crate) syn_retries: u32,
+    /// retransmissions of unacknowledged data after which the connection is given up on
+    pub(crate) data_retries: u32,
+    /// time between unanswered keepalive probes
+    pub(crate) keepalive_interval: Duration,
+    /// unanswered probes after which the connection is dropped
+    pub(crate) keepalive_probes: u32,
+    /// check the IPv4 header and TCP checksums of received packets
+    pub(crate) verify_checksums: bool,
+    /// what the interface reads the time from; connections are handed the time instead
+    pub(crate) clock: Arc<dyn Clock>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            mtu: 1500,
+            send_buffer: 1024,
+            recv_window: 1024,
+            iss: None,
+            isn: IsnGenerator::random(),
+            syn_backlog: 128,
+            accept_backlog: 128,
+            delayed_ack: Duration::from_millis(40),
+            msl: Duration::from_secs(30),
+            initial_rtt: Duration::from_secs(60),
+            min_rto: Duration::from_secs(1),
+            max_rto: Duration::from_secs(60),
+            syn_retries: 6,
+            data_retries: 15,
+            keepalive_interval: Duration::from_secs(75),
+            keepalive_probes: 9,
+            verify_checksums: true,
+            clock: Arc::new(SystemClock),
+        }
+    }
+}
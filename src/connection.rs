@@ -1,109 +1,349 @@
 use std::{
-    collections::{BTreeMap, VecDeque},
+    collections::VecDeque,
     io::{self, Write},
+    net::{Ipv4Addr, SocketAddrV4},
     time,
 };
 
-use crate::tcp::{Available, RecvSequenceSpace, SendSequenceSpace, State, Timers};
+use crate::{
+    config::Config,
+    seq::SeqNum,
+    tcp::{Available, RecvSequenceSpace, SendSequenceSpace, State, Timers},
+    timer::TimerKind,
+};
+
+/// What a [`Connection`] asks of whoever drives it, after being fed a segment, a timer or the
+/// application's data.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Output {
+    /// IPv4 packets to send, in order
+    pub segments: Vec<Vec<u8>>,
+    /// what changed for the application
+    pub events: Vec<ConnectionEvent>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionEvent {
+    /// the handshake is over
+    Established,
+    /// there is data to read, or the peer has closed its end
+    Readable,
+    /// there is room in the send queue again
+    Writable,
+    /// the peer stopped answering retransmissions or keepalive probes; `Closed` follows
+    TimedOut,
+    /// the connection is over: closed, refused or timed out
+    Closed,
+}
+
+/// What the application can observe of a connection, to tell what an input changed.
+#[derive(Clone, Copy)]
+struct Observed {
+    synchronized: bool,
+    available: Available,
+    closed: bool,
+}
 
+/// The TCP state machine of one connection, without any I/O: segments and timer expiries go in,
+/// and the segments to send come back out, along with what changed for the application.
+///
+/// [`Interface`](crate::Interface) drives connections from its packet thread, but they can be
+/// driven by anything that can move IPv4 packets and keep time:
+///
+/// ```
+/// use std::{net::{Ipv4Addr, SocketAddrV4}, time::Instant};
+/// use thunder::{Connection, ConnectionEvent, Interface};
+///
+/// let config = Interface::builder().config()?;
+/// let client = SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 2), 40000);
+/// let server = SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 1), 80);
+/// let now = Instant::now();
+///
+/// let (mut c, syn) = Connection::connect(client, server, &config, now)?;
+/// let (mut s, syn_ack) = Connection::accept(&syn.segments[0], &config, now)?.expect("a SYN");
+/// let ack = c.on_segment(&syn_ack.segments[0], now)?;
+/// assert_eq!(ack.events, [ConnectionEvent::Established]);
+/// assert_eq!(s.on_segment(&ack.segments[0], now)?.events, [ConnectionEvent::Established]);
+/// # Ok::<(), std::io::Error>(())
+/// ```
 pub struct Connection {
-    pub state: State,
-    pub send: SendSequenceSpace,
-    pub recv: RecvSequenceSpace,
-    pub ip: etherparse::Ipv4Header,
-    pub tcp: etherparse::TcpHeader,
-    pub timers: Timers,
+    pub(crate) state: State,
+    pub(crate) send: SendSequenceSpace,
+    pub(crate) recv: RecvSequenceSpace,
+    pub(crate) ip: etherparse::Ipv4Header,
+    pub(crate) tcp: etherparse::TcpHeader,
+    pub(crate) timers: Timers,
 
     pub(crate) incoming: VecDeque<u8>,
     pub(crate) unacked: VecDeque<u8>,
     pub(crate) closed: bool,
-    pub(crate) closed_at: Option<u32>,
+    pub(crate) closed_at: Option<SeqNum>,
+    /// the peer stopped answering, so the connection was given up on
+    pub(crate) timed_out: bool,
+    /// the peer reset the connection
+    pub(crate) reset: bool,
+
+    /// urgent byte received out of band, waiting for `recv_urgent`
+    pub(crate) oob: Option<u8>,
+    /// leave urgent bytes in `incoming` instead of pulling them out
+    pub(crate) oob_inline: bool,
+    /// number of bytes in `incoming` in front of the urgent mark
+    pub(crate) urgent_mark: Option<usize>,
+
+    pub(crate) config: Config,
+    /// largest segment the peer takes, from the MSS option of its SYN
+    pub(crate) peer_mss: Option<u16>,
+
+    /// segments built since the driver last collected them
+    outbox: Vec<Vec<u8>>,
 }
 
 impl Connection {
-    pub fn accept<'a>(
-        nic: &mut tun_tap::Iface,
-        iph: etherparse::Ipv4HeaderSlice<'a>,
-        tcph: etherparse::TcpHeaderSlice<'a>,
-    ) -> io::Result<Option<Self>> {
-        // only expected SYN packet
-        if !tcph.syn() {
-            return Ok(None);
-        }
-
-        let iss = 0;
-        let wnd = 1024;
-        let mut c = Connection {
-            state: State::SynRcvd,
-            // decide on stuff we're sending them
+    fn new(
+        state: State,
+        local: (Ipv4Addr, u16),
+        remote: (Ipv4Addr, u16),
+        recv: RecvSequenceSpace,
+        iss: SeqNum,
+        config: &Config,
+    ) -> Self {
+        Connection {
+            state,
+            // decide on stuff we're sending them; the window is the peer's, from its first segment
             send: SendSequenceSpace {
                 iss,
                 una: iss,
                 nxt: iss,
-                wnd,
-                up: false,
+                wnd: 0,
+                up: None,
 
-                wl1: 0,
-                wl2: 0,
+                wl1: SeqNum::default(),
+                wl2: SeqNum::default(),
             },
             // keep track of sender info
-            recv: RecvSequenceSpace {
-                irs: tcph.sequence_number(),
-                nxt: tcph.sequence_number() + 1,
-                wnd: tcph.window_size(),
-                up: false,
-            },
-            tcp: etherparse::TcpHeader::new(tcph.destination_port(), tcph.source_port(), iss, wnd),
+            recv,
+            tcp: etherparse::TcpHeader::new(local.1, remote.1, iss.get(), config.recv_window),
             ip: etherparse::Ipv4Header::new(
                 0,
                 64,
                 etherparse::IpNumber::TCP,
-                iph.destination_addr().octets(),
-                iph.source_addr().octets(),
+                local.0.octets(),
+                remote.0.octets(),
             )
             .expect("construct Ipv4 header"),
 
             incoming: Default::default(),
             unacked: Default::default(),
             timers: Timers {
-                send_times: BTreeMap::default(),
-                srtt: time::Duration::from_secs(1 * 60).as_secs_f64(),
+                send_times: VecDeque::new(),
+                srtt: config.initial_rtt.as_secs_f64(),
+                retransmit: None,
+                backoff: 0,
+                delayed_ack: None,
+                keepalive_idle: None,
+                keepalive: None,
+                keepalive_probes: 0,
+                time_wait: None,
             },
             closed: false,
             closed_at: None,
-        };
+            timed_out: false,
+            reset: false,
+            oob: None,
+            oob_inline: false,
+            urgent_mark: None,
+            config: config.clone(),
+            peer_mss: None,
+            outbox: Vec::new(),
+        }
+    }
+
+    /// A connection that has received the SYN of the peer, who started at `irs`.
+    fn syn_rcvd(
+        iph: &etherparse::Ipv4HeaderSlice<'_>,
+        tcph: &etherparse::TcpHeaderSlice<'_>,
+        irs: SeqNum,
+        iss: SeqNum,
+        config: &Config,
+    ) -> Self {
+        let mut c = Connection::new(
+            State::SynRcvd,
+            (iph.destination_addr(), tcph.destination_port()),
+            (iph.source_addr(), tcph.source_port()),
+            RecvSequenceSpace {
+                irs,
+                nxt: irs + 1,
+                wnd: config.recv_window,
+                up: None,
+            },
+            iss,
+            config,
+        );
+        c.send.wnd = tcph.window_size();
+        c.send.wl1 = tcph.sequence_number().into();
+        c.tcp.ack = true;
+        c
+    }
+
+    /// Passive open: answer the SYN in `packet`, if it is one.
+    pub fn accept(
+        packet: &[u8],
+        config: &Config,
+        now: time::Instant,
+    ) -> io::Result<Option<(Self, Output)>> {
+        let (iph, tcph, _) = parse(packet)?;
+        // only expected SYN packet
+        if !tcph.syn() {
+            return Ok(None);
+        }
+
+        let iss = initial_sequence(
+            config,
+            SocketAddrV4::new(iph.destination_addr(), tcph.destination_port()),
+            SocketAddrV4::new(iph.source_addr(), tcph.source_port()),
+            now,
+        );
+        let mut c = Connection::syn_rcvd(&iph, &tcph, tcph.sequence_number().into(), iss, config);
+        c.peer_mss = mss_option(&tcph);
 
         // need to establish a connection
         c.tcp.syn = true;
-        c.tcp.ack = true;
 
-        c.write(nic, c.send.nxt, 0)?;
+        c.transmit(c.send.nxt, 0, now)?;
+        let output = c.take_output(c.observe());
+
+        Ok(Some((c, output)))
+    }
+
+    /// Passive open without keeping any state: answer the SYN in `packet`, if it is one, with a
+    /// SYN cookie (RFC 4987 S3.6) as the initial sequence number. The connection only comes
+    /// into being when the peer's ACK brings the cookie back to
+    /// [`from_cookie`](Self::from_cookie).
+    pub fn syn_cookie(
+        packet: &[u8],
+        config: &Config,
+        now: time::Instant,
+    ) -> io::Result<Option<Output>> {
+        let (iph, tcph, _) = parse(packet)?;
+        if !tcph.syn() || tcph.ack() {
+            return Ok(None);
+        }
+
+        let cookie = config.isn.cookie(
+            SocketAddrV4::new(iph.destination_addr(), tcph.destination_port()),
+            SocketAddrV4::new(iph.source_addr(), tcph.source_port()),
+            tcph.sequence_number(),
+            mss_option(&tcph),
+            now,
+        );
+        let irs = tcph.sequence_number().into();
+        let mut c = Connection::syn_rcvd(&iph, &tcph, irs, cookie.into(), config);
+        c.tcp.syn = true;
+        c.transmit(c.send.nxt, 0, now)?;
 
-        Ok(Some(c))
+        Ok(Some(c.take_output(c.observe())))
     }
 
-    fn write(&mut self, nic: &mut tun_tap::Iface, seq: u32, mut limit: usize) -> io::Result<usize> {
-        let mut buf = [0u8; 1500];
+    /// Rebuild the connection a SYN cookie stood for, if `packet` acknowledges one that was
+    /// handed out in the last few minutes, and process the segment on it.
+    pub fn from_cookie(
+        packet: &[u8],
+        config: &Config,
+        now: time::Instant,
+    ) -> io::Result<Option<(Self, Output)>> {
+        let (iph, tcph, _) = parse(packet)?;
+        if !tcph.ack() || tcph.syn() || tcph.rst() {
+            return Ok(None);
+        }
+
+        let irs = SeqNum::from(tcph.sequence_number()) - 1;
+        let cookie = SeqNum::from(tcph.acknowledgment_number()) - 1;
+        let Some(found) = config.isn.check_cookie(
+            SocketAddrV4::new(iph.destination_addr(), tcph.destination_port()),
+            SocketAddrV4::new(iph.source_addr(), tcph.source_port()),
+            irs.get(),
+            cookie.get(),
+            now,
+        ) else {
+            return Ok(None);
+        };
+
+        // where we were after sending the SYN-ACK
+        let mut c = Connection::syn_rcvd(&iph, &tcph, irs, cookie, config);
+        c.send.nxt = cookie + 1;
+        c.peer_mss = found.mss;
+
+        let output = c.on_segment(packet, now)?;
+        Ok(Some((c, output)))
+    }
+
+    /// Active open: send a SYN from `local` to `remote`.
+    pub fn connect(
+        local: SocketAddrV4,
+        remote: SocketAddrV4,
+        config: &Config,
+        now: time::Instant,
+    ) -> io::Result<(Self, Output)> {
+        let mut c = Connection::new(
+            State::SynSent,
+            (*local.ip(), local.port()),
+            (*remote.ip(), remote.port()),
+            // filled in from the peer's SYN
+            RecvSequenceSpace {
+                irs: SeqNum::default(),
+                nxt: SeqNum::default(),
+                wnd: config.recv_window,
+                up: None,
+            },
+            initial_sequence(config, local, remote, now),
+            config,
+        );
+
+        c.tcp.syn = true;
+        c.transmit(c.send.nxt, 0, now)?;
+        let output = c.take_output(c.observe());
+
+        Ok((c, output))
+    }
+
+    /// Build the segment starting at `seq`, with up to `limit` bytes of the send queue, and
+    /// queue it for the driver.
+    fn transmit(&mut self, seq: SeqNum, mut limit: usize, now: time::Instant) -> io::Result<usize> {
+        let mut buf = vec![0u8; self.config.mtu];
         // self.tcp.sequence_number = self.send.nxt;
-        self.tcp.sequence_number = seq;
-        self.tcp.acknowledgment_number = self.recv.nxt;
+        self.tcp.sequence_number = seq.get();
+        self.tcp.acknowledgment_number = self.recv.nxt.get();
 
-        let mut offset = seq.wrapping_sub(self.send.una) as usize;
+        // the urgent pointer is relative to this segment, and only valid while it points forward
+        self.tcp.urg = false;
+        self.tcp.urgent_pointer = 0;
+        if let Some(up) = self.send.up {
+            let urgent_offset = up - seq;
+            if urgent_offset > 0 && urgent_offset <= u16::MAX as u32 {
+                self.tcp.urg = true;
+                self.tcp.urgent_pointer = urgent_offset as u16;
+            }
+        }
+
+        let mut offset = (seq - self.data_start()) as usize;
         // we want self.unacked[nunacked..]
         if let Some(closed_at) = self.closed_at {
-            if seq == closed_at.wrapping_add(1) {
+            if seq == closed_at + 1 {
                 offset = 0;
                 limit = 0;
             }
         }
 
         let (mut h, mut t) = self.unacked.as_slices();
-        if h.len() >= offset {
+        if limit == 0 {
+            // SYNs, keepalive probes and bare ACKs may not point into the send queue at all
+            (h, t) = (&[], &[]);
+        } else if h.len() >= offset {
             h = &h[offset..];
         } else {
             let skipped = h.len();
             h = &[];
-            t = &t[(offset - skipped)..];
+            t = t.get((offset - skipped)..).unwrap_or_default();
         }
 
         let max_data = std::cmp::min(limit, h.len() + t.len());
@@ -141,51 +381,77 @@ impl Connection {
 
         self.tcp.checksum = self
             .tcp
-            .calc_checksum_ipv4(&self.ip, &[])
+            .calc_checksum_ipv4(&self.ip, &buf[tcp_header_ends_at..payload_ends_at])
             .expect("failed to compute checksum");
 
         let mut tcp_header_buf = &mut buf[ip_header_ends_at..tcp_header_ends_at];
         self.tcp.write(&mut tcp_header_buf)?;
 
-        let mut next_seq = seq.wrapping_add(payload_bytes as u32);
+        let mut next_seq = seq + payload_bytes as u32;
 
         if self.tcp.syn {
-            next_seq = next_seq.wrapping_add(1);
+            next_seq += 1;
             self.tcp.syn = false;
         }
         if self.tcp.fin {
-            next_seq = next_seq.wrapping_add(1);
+            next_seq += 1;
             self.tcp.fin = false;
         }
 
-        if wrapping_lt(self.send.nxt, next_seq) {
+        let retransmission = seq.is_before(self.send.nxt);
+        if next_seq.is_after(self.send.nxt) {
             self.send.nxt = next_seq;
         }
-        self.timers.send_times.insert(seq, time::Instant::now());
+        // only segments that take up sequence space get acknowledged (and retransmitted)
+        if next_seq != seq {
+            // a retransmission replaces the entries of the segments it covers, which need not
+            // have started where it does
+            let send_times = &mut self.timers.send_times;
+            let at = send_times.partition_point(|&(sent, _, _)| sent.is_before(seq));
+            let covered = send_times
+                .range(at..)
+                .take_while(|&&(sent, _, _)| sent.is_before(next_seq))
+                .count();
+            send_times.drain(at..at + covered);
+            send_times.insert(at, (seq, now, retransmission));
+            if self.timers.retransmit.is_none() {
+                self.timers.retransmit = Some(now + self.rto());
+            }
+        }
+        // every segment carries the latest ACK
+        self.timers.delayed_ack = None;
 
-        nic.send(&buf[..payload_ends_at])?;
+        buf.truncate(payload_ends_at);
+        self.outbox.push(buf);
         Ok(payload_bytes)
     }
 
-    // pub fn send_rst(&mut self, nic: &mut tun_tap::Iface) -> io::Result<()> {
-    //     self.tcp.rst = true;
-    //     self.tcp.sequence_number = 0;
-    //     self.tcp.acknowledgment_number = 0;
-
-    //     self.write(nic, self.send.nxt, 0)?;
-
-    //     Ok(())
-    // }
+    /// Handle one IPv4 packet from the peer. Its checksums are not checked here: an interface
+    /// does that before handing packets over, as should any other driver.
+    pub fn on_segment(&mut self, packet: &[u8], now: time::Instant) -> io::Result<Output> {
+        let before = self.observe();
+        let (iph, tcph, data) = parse(packet)?;
+        self.on_packet(iph, tcph, data, now)?;
+        Ok(self.take_output(before))
+    }
 
-    pub fn on_packet<'a>(
+    fn on_packet<'a>(
         &mut self,
-        nic: &mut tun_tap::Iface,
-        _iph: etherparse::Ipv4HeaderSlice<'a>,
+        iph: etherparse::Ipv4HeaderSlice<'a>,
         tcph: etherparse::TcpHeaderSlice<'a>,
         data: &'a [u8],
-    ) -> io::Result<Available> {
+        now: time::Instant,
+    ) -> io::Result<()> {
+        if let State::SynSent = self.state {
+            return self.on_syn_sent(tcph, now);
+        }
+
+        let seqn = SeqNum::from(tcph.sequence_number());
+        if tcph.rst() {
+            return self.on_reset(seqn, now);
+        }
+
         // first, check that sequence number are valid (RFC 793 S3.3)
-        let seqn = tcph.sequence_number();
         let mut slen = data.len() as u32;
         if tcph.fin() {
             slen += 1;
@@ -193,224 +459,581 @@ impl Connection {
         if tcph.syn() {
             slen += 1;
         }
-        let wend = self.recv.nxt.wrapping_add(self.recv.wnd as u32);
+        let wend = self.recv.nxt + self.recv.wnd as u32;
         let okay = if slen == 0 {
             if self.recv.wnd == 0 {
-                if seqn == self.recv.nxt {
-                    false
-                } else {
-                    true
-                }
-            } else if !is_between_wrapped(self.recv.nxt.wrapping_sub(1), seqn, wend) {
-                false
+                seqn != self.recv.nxt
             } else {
-                true
+                seqn.is_between(self.recv.nxt - 1, wend)
             }
         } else {
-            if self.recv.wnd == 0 {
-                false
-            } else if !is_between_wrapped(self.recv.nxt.wrapping_sub(1), seqn, wend)
-                && !is_between_wrapped(
-                    self.recv.nxt.wrapping_sub(1),
-                    seqn.wrapping_add(slen - 1),
-                    wend,
-                )
-            {
-                false
-            } else {
-                true
-            }
+            self.recv.wnd != 0
+                && (seqn.is_between(self.recv.nxt - 1, wend)
+                    || (seqn + (slen - 1)).is_between(self.recv.nxt - 1, wend))
         };
 
         if !okay {
-            self.write(nic, self.send.nxt, 0)?;
-            return Ok(self.availability());
+            self.transmit(self.send.nxt, 0, now)?;
+            return Ok(());
         }
 
+        // a SYN in the window of a synchronized connection may be forged: answer with a challenge
+        // ACK, which a peer that really did start over will reset the connection on (RFC 5961 S4)
+        if tcph.syn() {
+            self.transmit(self.send.nxt, 0, now)?;
+            return Ok(());
+        }
+
+        // the peer is alive
+        self.timers.keepalive = self.timers.keepalive_idle.map(|idle| now + idle);
+        self.timers.keepalive_probes = 0;
+
         if !tcph.ack() {
-            if tcph.syn() {
-                // got SYN part of initial handshake
-                assert!(data.is_empty());
-                self.recv.nxt = seqn.wrapping_add(1);
-            }
-            return Ok(self.availability());
+            return Ok(());
         }
 
-        let ackn = tcph.acknowledgment_number();
+        let ackn = SeqNum::from(tcph.acknowledgment_number());
         if let State::SynRcvd = self.state {
-            if is_between_wrapped(
-                self.send.una.wrapping_sub(1),
-                ackn,
-                self.send.nxt.wrapping_add(1),
-            ) {
+            if ackn.is_between(self.send.una - 1, self.send.nxt + 1) {
                 self.state = State::Estab;
             } else {
-                // TODO: RST:
+                // not an ACK of our SYN: <SEQ=SEG.ACK><CTL=RST> (RFC 793 S3.9)
+                self.outbox.extend(reset(iph, tcph, data)?);
+                return Ok(());
             }
         }
 
-        if let State::Estab | State::FinWait1 | State::FinWait2 = self.state {
-            if is_between_wrapped(self.send.una, ackn, self.send.nxt.wrapping_add(1)) {
-                if !self.unacked.is_empty() {
-                    let data_start = if self.send.una == self.send.iss {
-                        self.send.una.wrapping_add(1)
-                    } else {
-                        self.send.una
-                    };
-                    let acked_data_end =
-                        std::cmp::min(ackn.wrapping_sub(data_start) as usize, self.unacked.len());
-                    self.unacked.drain(..acked_data_end);
-
-                    let old = std::mem::replace(&mut self.timers.send_times, BTreeMap::new());
-
-                    let una = self.send.una;
-                    let srtt = &mut self.timers.srtt;
-
-                    self.timers
-                        .send_times
-                        .extend(old.into_iter().filter_map(|(seq, sent)| {
-                            if is_between_wrapped(una, seq, ackn) {
-                                *srtt = 0.8 * *srtt + (1.0 - 0.8) * sent.elapsed().as_secs_f64();
-                                None
-                            } else {
-                                Some((seq, sent))
-                            }
-                        }));
-
-                    self.send.una = ackn;
+        if let State::Estab
+        | State::FinWait1
+        | State::FinWait2
+        | State::CloseWait
+        | State::Closing
+        | State::LastAck = self.state
+        {
+            // SND.UNA =< SEG.ACK =< SND.NXT
+            let acceptable = ackn.is_between(self.send.una - 1, self.send.nxt + 1);
+
+            if ackn.is_between(self.send.una, self.send.nxt + 1) {
+                let acked_data_end =
+                    std::cmp::min((ackn - self.data_start()) as usize, self.unacked.len());
+                self.unacked.drain(..acked_data_end);
+
+                // every segment that starts before the ACK is taken as acknowledged; there is no
+                // telling which transmission of a retransmitted one the ACK is for, so it says
+                // nothing about the round-trip time (Karn's algorithm, RFC 6298 S3)
+                while let Some(&(seq, sent, retransmitted)) = self.timers.send_times.front() {
+                    if !seq.is_before(ackn) {
+                        break;
+                    }
+                    self.timers.send_times.pop_front();
+                    if !retransmitted {
+                        self.timers.srtt = 0.8 * self.timers.srtt
+                            + (1.0 - 0.8) * now.duration_since(sent).as_secs_f64();
+                    }
+                }
+
+                // the urgent data has been delivered once the peer acks the byte before the pointer
+                if let Some(up) = self.send.up {
+                    if up.is_between(self.send.una, ackn + 1) {
+                        self.send.up = None;
+                    }
                 }
+
+                self.send.una = ackn;
+
+                // new data was acknowledged, so restart the retransmission timer (RFC 6298 S5)
+                self.timers.backoff = 0;
+                self.timers.retransmit = if ackn == self.send.nxt {
+                    None
+                } else {
+                    Some(now + self.rto())
+                };
             }
 
-            // TODO: prune self.unacked
-            // TODO: if unacked empty and waiting flush, notify
-            // TODO: update window
+            // take the window from the newest segment only, so that a reordered older one does
+            // not shrink it again (RFC 793 S3.9)
+            let (wl1, wl2) = (self.send.wl1, self.send.wl2);
+            if acceptable && (wl1.is_before(seqn) || (wl1 == seqn && !wl2.is_after(ackn))) {
+                self.send.wnd = tcph.window_size();
+                self.send.wl1 = seqn;
+                self.send.wl2 = ackn;
+            }
         }
 
-        if let State::FinWait1 = self.state {
-            if let Some(closed_at) = self.closed_at {
-                if self.send.una == closed_at.wrapping_add(1) {
-                    // our FIN has been ACKed!
-                    self.state = State::FinWait2;
+        if let Some(closed_at) = self.closed_at {
+            if self.send.una == closed_at + 1 {
+                // our FIN has been ACKed!
+                match self.state {
+                    State::FinWait1 => self.state = State::FinWait2,
+                    State::Closing => {
+                        self.state = State::TimeWait;
+                        self.timers.time_wait = Some(now + self.config.msl * 2);
+                    }
+                    State::LastAck => self.state = State::Closed,
+                    _ => {}
                 }
             }
         }
 
+        // there is no reassembly queue, so anything that starts beyond what we have received has
+        // to be sent again; a duplicate ACK tells the peer where we are
+        if slen > 0 && seqn.is_after(self.recv.nxt) {
+            self.transmit(self.send.nxt, 0, now)?;
+            return Ok(());
+        }
+
         if !data.is_empty() {
             if let State::Estab | State::FinWait1 | State::FinWait2 = self.state {
-                let mut unread_data_at = self.recv.nxt.wrapping_sub(seqn) as usize;
+                let unread_data_at = (self.recv.nxt - seqn) as usize;
                 if unread_data_at > data.len() {
-                    // we must have received a re-transmitted FIN that we have already senn
-                    // nxt points to beyond the fin, but the fin is not in data!
-                    assert_eq!(unread_data_at, data.len() + 1);
-                    unread_data_at = 0;
+                    // nothing we have not seen before
+                    self.transmit(self.send.nxt, 0, now)?;
+                    return Ok(());
+                }
+
+                if tcph.urg() && tcph.urgent_pointer() != 0 {
+                    // ignore pointers to urgent bytes we have already received
+                    let up = seqn + tcph.urgent_pointer() as u32;
+                    if up.is_after(self.recv.nxt) {
+                        self.recv.up = Some(up);
+                    }
+                }
+
+                // the urgent byte is the one right before the urgent pointer
+                let urgent_at = self
+                    .recv
+                    .up
+                    .map(|up| (up - 1 - seqn) as usize)
+                    .filter(|&i| i >= unread_data_at && i < data.len());
+                if let Some(i) = urgent_at {
+                    self.incoming.extend(&data[unread_data_at..i]);
+                    self.urgent_mark = Some(self.incoming.len());
+                    if self.oob_inline {
+                        self.incoming.push_back(data[i]);
+                    } else {
+                        self.oob = Some(data[i]);
+                    }
+                    self.incoming.extend(&data[(i + 1)..]);
+                    self.recv.up = None;
+                } else {
+                    self.incoming.extend(&data[unread_data_at..]);
                 }
-                self.incoming.extend(&data[unread_data_at..]);
 
-                self.recv.nxt = seqn.wrapping_add(data.len() as u32);
+                self.recv.nxt = seqn + data.len() as u32;
 
                 // Send an acknowledgement of the form: <SEQ=SND.NXT><ACK=RCV.NXT><CTL=ACK>
-                // TODO: maybe just tick to piggyback ack on data?
-                self.write(nic, self.send.nxt, 0)?;
+                // right away for every second segment, otherwise hold it back in case there is
+                // data to piggyback it on
+                if self.timers.delayed_ack.is_some() {
+                    self.transmit(self.send.nxt, 0, now)?;
+                } else {
+                    self.timers.delayed_ack = Some(now + self.config.delayed_ack);
+                }
             }
         }
 
         if tcph.fin() {
             match self.state {
+                State::SynRcvd | State::Estab => {
+                    // the peer is done sending, but we may still have things to say
+                    self.recv.nxt += 1;
+                    self.transmit(self.send.nxt, 0, now)?;
+                    self.state = State::CloseWait;
+                }
+                State::FinWait1 => {
+                    // simultaneous close: both FINs are in flight
+                    self.recv.nxt += 1;
+                    self.transmit(self.send.nxt, 0, now)?;
+                    self.state = State::Closing;
+                }
                 State::FinWait2 => {
                     // we're done with the connnection
-                    self.recv.nxt = self.recv.nxt.wrapping_add(1);
-                    self.write(nic, self.send.nxt, 0)?;
+                    self.recv.nxt += 1;
+                    self.transmit(self.send.nxt, 0, now)?;
                     self.state = State::TimeWait;
+                    self.timers.time_wait = Some(now + self.config.msl * 2);
                 }
-                _ => unimplemented!(),
+                // the FIN has been received already
+                _ => {}
             }
         }
 
-        Ok(self.availability())
+        Ok(())
+    }
+
+    /// A reset in a synchronized state. One that only has to land somewhere in the window is
+    /// easy to forge, so it has to be exactly at RCV.NXT; one elsewhere in the window is
+    /// answered with a challenge ACK, which a peer that really did reset answers with an exact
+    /// reset (RFC 5961 S3.2). One outside the window is dropped.
+    fn on_reset(&mut self, seqn: SeqNum, now: time::Instant) -> io::Result<()> {
+        if seqn == self.recv.nxt {
+            self.state = State::Closed;
+            self.reset = true;
+        } else if seqn.is_between(self.recv.nxt, self.recv.nxt + self.recv.wnd as u32) {
+            self.transmit(self.send.nxt, 0, now)?;
+        }
+        Ok(())
+    }
+
+    /// Handle the answer to our SYN (RFC 793 S3.9, SYN-SENT STATE).
+    fn on_syn_sent(
+        &mut self,
+        tcph: etherparse::TcpHeaderSlice<'_>,
+        now: time::Instant,
+    ) -> io::Result<()> {
+        let ackn = SeqNum::from(tcph.acknowledgment_number());
+        if tcph.ack() && ackn != self.send.nxt {
+            // not about our SYN
+            return Ok(());
+        }
+
+        if tcph.rst() {
+            if tcph.ack() {
+                // nobody is listening
+                self.state = State::Closed;
+            }
+            return Ok(());
+        }
+
+        if tcph.syn() && tcph.ack() {
+            self.recv.irs = tcph.sequence_number().into();
+            self.recv.nxt = self.recv.irs + 1;
+            self.send.wnd = tcph.window_size();
+            self.send.wl1 = self.recv.irs;
+            self.send.wl2 = ackn;
+            self.peer_mss = mss_option(&tcph);
+            self.send.una = ackn;
+            self.timers.send_times.clear();
+            self.timers.retransmit = None;
+            self.timers.backoff = 0;
+            self.state = State::Estab;
+
+            self.tcp.ack = true;
+            self.transmit(self.send.nxt, 0, now)?;
+        }
+
+        Ok(())
     }
 
     pub(crate) fn is_rcv_closed(&self) -> bool {
-        if let State::TimeWait = self.state {
-            true
+        matches!(
+            self.state,
+            State::Closing | State::TimeWait | State::CloseWait | State::LastAck | State::Closed
+        )
+    }
+
+    pub fn state(&self) -> State {
+        self.state
+    }
+
+    pub fn is_closed(&self) -> bool {
+        matches!(self.state, State::Closed)
+    }
+
+    /// Sequence number of the first byte in `unacked`, which comes after our SYN.
+    fn data_start(&self) -> SeqNum {
+        if self.send.una == self.send.iss {
+            self.send.una + 1
+        } else {
+            self.send.una
+        }
+    }
+
+    /// Whatever data fits in one packet next to our IPv4 and TCP headers, and that the peer
+    /// takes.
+    fn mss(&self) -> u32 {
+        let mss = (self.config.mtu - self.ip.header_len() - self.tcp.header_len()) as u32;
+        self.peer_mss.map_or(mss, |peer| mss.min(peer as u32))
+    }
+
+    /// Retransmission timeout, backed off exponentially while retransmissions go unanswered.
+    fn rto(&self) -> time::Duration {
+        let rto = f64::max(self.config.min_rto.as_secs_f64(), 1.5 * self.timers.srtt)
+            * 2f64.powi(self.timers.backoff.min(16) as i32);
+        time::Duration::from_secs_f64(rto.min(self.config.max_rto.as_secs_f64()))
+    }
+
+    /// Read whatever is available without blocking; `None` means the caller has to wait.
+    pub fn try_read(&mut self, buf: &mut [u8]) -> Option<usize> {
+        if self.is_rcv_closed() && self.incoming.is_empty() {
+            // no more data to read, and no need to block
+            return Some(0);
+        }
+
+        if !self.incoming.is_empty() {
+            return Some(self.read_incoming(buf));
+        }
+
+        None
+    }
+
+    /// Queue as much of `buf` as fits in the send queue; `None` means the queue is full. Nothing
+    /// more is taken once the connection has been closed, since it would come after our FIN.
+    pub fn try_write(&mut self, buf: &[u8]) -> Option<usize> {
+        if self.closed {
+            return Some(0);
+        }
+        if self.unacked.len() >= self.config.send_buffer {
+            return None;
+        }
+
+        let nwrite = std::cmp::min(buf.len(), self.config.send_buffer - self.unacked.len());
+        self.unacked.extend(&buf[..nwrite]);
+
+        Some(nwrite)
+    }
+
+    /// Copy bytes out of `incoming`, stopping at the urgent mark so that the caller can tell
+    /// when it has been reached.
+    /// Put back `data` that was read when the urgent mark was at `urgent_mark`, as if it had
+    /// never been read.
+    pub(crate) fn unread(&mut self, data: &[u8], urgent_mark: Option<usize>) {
+        for &byte in data.iter().rev() {
+            self.incoming.push_front(byte);
+        }
+        self.urgent_mark = urgent_mark;
+    }
+
+    pub(crate) fn read_incoming(&mut self, buf: &mut [u8]) -> usize {
+        let max = match self.urgent_mark {
+            Some(mark) if mark > 0 => std::cmp::min(buf.len(), mark),
+            _ => buf.len(),
+        };
+
+        let mut nread = 0;
+        let (head, tail) = self.incoming.as_slices();
+
+        let hread = std::cmp::min(max, head.len());
+        buf[..hread].copy_from_slice(&head[..hread]);
+        nread += hread;
+
+        let tread = std::cmp::min(max - nread, tail.len());
+        buf[hread..(hread + tread)].copy_from_slice(&tail[..tread]);
+        nread += tread;
+
+        drop(self.incoming.drain(..nread));
+
+        if nread > 0 {
+            self.urgent_mark = match self.urgent_mark {
+                Some(0) | None => None,
+                Some(mark) => Some(mark - nread),
+            };
+        }
+
+        nread
+    }
+
+    /// Queue a single byte of urgent data; the urgent pointer goes out with every segment until
+    /// the byte has been acknowledged.
+    pub fn send_urgent(&mut self, byte: u8) {
+        if self.closed {
+            return;
+        }
+        self.unacked.push_back(byte);
+        self.send.up = Some(self.data_start() + self.unacked.len() as u32);
+    }
+
+    pub fn recv_urgent(&mut self) -> io::Result<u8> {
+        if self.oob_inline {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "urgent data is delivered inline",
+            ));
+        }
+
+        if let Some(byte) = self.oob.take() {
+            return Ok(byte);
+        }
+
+        if self.recv.up.is_some() {
+            Err(io::Error::new(
+                io::ErrorKind::WouldBlock,
+                "urgent data has not arrived yet",
+            ))
         } else {
-            false
+            Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "no urgent data pending",
+            ))
         }
     }
 
-    fn availability(&self) -> Available {
+    pub fn availability(&self) -> Available {
         let mut a = Available::empty();
         if self.is_rcv_closed() || !self.incoming.is_empty() {
             a |= Available::READ;
         }
 
+        if self.unacked.len() < self.config.send_buffer {
+            a |= Available::WRITE;
+        }
+
         a
     }
 
-    pub(crate) fn on_tick(&mut self, nic: &mut tun_tap::Iface) -> io::Result<()> {
-        if let State::FinWait2 | State::TimeWait = self.state {
+    /// Probe the peer after `idle` without hearing from it, from `now` on.
+    pub fn set_keepalive(&mut self, idle: Option<time::Duration>, now: time::Instant) {
+        self.timers.keepalive_idle = idle;
+        self.timers.keepalive = idle.map(|idle| now + idle);
+        self.timers.keepalive_probes = 0;
+    }
+
+    /// When the timer of the given kind is due, if it is armed.
+    pub(crate) fn deadline(&self, kind: TimerKind) -> Option<time::Instant> {
+        match (kind, &self.state) {
+            (_, State::Closed) => None,
+            (TimerKind::Retransmit, _) => self.timers.retransmit,
+            (TimerKind::DelayedAck, _) => self.timers.delayed_ack,
+            (TimerKind::Keepalive, State::Estab) => self.timers.keepalive,
+            (TimerKind::Keepalive, _) => None,
+            (TimerKind::TimeWait, _) => self.timers.time_wait,
+        }
+    }
+
+    /// When [`on_timer`](Self::on_timer) has something to do next, if ever.
+    pub fn next_deadline(&self) -> Option<time::Instant> {
+        TimerKind::ALL
+            .into_iter()
+            .filter_map(|kind| self.deadline(kind))
+            .min()
+    }
+
+    /// Fire whatever timers are due at `now`: retransmissions, delayed ACKs, keepalive probes
+    /// and the end of TIME-WAIT.
+    pub fn on_timer(&mut self, now: time::Instant) -> io::Result<Output> {
+        let before = self.observe();
+        for kind in TimerKind::ALL {
+            self.fire(kind, now)?;
+        }
+        Ok(self.take_output(before))
+    }
+
+    fn fire(&mut self, kind: TimerKind, now: time::Instant) -> io::Result<()> {
+        if self.deadline(kind).is_none_or(|at| at > now) {
             return Ok(());
         }
 
-        let nunacked_data = self
-            .closed_at
-            .unwrap_or(self.send.nxt)
-            .wrapping_sub(self.send.una);
-        let unsent_data = self.unacked.len() as u32 - nunacked_data;
+        match kind {
+            TimerKind::Retransmit => self.retransmit(now)?,
+            TimerKind::DelayedAck => {
+                self.transmit(self.send.nxt, 0, now)?;
+            }
+            TimerKind::Keepalive => {
+                if self.timers.keepalive_probes == self.config.keepalive_probes {
+                    self.time_out();
+                    return Ok(());
+                }
+                // an old sequence number, which the peer has to answer with an ACK
+                self.transmit(self.send.una - 1, 0, now)?;
+                self.timers.keepalive_probes += 1;
+                self.timers.keepalive = Some(now + self.config.keepalive_interval);
+            }
+            TimerKind::TimeWait => {
+                self.timers.time_wait = None;
+                self.state = State::Closed;
+            }
+        }
 
-        let waited_for = self
-            .timers
-            .send_times
-            .range(self.send.una..)
-            .next()
-            .map(|x| x.1.elapsed());
+        Ok(())
+    }
 
-        let should_retransmit = if let Some(waited_for) = waited_for {
-            waited_for > time::Duration::from_secs(1)
-                && waited_for.as_secs_f64() > 1.5 * self.timers.srtt
+    fn retransmit(&mut self, now: time::Instant) -> io::Result<()> {
+        self.timers.retransmit = None;
+        let retries = if self.send.una == self.send.iss {
+            self.config.syn_retries
         } else {
-            false
+            self.config.data_retries
         };
+        if self.timers.backoff == retries {
+            self.time_out();
+            return Ok(());
+        }
+        self.timers.backoff += 1;
 
-        if should_retransmit {
-            // we should retransmit things
-            let resend = std::cmp::min(self.unacked.len() as u32, self.send.wnd as u32);
-            if resend < self.send.wnd as u32 && self.closed {
-                // can include the FIN?
-                self.tcp.fin = true;
-                self.closed_at = Some(self.send.una.wrapping_add(self.unacked.len() as u32));
-            }
-            self.write(nic, self.send.una, resend as usize)?;
-        } else {
-            // we should send new data if we have new data and space in the window
-            if unsent_data == 0 && self.closed_at.is_some() {
+        if self.send.una == self.send.iss {
+            // the peer has not seen our SYN yet
+            self.tcp.syn = true;
+            self.transmit(self.send.una, 0, now)?;
+            return Ok(());
+        }
+
+        // a closed window is probed with a byte, whose ACK says when it opens (RFC 1122 S4.2.2.17)
+        let wnd = std::cmp::max(self.send.wnd as u32, 1);
+        let unacked = self.unacked.len() as u32;
+        let resend = std::cmp::min(std::cmp::min(unacked, wnd), self.mss());
+        if resend == unacked && resend < wnd && self.closed {
+            // the FIN goes right after the last byte, if that is in this segment
+            self.tcp.fin = true;
+            self.closed_at = Some(self.data_start() + self.unacked.len() as u32);
+        }
+        self.transmit(self.send.una, resend as usize, now)?;
+
+        Ok(())
+    }
+
+    /// Give up on a peer that has stopped answering (RFC 1122 S4.2.3.5).
+    fn time_out(&mut self) {
+        self.state = State::Closed;
+        self.timed_out = true;
+    }
+
+    /// Send whatever new data (and FIN) the window allows, e.g. after the application has
+    /// written or closed.
+    pub fn send_pending(&mut self, now: time::Instant) -> io::Result<Output> {
+        let before = self.observe();
+        self.send_queued(now)?;
+        Ok(self.take_output(before))
+    }
+
+    fn send_queued(&mut self, now: time::Instant) -> io::Result<()> {
+        if !matches!(
+            self.state,
+            State::Estab | State::FinWait1 | State::CloseWait | State::LastAck
+        ) {
+            return Ok(());
+        }
+
+        loop {
+            let nunacked_data = self.closed_at.unwrap_or(self.send.nxt) - self.data_start();
+            let unsent_data = (self.unacked.len() as u32).saturating_sub(nunacked_data);
+            let send_fin = self.closed && self.closed_at.is_none();
+            if unsent_data == 0 && !send_fin {
                 return Ok(());
             }
 
-            let allowed = self.send.wnd as u32 - nunacked_data;
+            let allowed = (self.send.wnd as u32).saturating_sub(nunacked_data);
             if allowed == 0 {
+                // nothing in flight will bring a window update, so probe for one
+                if self.timers.retransmit.is_none() {
+                    self.timers.retransmit = Some(now + self.rto());
+                }
                 return Ok(());
             }
 
-            let send = std::cmp::min(unsent_data, allowed);
-            if send < allowed && self.closed && self.closed_at.is_none() {
+            let send = std::cmp::min(std::cmp::min(unsent_data, allowed), self.mss());
+            if send == unsent_data && send < allowed && send_fin {
                 self.tcp.fin = true;
-                self.closed_at = Some(self.send.una.wrapping_add(self.unacked.len() as u32));
+                self.closed_at = Some(self.data_start() + self.unacked.len() as u32);
             }
-            self.write(nic, self.send.nxt, send as usize)?;
+            self.transmit(self.send.nxt, send as usize, now)?;
         }
-
-        // decide if it needs to send something send it
-        Ok(())
     }
 
-    pub(crate) fn close(&mut self) -> io::Result<()> {
+    /// Close our end, which sends a FIN once everything written before has been sent.
+    pub fn close(&mut self) -> io::Result<()> {
         self.closed = true;
 
         match self.state {
+            State::SynSent => {
+                self.state = State::Closed;
+            }
             State::SynRcvd | State::Estab => {
                 self.state = State::FinWait1;
             }
-            State::FinWait1 | State::FinWait2 => {}
+            State::CloseWait => {
+                self.state = State::LastAck;
+            }
+            State::FinWait1 | State::FinWait2 | State::Closing | State::LastAck => {}
             _ => {
                 return Err(io::Error::new(
                     io::ErrorKind::NotConnected,
@@ -423,16 +1046,151 @@ impl Connection {
     }
 }
 
-fn wrapping_lt(lhs: u32, rhs: u32) -> bool {
-    // From RFC1323:
-    //  TCP datermines if a data segement is "old" or "new" by testing
-    //  whether its sequence number is within 2 ** 31 bytes of the left edge
-    //  of the window. and if it is not, discarding the data as "old". To insure that new data is
-    //  never mistakenly considered old and vice-versa. the left edge of the sender's window has to
-    //  be at most 2 ** 31 away  from the right edge of the receiver's window.
-    lhs.wrapping_sub(rhs) > 2 ^ 31
+impl Connection {
+    fn observe(&self) -> Observed {
+        Observed {
+            synchronized: self.state.is_synchronized(),
+            available: self.availability(),
+            closed: self.is_closed(),
+        }
+    }
+
+    /// Hand the segments built so far to the driver, along with what changed since `before`.
+    fn take_output(&mut self, before: Observed) -> Output {
+        let after = self.observe();
+        let mut events = Vec::new();
+        if !before.synchronized && after.synchronized {
+            events.push(ConnectionEvent::Established);
+        }
+        let became = after.available - before.available;
+        if became.contains(Available::READ) {
+            events.push(ConnectionEvent::Readable);
+        }
+        if became.contains(Available::WRITE) {
+            events.push(ConnectionEvent::Writable);
+        }
+        if !before.closed && after.closed {
+            if self.timed_out {
+                events.push(ConnectionEvent::TimedOut);
+            }
+            events.push(ConnectionEvent::Closed);
+        }
+
+        Output {
+            segments: std::mem::take(&mut self.outbox),
+            events,
+        }
+    }
+}
+
+/// Split an IPv4 packet into its headers and the TCP payload, or fail with `InvalidData` if it is
+/// not a whole, unfragmented TCP segment.
+pub(crate) fn parse(
+    packet: &[u8],
+) -> io::Result<(
+    etherparse::Ipv4HeaderSlice<'_>,
+    etherparse::TcpHeaderSlice<'_>,
+    &[u8],
+)> {
+    let invalid = |e: etherparse::err::ipv4::HeaderSliceError| {
+        io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+    };
+    let iph = etherparse::Ipv4HeaderSlice::from_slice(packet).map_err(invalid)?;
+    if iph.protocol() != etherparse::IpNumber::TCP {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "not a TCP segment",
+        ));
+    }
+    if iph.more_fragments() || iph.fragments_offset().value() != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "fragments are not reassembled",
+        ));
+    }
+    // anything past the total length is padding
+    let segment = packet
+        .get(iph.slice().len()..usize::from(iph.total_len()))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "truncated packet"))?;
+    let tcph = etherparse::TcpHeaderSlice::from_slice(segment)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+    let data = &segment[tcph.slice().len()..];
+
+    Ok((iph, tcph, data))
+}
+
+/// Whether the IPv4 header checksum and the TCP checksum of a parsed packet are right.
+pub(crate) fn checksums_match(
+    iph: &etherparse::Ipv4HeaderSlice<'_>,
+    tcph: &etherparse::TcpHeaderSlice<'_>,
+    data: &[u8],
+) -> bool {
+    iph.to_header().calc_header_checksum() == iph.header_checksum()
+        && tcph.calc_checksum_ipv4(iph, data).ok() == Some(tcph.checksum())
 }
 
-fn is_between_wrapped(start: u32, x: u32, end: u32) -> bool {
-    wrapping_lt(start, x) && wrapping_lt(x, end)
+/// Smallest MSS option we go by; anything lower would only make for a flood of tiny segments.
+const MIN_PEER_MSS: u16 = 88;
+
+/// The initial sequence number of a connection from `local` to `remote` opened at `now`.
+fn initial_sequence(
+    config: &Config,
+    local: SocketAddrV4,
+    remote: SocketAddrV4,
+    now: time::Instant,
+) -> SeqNum {
+    SeqNum::from(
+        config
+            .iss
+            .unwrap_or_else(|| config.isn.generate(local, remote, now)),
+    )
+}
+
+/// The MSS option of a SYN, if it has one.
+fn mss_option(tcph: &etherparse::TcpHeaderSlice<'_>) -> Option<u16> {
+    tcph.options_iterator().find_map(|option| match option {
+        Ok(etherparse::TcpOptionElement::MaximumSegmentSize(mss)) => Some(mss.max(MIN_PEER_MSS)),
+        _ => None,
+    })
+}
+
+/// Answer a segment that belongs to no connection with a reset (RFC 793 S3.4).
+pub(crate) fn reset(
+    iph: etherparse::Ipv4HeaderSlice<'_>,
+    tcph: etherparse::TcpHeaderSlice<'_>,
+    data: &[u8],
+) -> io::Result<Option<Vec<u8>>> {
+    if tcph.rst() {
+        return Ok(None);
+    }
+
+    let mut tcp = etherparse::TcpHeader::new(tcph.destination_port(), tcph.source_port(), 0, 0);
+    tcp.rst = true;
+    if tcph.ack() {
+        // <SEQ=SEG.ACK><CTL=RST>
+        tcp.sequence_number = tcph.acknowledgment_number();
+    } else {
+        // <SEQ=0><ACK=SEG.SEQ+SEG.LEN><CTL=RST,ACK>
+        let slen = data.len() as u32 + tcph.syn() as u32 + tcph.fin() as u32;
+        tcp.ack = true;
+        tcp.acknowledgment_number = (SeqNum::from(tcph.sequence_number()) + slen).get();
+    }
+
+    let ip = etherparse::Ipv4Header::new(
+        tcp.header_len() as u16,
+        64,
+        etherparse::IpNumber::TCP,
+        iph.destination_addr().octets(),
+        iph.source_addr().octets(),
+    )
+    .expect("construct Ipv4 header");
+    tcp.checksum = tcp
+        .calc_checksum_ipv4(&ip, &[])
+        .expect("failed to compute checksum");
+
+    let mut buf = Vec::with_capacity(ip.header_len() + tcp.header_len());
+    ip.write(&mut buf)?;
+    tcp.write(&mut buf)?;
+
+    Ok(Some(buf))
 }
@@ -0,0 +1,65 @@
+use std::{
+    io,
+    os::fd::{AsRawFd, RawFd},
+};
+
+use crate::link;
+
+/// A link that carries raw IPv4 packets, e.g. a tun device, or Ethernet frames wrapped in
+/// [`Ethernet`](crate::Ethernet).
+///
+/// The packet thread polls the device's file descriptor for readability before calling `recv`,
+/// so `recv` only has to hand over one packet that is already waiting.
+pub trait NetDevice: AsRawFd {
+    /// Send a single packet.
+    fn send(&mut self, packet: &[u8]) -> io::Result<usize>;
+
+    /// Receive a single packet into `buf`, returning its length, or 0 if what arrived was not
+    /// meant for the stack.
+    fn recv(&mut self, buf: &mut [u8]) -> io::Result<usize>;
+
+    /// Largest packet the device can carry, headers included.
+    fn mtu(&self) -> usize;
+}
+
+/// A tun or tap device, which knows the MTU of its link.
+///
+/// [`InterfaceBuilder`](crate::InterfaceBuilder) opens one itself; [`Tun::open`] is for
+/// wrapping a tun device in another device first, e.g. [`Impaired`](crate::Impaired).
+pub struct Tun {
+    iface: tun_tap::Iface,
+    mtu: usize,
+}
+
+impl Tun {
+    /// Open the existing tun device `name`, with the MTU its link has been given.
+    pub fn open(name: &str) -> io::Result<Self> {
+        let iface = tun_tap::Iface::without_packet_info(name, tun_tap::Mode::Tun)?;
+        let mtu = link::mtu(name)?;
+        Ok(Self::new(iface, mtu))
+    }
+
+    pub(crate) fn new(iface: tun_tap::Iface, mtu: usize) -> Self {
+        Self { iface, mtu }
+    }
+}
+
+impl AsRawFd for Tun {
+    fn as_raw_fd(&self) -> RawFd {
+        self.iface.as_raw_fd()
+    }
+}
+
+impl NetDevice for Tun {
+    fn send(&mut self, packet: &[u8]) -> io::Result<usize> {
+        self.iface.send(packet)
+    }
+
+    fn recv(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.iface.recv(buf)
+    }
+
+    fn mtu(&self) -> usize {
+        self.mtu
+    }
+}
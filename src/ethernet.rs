@@ -0,0 +1,264 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    io,
+    net::Ipv4Addr,
+    os::fd::{AsRawFd, RawFd},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use etherparse::{EtherType, Ethernet2Header, Ethernet2HeaderSlice};
+
+use crate::{
+    clock::{Clock, SystemClock},
+    device::NetDevice,
+};
+
+const BROADCAST: [u8; 6] = [0xff; 6];
+/// how long a resolved address is trusted before asking again
+const ARP_TIMEOUT: Duration = Duration::from_secs(60);
+/// how long to wait for a reply before repeating a request
+const ARP_RETRY: Duration = Duration::from_secs(1);
+/// how long an address that does not answer keeps its held-back packets after the last request
+const ARP_GIVE_UP: Duration = Duration::from_secs(3);
+/// packets held back per address that is still being resolved
+const ARP_QUEUE: usize = 3;
+/// addresses the table holds, so that spoofed requests cannot grow it without bound
+const ARP_ENTRIES: usize = 256;
+
+const ARP_REQUEST: u16 = 1;
+const ARP_REPLY: u16 = 2;
+
+enum Neighbor {
+    Resolved {
+        mac: [u8; 6],
+        expires: Instant,
+    },
+    Pending {
+        asked: Instant,
+        queue: VecDeque<Vec<u8>>,
+    },
+}
+
+impl Neighbor {
+    /// when the entry stops being of use
+    fn expires(&self) -> Instant {
+        match self {
+            Neighbor::Resolved { expires, .. } => *expires,
+            Neighbor::Pending { asked, .. } => *asked + ARP_GIVE_UP,
+        }
+    }
+}
+
+/// Ethernet II framing on top of a device that carries frames, such as a tap device.
+///
+/// Hands IPv4 packets addressed to `mac` (or broadcast) to the stack, answers ARP requests for
+/// `addr`, and resolves the MAC address of each destination through ARP before sending to it.
+/// Packets to an address that is still being resolved are held back, a few at a time.
+pub struct Ethernet<D> {
+    device: D,
+    mac: [u8; 6],
+    addr: Ipv4Addr,
+    neighbors: HashMap<Ipv4Addr, Neighbor>,
+    clock: Arc<dyn Clock>,
+}
+
+impl<D: NetDevice> Ethernet<D> {
+    pub fn new(device: D, mac: [u8; 6], addr: Ipv4Addr) -> Self {
+        Self {
+            device,
+            mac,
+            addr,
+            neighbors: HashMap::new(),
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    /// Time ARP entries and requests with `clock` rather than the system clock.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    fn send_frame(
+        &mut self,
+        dst: [u8; 6],
+        ether_type: EtherType,
+        payload: &[u8],
+    ) -> io::Result<()> {
+        let header = Ethernet2Header {
+            source: self.mac,
+            destination: dst,
+            ether_type,
+        };
+        let mut frame = Vec::with_capacity(Ethernet2Header::LEN + payload.len());
+        frame.extend_from_slice(&header.to_bytes());
+        frame.extend_from_slice(payload);
+        self.device.send(&frame)?;
+        Ok(())
+    }
+
+    fn send_arp(
+        &mut self,
+        dst: [u8; 6],
+        operation: u16,
+        target: ([u8; 6], Ipv4Addr),
+    ) -> io::Result<()> {
+        // RFC 826, for Ethernet and IPv4
+        let mut arp = Vec::with_capacity(28);
+        arp.extend_from_slice(&1u16.to_be_bytes());
+        arp.extend_from_slice(&u16::from(EtherType::IPV4).to_be_bytes());
+        arp.extend_from_slice(&[6, 4]);
+        arp.extend_from_slice(&operation.to_be_bytes());
+        arp.extend_from_slice(&self.mac);
+        arp.extend_from_slice(&self.addr.octets());
+        arp.extend_from_slice(&target.0);
+        arp.extend_from_slice(&target.1.octets());
+        self.send_frame(dst, EtherType::ARP, &arp)
+    }
+
+    /// Whether there is room for another address, once expired entries are gone. Unless the
+    /// new address is only learned in passing, the entry that expires first makes room.
+    fn make_room(&mut self, now: Instant, evict: bool) -> bool {
+        if self.neighbors.len() < ARP_ENTRIES {
+            return true;
+        }
+        self.neighbors.retain(|_, n| n.expires() > now);
+        if self.neighbors.len() < ARP_ENTRIES {
+            return true;
+        }
+        if !evict {
+            return false;
+        }
+        let first = self
+            .neighbors
+            .iter()
+            .min_by_key(|(_, n)| n.expires())
+            .map(|(&addr, _)| addr);
+        if let Some(addr) = first {
+            self.neighbors.remove(&addr);
+        }
+        true
+    }
+
+    fn on_arp(&mut self, arp: &[u8]) -> io::Result<()> {
+        if arp.len() < 28 || arp[..6] != [0, 1, 8, 0, 6, 4] {
+            // not Ethernet and IPv4
+            return Ok(());
+        }
+
+        let operation = u16::from_be_bytes([arp[6], arp[7]]);
+        let sender_mac: [u8; 6] = arp[8..14].try_into().expect("checked length");
+        let sender_addr = Ipv4Addr::new(arp[14], arp[15], arp[16], arp[17]);
+        let target_addr = Ipv4Addr::new(arp[24], arp[25], arp[26], arp[27]);
+
+        // learn the sender if we are talking to it already, or it is talking to us and there
+        // is room
+        let for_us = target_addr == self.addr;
+        let now = self.clock.now();
+        let known = self.neighbors.contains_key(&sender_addr);
+        if known || (for_us && self.make_room(now, false)) {
+            let previous = self.neighbors.insert(
+                sender_addr,
+                Neighbor::Resolved {
+                    mac: sender_mac,
+                    expires: now + ARP_TIMEOUT,
+                },
+            );
+            if let Some(Neighbor::Pending { queue, .. }) = previous {
+                for packet in queue {
+                    self.send_frame(sender_mac, EtherType::IPV4, &packet)?;
+                }
+            }
+        }
+
+        if for_us && operation == ARP_REQUEST {
+            self.send_arp(sender_mac, ARP_REPLY, (sender_mac, sender_addr))?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<D: AsRawFd> AsRawFd for Ethernet<D> {
+    fn as_raw_fd(&self) -> RawFd {
+        self.device.as_raw_fd()
+    }
+}
+
+impl<D: NetDevice> NetDevice for Ethernet<D> {
+    fn send(&mut self, packet: &[u8]) -> io::Result<usize> {
+        let dst = etherparse::Ipv4HeaderSlice::from_slice(packet)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?
+            .destination_addr();
+
+        let now = self.clock.now();
+        match self.neighbors.get_mut(&dst) {
+            Some(Neighbor::Resolved { mac, expires }) if *expires > now => {
+                let mac = *mac;
+                self.send_frame(mac, EtherType::IPV4, packet)?;
+            }
+            Some(Neighbor::Pending { asked, queue }) => {
+                if queue.len() == ARP_QUEUE {
+                    queue.pop_front();
+                }
+                queue.push_back(packet.to_vec());
+
+                if now.duration_since(*asked) >= ARP_RETRY {
+                    *asked = now;
+                    self.send_arp(BROADCAST, ARP_REQUEST, ([0; 6], dst))?;
+                }
+            }
+            // unknown, or the entry has expired
+            entry => {
+                if entry.is_none() {
+                    self.make_room(now, true);
+                }
+                self.neighbors.insert(
+                    dst,
+                    Neighbor::Pending {
+                        asked: now,
+                        queue: VecDeque::from([packet.to_vec()]),
+                    },
+                );
+                self.send_arp(BROADCAST, ARP_REQUEST, ([0; 6], dst))?;
+            }
+        }
+
+        Ok(packet.len())
+    }
+
+    /// Returns 0 for frames that carry no packet for the stack, like ARP or other hosts' traffic.
+    fn recv(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut frame = vec![0u8; self.device.mtu()];
+        let n = self.device.recv(&mut frame)?;
+        let Ok(header) = Ethernet2HeaderSlice::from_slice(&frame[..n]) else {
+            return Ok(0);
+        };
+        if header.destination() != self.mac && header.destination() != BROADCAST {
+            return Ok(0);
+        }
+
+        let payload = &frame[Ethernet2Header::LEN..n];
+        match header.ether_type() {
+            EtherType::IPV4 => {
+                // short frames are padded to the minimum Ethernet frame size
+                let len = etherparse::Ipv4HeaderSlice::from_slice(payload)
+                    .map_or(payload.len(), |iph| iph.total_len() as usize)
+                    .min(payload.len())
+                    .min(buf.len());
+                buf[..len].copy_from_slice(&payload[..len]);
+                Ok(len)
+            }
+            EtherType::ARP => {
+                self.on_arp(payload)?;
+                Ok(0)
+            }
+            _ => Ok(0),
+        }
+    }
+
+    fn mtu(&self) -> usize {
+        self.device.mtu() - Ethernet2Header::LEN
+    }
+}
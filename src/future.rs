@@ -0,0 +1,128 @@
+use std::{
+    future::Future,
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use crate::{
+    request::{Reply, Wait},
+    tcp_listener::TcpListener,
+    tcp_stream::TcpStream,
+    InterfaceRequest, Quad,
+};
+
+/// Future returned by [`TcpListener::accept_async`].
+pub struct AcceptFuture<'a> {
+    pub(crate) listener: &'a mut TcpListener,
+    pub(crate) reply: Option<Reply<Quad>>,
+}
+
+impl Future for AcceptFuture<'_> {
+    type Output = io::Result<TcpStream>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        if this.reply.is_none() {
+            let port = this.listener.port;
+            match this.listener.h.start(|read| InterfaceRequest::Accept {
+                port,
+                wait: Wait::Until(None),
+                read,
+            }) {
+                Ok(reply) => this.reply = Some(reply),
+                Err(e) => return Poll::Ready(Err(e)),
+            }
+        }
+
+        match this.reply.as_ref().expect("request started above").poll(cx) {
+            Poll::Ready(result) => {
+                this.reply = None;
+                Poll::Ready(result.map(|quad| this.listener.stream(quad)))
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+/// Future returned by [`TcpStream::read_async`].
+pub struct ReadFuture<'a> {
+    pub(crate) stream: &'a mut TcpStream,
+    pub(crate) buf: &'a mut [u8],
+    pub(crate) reply: Option<Reply<Vec<u8>>>,
+}
+
+impl Future for ReadFuture<'_> {
+    type Output = io::Result<usize>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        if this.buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+
+        if this.reply.is_none() {
+            let (quad, max_length) = (this.stream.quad, this.buf.len());
+            match this.stream.h.start(|read| InterfaceRequest::Read {
+                quad,
+                max_length,
+                wait: Wait::Until(None),
+                read,
+            }) {
+                Ok(reply) => this.reply = Some(reply),
+                Err(e) => return Poll::Ready(Err(e)),
+            }
+        }
+
+        match this.reply.as_ref().expect("request started above").poll(cx) {
+            Poll::Ready(result) => {
+                this.reply = None;
+                Poll::Ready(result.map(|data| {
+                    this.buf[..data.len()].copy_from_slice(&data);
+                    data.len()
+                }))
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+/// Future returned by [`TcpStream::write_async`].
+pub struct WriteFuture<'a> {
+    pub(crate) stream: &'a mut TcpStream,
+    pub(crate) buf: &'a [u8],
+    pub(crate) reply: Option<Reply<usize>>,
+}
+
+impl Future for WriteFuture<'_> {
+    type Output = io::Result<usize>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        if this.buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+
+        if this.reply.is_none() {
+            let quad = this.stream.quad;
+            let bytes = this.buf.to_vec();
+            match this.stream.h.start(|ack| InterfaceRequest::Write {
+                quad,
+                bytes,
+                wait: Wait::Until(None),
+                ack,
+            }) {
+                Ok(reply) => this.reply = Some(reply),
+                Err(e) => return Poll::Ready(Err(e)),
+            }
+        }
+
+        match this.reply.as_ref().expect("request started above").poll(cx) {
+            Poll::Ready(result) => {
+                this.reply = None;
+                Poll::Ready(result)
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
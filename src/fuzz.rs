@@ -0,0 +1,139 @@
+use std::{
+    io,
+    net::Ipv4Addr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    clock::Clock,
+    request::{self, Wait},
+    sim::{Outbox, SimClock},
+    ConnectionManager, Interface, InterfaceRequest, Quad,
+};
+
+/// A stack with nothing around it for the fuzz targets in `fuzz/` to throw packets at: the work
+/// of the packet thread done inline, a device that keeps what is sent, and time that only moves
+/// when told to.
+pub struct Stack {
+    cm: ConnectionManager,
+    device: Outbox,
+    clock: SimClock,
+}
+
+impl Stack {
+    /// the stack's address
+    pub const ADDR: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 1);
+    /// the port it listens on
+    pub const PORT: u16 = 8080;
+    /// the most [`read`](Self::read) takes from a connection at once
+    pub const READ_LENGTH: usize = 64 * 1024;
+
+    pub fn new() -> io::Result<Self> {
+        let (addr, mut config) = Interface::builder()
+            .addr(Self::ADDR, 24)
+            .msl(Duration::from_secs(1))
+            .initial_rtt(Duration::from_millis(100))
+            .rto(Duration::from_millis(200), Duration::from_secs(10))
+            // random bytes would hardly ever get past the checksums
+            .verify_checksums(false)
+            .isn_seed(0)
+            // so that SYN cookies are reached as well
+            .syn_backlog(2)
+            .finish(1500)?;
+        let clock = SimClock::new(Instant::now());
+        config.clock = Arc::new(clock.clone());
+        let mtu = config.mtu;
+
+        let mut stack = Self {
+            cm: ConnectionManager::new(addr, config, Default::default()),
+            device: Outbox {
+                packets: Vec::new(),
+                mtu,
+            },
+            clock,
+        };
+        let (ack, _) = request::channel();
+        stack.handle(InterfaceRequest::Bind {
+            port: Self::PORT,
+            ack,
+        })?;
+
+        Ok(stack)
+    }
+
+    /// Hand the stack a packet as the packet loop would; only a failing device may make this
+    /// fail, so any error is a bug.
+    pub fn receive(&mut self, packet: &[u8]) -> io::Result<()> {
+        self.cm.on_datagram(&mut self.device, packet)
+    }
+
+    /// Let `by` pass, firing timers as they come due.
+    pub fn advance(&mut self, by: Duration) -> io::Result<()> {
+        let to = self.clock.now() + by;
+        while let Some(at) = self.cm.timers.next_deadline().filter(|&at| at <= to) {
+            self.clock.set(at);
+            while let Some(timer) = self.cm.timers.pop_expired(at) {
+                self.cm.on_timer(&mut self.device, timer, at)?;
+            }
+        }
+        self.clock.set(to);
+
+        Ok(())
+    }
+
+    /// Write `data` to every connection, as much as fits.
+    pub fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        for quad in self.quads() {
+            let (ack, reply) = request::channel();
+            self.handle(InterfaceRequest::Write {
+                quad,
+                bytes: data.to_vec(),
+                wait: Wait::Never,
+                ack,
+            })?;
+            // a request that never waits is answered by now; a full queue is no bug
+            let _ = reply.wait();
+        }
+        Ok(())
+    }
+
+    /// Read whatever has arrived on every connection.
+    pub fn read(&mut self) -> io::Result<()> {
+        for quad in self.quads() {
+            let (read, reply) = request::channel();
+            self.handle(InterfaceRequest::Read {
+                quad,
+                max_length: Self::READ_LENGTH,
+                wait: Wait::Never,
+                read,
+            })?;
+            let _ = reply.wait();
+        }
+        Ok(())
+    }
+
+    /// Close every connection.
+    pub fn close(&mut self) -> io::Result<()> {
+        for quad in self.quads() {
+            self.handle(InterfaceRequest::Close { quad })?;
+        }
+        Ok(())
+    }
+
+    /// Take what the stack has sent since the last call.
+    pub fn sent(&mut self) -> Vec<Vec<u8>> {
+        std::mem::take(&mut self.device.packets)
+    }
+
+    fn handle(&mut self, request: InterfaceRequest) -> io::Result<()> {
+        self.cm.handle(&mut self.device, request).map(drop)
+    }
+
+    /// in a fixed order, so that a fuzz input always does the same thing
+    fn quads(&self) -> Vec<Quad> {
+        let mut quads: Vec<_> = self.cm.connections.keys().copied().collect();
+        quads.sort();
+        quads
+    }
+}
@@ -0,0 +1,313 @@
+use std::{
+    cmp::Reverse,
+    collections::BinaryHeap,
+    io,
+    os::fd::{AsRawFd, BorrowedFd, RawFd},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use nix::sys::{
+    epoll::{Epoll, EpollCreateFlags, EpollEvent, EpollFlags},
+    time::TimeSpec,
+    timerfd::{ClockId, Expiration, TimerFd, TimerFlags, TimerSetTimeFlags},
+};
+
+use crate::{device::NetDevice, rng::Rng};
+
+/// What an [`Impaired`] device does to the packets going through it, in both directions.
+///
+/// Probabilities are per packet, between 0 and 1; by default every packet goes through
+/// untouched.
+#[derive(Debug, Clone, Default)]
+pub struct Impairment {
+    loss: f64,
+    duplicate: f64,
+    corrupt: f64,
+    reorder: f64,
+    reorder_by: Duration,
+    delay: Duration,
+    jitter: Duration,
+    seed: Option<u64>,
+}
+
+impl Impairment {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Drop packets with probability `p`.
+    pub fn loss(mut self, p: f64) -> Self {
+        self.loss = p;
+        self
+    }
+
+    /// Deliver packets twice with probability `p`.
+    pub fn duplicate(mut self, p: f64) -> Self {
+        self.duplicate = p;
+        self
+    }
+
+    /// Flip one random bit of a packet with probability `p`.
+    pub fn corrupt(mut self, p: f64) -> Self {
+        self.corrupt = p;
+        self
+    }
+
+    /// Hold a packet back for an extra `by` with probability `p`, so that the packets after
+    /// it overtake it.
+    pub fn reorder(mut self, p: f64, by: Duration) -> Self {
+        self.reorder = p;
+        self.reorder_by = by;
+        self
+    }
+
+    /// Delay every packet by `delay`, plus up to `jitter` more.
+    pub fn delay(mut self, delay: Duration, jitter: Duration) -> Self {
+        self.delay = delay;
+        self.jitter = jitter;
+        self
+    }
+
+    /// Seed the random decisions, to repeat a run exactly; otherwise every device picks its own
+    /// seed (see [`Impaired::seed`]).
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    fn check(&self) -> io::Result<()> {
+        for p in [self.loss, self.duplicate, self.corrupt, self.reorder] {
+            if !(0.0..=1.0).contains(&p) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "probability is not between 0 and 1",
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// a packet held back until `at`; `id` keeps packets due at the same time in order
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+struct Held {
+    at: Instant,
+    id: u64,
+    packet: Vec<u8>,
+}
+
+/// Wraps a device to drop, delay, reorder, duplicate and corrupt the packets that go through
+/// it, e.g. to exercise retransmission:
+///
+/// ```
+/// use std::{net::Ipv4Addr, time::Duration};
+/// use thunder::{Impaired, Impairment, Interface, Loopback};
+///
+/// let (a, b) = Loopback::pair()?;
+/// let lossy = Impairment::new()
+///     .loss(0.1)
+///     .delay(Duration::from_millis(20), Duration::from_millis(5))
+///     .seed(42);
+/// let server = Interface::with_device(Impaired::new(a, lossy)?, Ipv4Addr::new(10, 0, 0, 1))?;
+/// let client = Interface::with_device(b, Ipv4Addr::new(10, 0, 0, 2))?;
+/// # Ok::<(), std::io::Error>(())
+/// ```
+///
+/// Delays are measured in real time, whatever clock the interface uses.
+pub struct Impaired<D> {
+    device: D,
+    impairment: Impairment,
+    seed: u64,
+    rng: Rng,
+    next_id: u64,
+    outbound: BinaryHeap<Reverse<Held>>,
+    inbound: BinaryHeap<Reverse<Held>>,
+    /// expires when the next held packet is due
+    timer: TimerFd,
+    /// readable when either the device or the timer is, which is what the packet thread polls
+    epoll: Epoll,
+}
+
+impl<D: NetDevice> Impaired<D> {
+    pub fn new(device: D, impairment: Impairment) -> io::Result<Self> {
+        impairment.check()?;
+
+        let timer = TimerFd::new(
+            ClockId::CLOCK_MONOTONIC,
+            TimerFlags::TFD_NONBLOCK | TimerFlags::TFD_CLOEXEC,
+        )?;
+        let epoll = Epoll::new(EpollCreateFlags::EPOLL_CLOEXEC)?;
+        epoll.add(device_fd(&device), EpollEvent::new(EpollFlags::EPOLLIN, 0))?;
+        epoll.add(&timer, EpollEvent::new(EpollFlags::EPOLLIN, 1))?;
+
+        let seed = impairment.seed.unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_nanos() as u64
+        });
+
+        Ok(Self {
+            device,
+            impairment,
+            seed,
+            rng: Rng::new(seed),
+            next_id: 0,
+            outbound: BinaryHeap::new(),
+            inbound: BinaryHeap::new(),
+            timer,
+            epoll,
+        })
+    }
+
+    /// The seed of the random decisions, which reproduces them when passed to
+    /// [`Impairment::seed`].
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Decide the fate of a packet, and hold back whatever copies of it survive until they are
+    /// due.
+    fn impair(&mut self, packet: &[u8], now: Instant, outbound: bool) {
+        if self.rng.chance(self.impairment.loss) {
+            return;
+        }
+
+        let copies = if self.rng.chance(self.impairment.duplicate) {
+            2
+        } else {
+            1
+        };
+        for _ in 0..copies {
+            let mut packet = packet.to_vec();
+            if !packet.is_empty() && self.rng.chance(self.impairment.corrupt) {
+                let bit = self.rng.below(packet.len() as u64 * 8);
+                packet[(bit / 8) as usize] ^= 1 << (bit % 8);
+            }
+
+            let mut at = now + self.impairment.delay;
+            if !self.impairment.jitter.is_zero() {
+                let jitter = self.impairment.jitter.as_nanos() as u64;
+                at += Duration::from_nanos(self.rng.below(jitter + 1));
+            }
+            if self.rng.chance(self.impairment.reorder) {
+                at += self.impairment.reorder_by;
+            }
+
+            let held = Reverse(Held {
+                at,
+                id: self.next_id,
+                packet,
+            });
+            self.next_id += 1;
+            if outbound {
+                self.outbound.push(held);
+            } else {
+                self.inbound.push(held);
+            }
+        }
+    }
+
+    /// Send the outbound packets that are due.
+    fn release(&mut self, now: Instant) -> io::Result<()> {
+        while self.outbound.peek().is_some_and(|held| held.0.at <= now) {
+            let Reverse(held) = self.outbound.pop().expect("just peeked");
+            self.device.send(&held.packet)?;
+        }
+
+        Ok(())
+    }
+
+    fn pop_inbound(&mut self, now: Instant) -> Option<Vec<u8>> {
+        if self.inbound.peek().is_some_and(|held| held.0.at <= now) {
+            return self.inbound.pop().map(|held| held.0.packet);
+        }
+        None
+    }
+
+    /// Wake the packet thread up when the next held packet is due.
+    fn arm(&self, now: Instant) -> io::Result<()> {
+        let next = [self.outbound.peek(), self.inbound.peek()]
+            .into_iter()
+            .flatten()
+            .map(|held| held.0.at)
+            .min();
+        match next {
+            Some(at) => {
+                // a zero timeout would disarm the timer instead
+                let after = at
+                    .saturating_duration_since(now)
+                    .max(Duration::from_nanos(1));
+                self.timer.set(
+                    Expiration::OneShot(TimeSpec::from_duration(after)),
+                    TimerSetTimeFlags::empty(),
+                )?;
+            }
+            None => self.timer.unset()?,
+        }
+
+        Ok(())
+    }
+
+    fn device_readable(&self) -> io::Result<bool> {
+        let fd = device_fd(&self.device);
+        let mut pfd = [nix::poll::PollFd::new(&fd, nix::poll::PollFlags::POLLIN)];
+        nix::poll::poll(&mut pfd, 0)?;
+        Ok(pfd[0]
+            .revents()
+            .is_some_and(|r| r.intersects(nix::poll::PollFlags::POLLIN)))
+    }
+}
+
+fn device_fd(device: &impl AsRawFd) -> BorrowedFd<'_> {
+    // SAFETY: the fd stays open for as long as the device is borrowed
+    unsafe { BorrowedFd::borrow_raw(device.as_raw_fd()) }
+}
+
+impl<D: AsRawFd> AsRawFd for Impaired<D> {
+    fn as_raw_fd(&self) -> RawFd {
+        self.epoll.0.as_raw_fd()
+    }
+}
+
+impl<D: NetDevice> NetDevice for Impaired<D> {
+    fn send(&mut self, packet: &[u8]) -> io::Result<usize> {
+        let now = Instant::now();
+        self.impair(packet, now, true);
+        self.release(now)?;
+        self.arm(now)?;
+        Ok(packet.len())
+    }
+
+    /// Returns 0 when woken up by the timer with nothing due to hand to the stack.
+    fn recv(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        // the timer has done its job once we get here
+        let _ = self.timer.wait();
+
+        let now = Instant::now();
+        self.release(now)?;
+
+        let mut packet = self.pop_inbound(now);
+        if packet.is_none() && self.device_readable()? {
+            let mut arrived = vec![0u8; self.device.mtu()];
+            let n = self.device.recv(&mut arrived)?;
+            if n > 0 {
+                self.impair(&arrived[..n], now, false);
+                packet = self.pop_inbound(now);
+            }
+        }
+        self.arm(now)?;
+
+        let Some(packet) = packet else {
+            return Ok(0);
+        };
+        let len = packet.len().min(buf.len());
+        buf[..len].copy_from_slice(&packet[..len]);
+        Ok(len)
+    }
+
+    fn mtu(&self) -> usize {
+        self.device.mtu()
+    }
+}
@@ -0,0 +1,178 @@
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    net::SocketAddrV4,
+    sync::{Arc, OnceLock},
+    time::{Duration, Instant},
+};
+
+use siphasher::sip::SipHasher24;
+
+use crate::rng::Rng;
+
+/// Picks initial sequence numbers as RFC 6528 suggests:
+///
+/// ```text
+/// ISN = M + F(localip, localport, remoteip, remoteport, secretkey)
+/// ```
+///
+/// where `M` ticks every 4 microseconds and `F` is SipHash-2-4 keyed with a secret. An off-path
+/// attacker cannot guess the numbers of a connection without the key, while a reused quad still
+/// starts above where its previous incarnation left off.
+///
+/// The same secret authenticates SYN cookies.
+#[derive(Debug, Clone)]
+pub(crate) struct IsnGenerator {
+    key: [u64; 2],
+    /// where `M` starts counting: the first time a sequence number was asked for, so that drivers
+    /// with virtual time get the same numbers on every run
+    epoch: Arc<OnceLock<Instant>>,
+}
+
+impl IsnGenerator {
+    /// With a key nobody else knows, drawn from the randomly keyed hasher of the standard
+    /// library (which is seeded by the operating system).
+    pub(crate) fn random() -> Self {
+        let state = RandomState::new();
+        let draw = |n: u64| {
+            let mut hasher = state.build_hasher();
+            hasher.write_u64(n);
+            hasher.finish()
+        };
+        Self::with_key([draw(0), draw(1)])
+    }
+
+    /// With a key that follows from `seed`, for runs that have to be repeatable.
+    pub(crate) fn seeded(seed: u64) -> Self {
+        let mut rng = Rng::new(seed);
+        Self::with_key([rng.next_u64(), rng.next_u64()])
+    }
+
+    fn with_key(key: [u64; 2]) -> Self {
+        Self {
+            key,
+            epoch: Arc::default(),
+        }
+    }
+
+    /// The initial sequence number of a connection from `local` to `remote` opened at `now`.
+    pub(crate) fn generate(&self, local: SocketAddrV4, remote: SocketAddrV4, now: Instant) -> u32 {
+        let m = (self.since_epoch(now).as_micros() / 4) as u32;
+        m.wrapping_add(siphash24(self.key, &quad(local, remote)) as u32)
+    }
+
+    /// A SYN cookie (RFC 4987 S3.6): the initial sequence number of a connection from `local`
+    /// to `remote` that is only remembered by the peer, who started at `peer_iss`.
+    ///
+    /// ```text
+    ///  31     27                                                 3   0
+    /// +--------+--------------------------------------------------+----+
+    /// |  time  |   MAC of the quad, peer_iss, time and MSS index   |MSS |
+    /// +--------+--------------------------------------------------+----+
+    /// ```
+    pub(crate) fn cookie(
+        &self,
+        local: SocketAddrV4,
+        remote: SocketAddrV4,
+        peer_iss: u32,
+        mss: Option<u16>,
+        now: Instant,
+    ) -> u32 {
+        let t = self.cookie_time(now);
+        // the largest MSS in the table the peer can take
+        let index = match mss {
+            None => 0,
+            Some(mss) => COOKIE_MSS.iter().rposition(|&m| m <= mss).unwrap_or(0) + 1,
+        } as u8;
+        (t % 32) << 27 | self.cookie_mac(local, remote, peer_iss, t, index) | index as u32
+    }
+
+    /// Whether `cookie` is one we handed out to `remote` in the last few minutes; if it is,
+    /// what it remembers about the connection.
+    pub(crate) fn check_cookie(
+        &self,
+        local: SocketAddrV4,
+        remote: SocketAddrV4,
+        peer_iss: u32,
+        cookie: u32,
+        now: Instant,
+    ) -> Option<SynCookie> {
+        let now = self.cookie_time(now);
+        let age = now.wrapping_sub(cookie >> 27) % 32;
+        if age > COOKIE_MAX_AGE {
+            return None;
+        }
+        let t = now.checked_sub(age)?;
+
+        let index = (cookie & 0b111) as u8;
+        if cookie & !(0b11111 << 27 | 0b111) != self.cookie_mac(local, remote, peer_iss, t, index) {
+            return None;
+        }
+        let mss = match index {
+            0 => None,
+            i => Some(COOKIE_MSS[i as usize - 1]),
+        };
+        Some(SynCookie { mss })
+    }
+
+    /// the 24 bits in the middle of a cookie
+    fn cookie_mac(
+        &self,
+        local: SocketAddrV4,
+        remote: SocketAddrV4,
+        peer_iss: u32,
+        t: u32,
+        index: u8,
+    ) -> u32 {
+        // longer than the input of `generate`, so the two never hash the same bytes
+        let mut input = [0u8; 21];
+        input[..12].copy_from_slice(&quad(local, remote));
+        input[12..16].copy_from_slice(&peer_iss.to_be_bytes());
+        input[16..20].copy_from_slice(&t.to_be_bytes());
+        input[20] = index;
+        siphash24(self.key, &input) as u32 & 0x07ff_fff8
+    }
+
+    fn cookie_time(&self, now: Instant) -> u32 {
+        (self.since_epoch(now).as_secs() / COOKIE_PERIOD.as_secs()) as u32
+    }
+
+    fn since_epoch(&self, now: Instant) -> Duration {
+        let epoch = *self.epoch.get_or_init(|| now);
+        now.saturating_duration_since(epoch)
+    }
+}
+
+/// What a valid SYN cookie brings back of the SYN it answered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct SynCookie {
+    /// the peer's maximum segment size, rounded down to one of `COOKIE_MSS`
+    pub(crate) mss: Option<u16>,
+}
+
+/// How often the time in SYN cookies ticks.
+const COOKIE_PERIOD: Duration = Duration::from_secs(64);
+/// Ticks after which a cookie is no longer accepted.
+const COOKIE_MAX_AGE: u32 = 2;
+/// How long after it was handed out a cookie may still be accepted: it was made in the
+/// middle of a tick at the earliest.
+pub(crate) const COOKIE_LIFETIME: Duration =
+    Duration::from_secs(COOKIE_PERIOD.as_secs() * (COOKIE_MAX_AGE as u64 + 1));
+/// The MSS values a cookie can hold, in three bits along with "no MSS option".
+const COOKIE_MSS: [u16; 7] = [536, 1220, 1360, 1400, 1440, 1460, 8960];
+
+fn quad(local: SocketAddrV4, remote: SocketAddrV4) -> [u8; 12] {
+    let mut quad = [0u8; 12];
+    quad[..4].copy_from_slice(&local.ip().octets());
+    quad[4..6].copy_from_slice(&local.port().to_be_bytes());
+    quad[6..10].copy_from_slice(&remote.ip().octets());
+    quad[10..].copy_from_slice(&remote.port().to_be_bytes());
+    quad
+}
+
+/// SipHash-2-4 of `data`.
+fn siphash24(key: [u64; 2], data: &[u8]) -> u64 {
+    let mut hasher = SipHasher24::new_with_keys(key[0], key[1]);
+    hasher.write(data);
+    hasher.finish()
+}
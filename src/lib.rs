@@ -1,20 +1,72 @@
 use std::{
     collections::{hash_map::Entry, HashMap, VecDeque},
-    io,
-    net::Ipv4Addr,
-    os::fd::{AsFd, AsRawFd, BorrowedFd, RawFd},
-    sync::{mpsc, Arc, Condvar, Mutex},
+    io::{self, Read, Write},
+    net::{Ipv4Addr, SocketAddrV4},
+    os::{
+        fd::{AsFd, AsRawFd, BorrowedFd, RawFd},
+        unix::net::UnixStream,
+    },
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc, Arc, Condvar, Weak,
+    },
     thread,
+    time::Instant,
 };
 
-use connection::Connection;
+use capture::Captured;
+use request::{Completion, Outgoing, Parked, Reply, Wait};
+use timer::{Timer, TimerKind, TimerQueue};
 
+mod builder;
+mod capture;
+mod clock;
+mod config;
 mod connection;
+mod device;
+mod ethernet;
+mod future;
+#[cfg(feature = "fuzzing")]
+#[doc(hidden)]
+pub mod fuzz;
+mod impair;
+mod isn;
+mod link;
+mod loopback;
+mod pcap;
+mod poll;
+mod replay;
+mod request;
+mod rng;
+mod script;
+mod seq;
+mod sim;
 mod tcp;
 mod tcp_listener;
 mod tcp_stream;
+mod timer;
+
+pub use builder::InterfaceBuilder;
+pub use clock::{Clock, MockClock, SystemClock};
+pub use config::Config;
+pub use connection::{Connection, ConnectionEvent, Output};
+pub use device::{NetDevice, Tun};
+pub use ethernet::Ethernet;
+pub use future::{AcceptFuture, ReadFuture, WriteFuture};
+pub use impair::{Impaired, Impairment};
+pub use loopback::Loopback;
+pub use pcap::{PcapReader, PcapWriter};
+pub use poll::{Event, Poller, Source, Token};
+pub use replay::{Replay, ReplayHandle};
+pub use script::Script;
+pub use seq::SeqNum;
+pub use sim::{Host, Link, LinkStats, NodeId, Simulator};
+pub use tcp::{Available, State};
+pub use tcp_listener::TcpListener;
+pub use tcp_stream::TcpStream;
 
-const SENDQUEUE_SIZE: usize = 1024;
+/// local ports handed out to `connect`, as suggested by RFC 6335
+const EPHEMERAL_PORTS: std::ops::RangeInclusive<u16> = 49152..=65535;
 
 struct RawFdWrapper(RawFd);
 
@@ -30,68 +82,918 @@ impl AsFd for RawFdWrapper {
     }
 }
 
-#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Quad {
     src: (Ipv4Addr, u16),
     dst: (Ipv4Addr, u16),
 }
 
-#[derive(Default)]
 pub struct Foobar {
-    manager: Mutex<ConnectionManager>,
-    pending_var: Condvar,
-    rcv_var: Condvar,
+    requests: mpsc::Sender<InterfaceRequest>,
+    /// written to after every request, so that the packet thread wakes up from `poll`
+    wake: UnixStream,
+    /// the interface's clock, which request deadlines are measured against
+    clock: Arc<dyn Clock>,
+    /// the stack is driven by a [`Simulator`](sim::Simulator) on the thread that would block
+    /// waiting for it
+    simulated: bool,
 }
 
 type InterfaceHandle = Arc<Foobar>;
 
+impl Foobar {
+    fn send(&self, request: InterfaceRequest) -> io::Result<()> {
+        self.requests.send(request).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::ConnectionAborted,
+                "interface has been shut down",
+            )
+        })?;
+
+        match (&self.wake).write(&[0]) {
+            Ok(_) => Ok(()),
+            // plenty of wakeups are queued up already
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Hand a request to the packet thread without waiting for the answer.
+    fn start<T>(
+        &self,
+        request: impl FnOnce(Completion<T>) -> InterfaceRequest,
+    ) -> io::Result<Reply<T>> {
+        let (completion, reply) = request::channel();
+        self.send(request(completion))?;
+        Ok(reply)
+    }
+
+    fn now(&self) -> Instant {
+        self.clock.now()
+    }
+
+    /// Hand a request to the packet thread and block until it has been answered.
+    fn call<T>(&self, request: impl FnOnce(Completion<T>) -> InterfaceRequest) -> io::Result<T> {
+        if self.simulated {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "blocking calls would stall the simulation; use the async methods",
+            ));
+        }
+        self.start(request)?.wait()
+    }
+}
+
+/// std rejects zero durations for socket timeouts, since they would be indistinguishable from
+/// non-blocking mode.
+fn check_timeout(timeout: Option<std::time::Duration>) -> io::Result<()> {
+    if timeout.is_some_and(|t| t.is_zero()) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "cannot set a 0 duration timeout",
+        ));
+    }
+
+    Ok(())
+}
+
 #[derive(Default)]
 pub struct Pending {
     pub quads: VecDeque<Quad>,
     pub var: Condvar,
 }
 
-#[derive(Default)]
+/// Connection state, owned by the packet thread.
 pub struct ConnectionManager {
-    terminate: bool,
-    connections: HashMap<Quad, Connection>,
+    /// our address, used for connections we open
+    addr: Ipv4Addr,
+    /// the next ephemeral port to try
+    next_port: u16,
+    connections: HashMap<Quad, Socket>,
     pending: HashMap<u16, VecDeque<Quad>>,
+    /// connections still in SYN-RCVD, per bound port
+    half_open: HashMap<u16, usize>,
+    /// when each bound port last answered a SYN with a cookie
+    cookie_sent_at: HashMap<u16, Instant>,
+    /// blocked `accept` calls, per bound port
+    acceptors: HashMap<u16, VecDeque<Parked<(), Quad>>>,
+    registrations: HashMap<poll::SourceKey, Vec<poll::Registration>>,
+    timers: TimerQueue<Timer>,
+    config: Config,
+    counters: Arc<Counters>,
+}
+
+/// What the packet thread has dropped, and how much it keeps track of, for the application to
+/// look at.
+#[derive(Debug, Default)]
+pub(crate) struct Counters {
+    /// packets whose IPv4 header or TCP checksum did not add up
+    bad_checksum: AtomicU64,
+    /// SYNs answered with a cookie because the listener's backlog was full
+    syn_cookies: AtomicU64,
+    /// entries in the timer queue after the last turn of the packet loop
+    queued_timers: AtomicU64,
+}
+
+/// A connection, and what the application is doing with it.
+struct Socket {
+    conn: Connection,
+    /// application requests blocked on this connection
+    waiting: request::Waiting,
+    /// the application has dropped its handle, so the connection can go once it is closed
+    released: bool,
+    /// the handshake has completed, so a closed connection was not refused
+    established: bool,
+    /// counted in the half-open backlog of its listener
+    half_open: bool,
+}
+
+impl Socket {
+    fn new(conn: Connection) -> Self {
+        Self {
+            conn,
+            waiting: Default::default(),
+            released: false,
+            established: false,
+            half_open: false,
+        }
+    }
+}
+
+bitflags::bitflags! {
+    /// The requests parked on a connection that may be able to make progress.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    struct Wake: u8 {
+        const READERS = 0b001;
+        const WRITERS = 0b010;
+        const CONNECTORS = 0b100;
+    }
+}
+
+impl Wake {
+    /// Whose turn it is after a connection reported `events`.
+    fn after(events: &[ConnectionEvent]) -> Self {
+        events.iter().fold(Wake::empty(), |wake, event| {
+            wake | match event {
+                ConnectionEvent::Established => Wake::CONNECTORS,
+                ConnectionEvent::Readable => Wake::READERS,
+                ConnectionEvent::Writable => Wake::WRITERS,
+                // whatever they were waiting for will not happen now
+                ConnectionEvent::TimedOut | ConnectionEvent::Closed => Wake::all(),
+            }
+        })
+    }
 }
 
-pub enum InterfaceRequest {
+/// Send the segments a connection has asked for, and hand back what changed for the
+/// application.
+fn transmit(
+    nic: &mut impl NetDevice,
+    output: connection::Output,
+) -> io::Result<Vec<ConnectionEvent>> {
+    for segment in output.segments {
+        nic.send(&segment)?;
+    }
+
+    Ok(output.events)
+}
+
+/// Answer a segment that belongs to no connection with a reset.
+fn reset(
+    nic: &mut impl NetDevice,
+    iph: etherparse::Ipv4HeaderSlice<'_>,
+    tcph: etherparse::TcpHeaderSlice<'_>,
+    data: &[u8],
+) -> io::Result<()> {
+    if let Some(rst) = connection::reset(iph, tcph, data)? {
+        nic.send(&rst)?;
+    }
+
+    Ok(())
+}
+
+/// Everything application threads ask of the packet thread; the answer comes back through the
+/// request's `Completion`.
+pub(crate) enum InterfaceRequest {
     Write {
         quad: Quad,
         bytes: Vec<u8>,
-        ack: mpsc::Sender<usize>,
+        wait: Wait,
+        ack: Completion<usize>,
+    },
+    SendUrgent {
+        quad: Quad,
+        byte: u8,
+        wait: Wait,
+        ack: Completion<usize>,
     },
     Flush {
         quad: Quad,
-        ack: mpsc::Sender<usize>,
+        wait: Wait,
+        ack: Completion<()>,
+    },
+    Shutdown {
+        quad: Quad,
+        ack: Completion<()>,
+    },
+    Close {
+        quad: Quad,
     },
     Bind {
         port: u16,
-        ack: mpsc::Sender<()>,
+        ack: Completion<()>,
+    },
+    Unbind {
+        port: u16,
     },
-    Unbind {},
     Read {
         quad: Quad,
         max_length: usize,
-        read: mpsc::Sender<Vec<u8>>,
+        wait: Wait,
+        read: Completion<Vec<u8>>,
+    },
+    RecvUrgent {
+        quad: Quad,
+        read: Completion<u8>,
+    },
+    SetOobInline {
+        quad: Quad,
+        inline: bool,
+        ack: Completion<()>,
+    },
+    AtMark {
+        quad: Quad,
+        ack: Completion<bool>,
+    },
+    SetKeepalive {
+        quad: Quad,
+        idle: Option<std::time::Duration>,
+        ack: Completion<()>,
     },
     Accept {
         port: u16,
-        read: mpsc::Sender<Quad>,
+        wait: Wait,
+        read: Completion<Quad>,
+    },
+    Connect {
+        remote: (Ipv4Addr, u16),
+        wait: Wait,
+        ack: Completion<Quad>,
+    },
+    Register {
+        key: poll::SourceKey,
+        registration: poll::Registration,
+        ack: Completion<()>,
+    },
+    Deregister {
+        key: poll::SourceKey,
+        poller: Weak<poll::Shared>,
+        ack: Completion<Token>,
+    },
+    /// handled by the packet loop, which owns the device
+    Capture {
+        sink: Option<capture::Sink>,
+        ack: Completion<()>,
+    },
+    /// the mock clock has moved; answered by the packet loop once the timers due have fired
+    Tick {
+        ack: Completion<()>,
     },
+    Terminate,
+}
+
+fn connection_refused() -> io::Error {
+    io::Error::new(io::ErrorKind::ConnectionRefused, "connection refused")
+}
+
+fn connection_reset() -> io::Error {
+    io::Error::new(io::ErrorKind::ConnectionReset, "connection reset by peer")
+}
+
+fn timed_out() -> io::Error {
+    io::Error::new(io::ErrorKind::TimedOut, "peer stopped answering")
+}
+
+fn terminated() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::ConnectionAborted,
+        "stream was terminated unexpectedly",
+    )
+}
+
+fn would_block(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::WouldBlock, msg)
+}
+
+impl ConnectionManager {
+    fn new(addr: Ipv4Addr, config: Config, counters: Arc<Counters>) -> Self {
+        Self {
+            addr,
+            next_port: *EPHEMERAL_PORTS.start(),
+            connections: Default::default(),
+            pending: Default::default(),
+            half_open: Default::default(),
+            cookie_sent_at: Default::default(),
+            acceptors: Default::default(),
+            registrations: Default::default(),
+            timers: Default::default(),
+            config,
+            counters,
+        }
+    }
+
+    /// Handle one request from an application thread. Returns `false` once the interface is
+    /// shutting down.
+    fn handle(&mut self, nic: &mut impl NetDevice, request: InterfaceRequest) -> io::Result<bool> {
+        match request {
+            InterfaceRequest::Write {
+                quad,
+                bytes,
+                wait,
+                ack,
+            } => self.park_write(nic, quad, Outgoing::Data(bytes), wait, ack)?,
+            InterfaceRequest::SendUrgent {
+                quad,
+                byte,
+                wait,
+                ack,
+            } => self.park_write(nic, quad, Outgoing::Urgent(byte), wait, ack)?,
+            InterfaceRequest::Flush { quad, wait, ack } => {
+                let Some(c) = self.connections.get_mut(&quad) else {
+                    ack.complete(Err(terminated()));
+                    return Ok(true);
+                };
+                if let Wait::Never = wait {
+                    if !c.conn.unacked.is_empty() {
+                        ack.complete(Err(would_block("bytes not yet acknowledged")));
+                        return Ok(true);
+                    }
+                }
+                c.waiting.flushers.push_back(Parked {
+                    arg: (),
+                    deadline: wait.deadline(),
+                    completion: ack,
+                });
+                self.schedule_deadline(wait);
+                self.on_connection_event(nic, quad)?;
+            }
+            InterfaceRequest::Shutdown { quad, ack } => match self.connections.get_mut(&quad) {
+                Some(c) => {
+                    ack.complete(c.conn.close());
+                    self.on_connection_event(nic, quad)?;
+                }
+                None => ack.complete(Err(terminated())),
+            },
+            InterfaceRequest::Close { quad } => {
+                self.registrations.remove(&poll::SourceKey::Stream(quad));
+                if let Some(c) = self.connections.get_mut(&quad) {
+                    // the stream may have been shut down already
+                    let _ = c.conn.close();
+                    c.waiting.abort(terminated);
+                    c.released = true;
+                    self.on_connection_event(nic, quad)?;
+                }
+            }
+            InterfaceRequest::Bind { port, ack } => match self.pending.entry(port) {
+                Entry::Vacant(v) => {
+                    v.insert(VecDeque::new());
+                    ack.complete(Ok(()));
+                }
+                Entry::Occupied(_) => ack.complete(Err(io::Error::new(
+                    io::ErrorKind::AddrInUse,
+                    "port already bound",
+                ))),
+            },
+            InterfaceRequest::Unbind { port } => {
+                self.registrations.remove(&poll::SourceKey::Listener(port));
+                self.acceptors.remove(&port);
+                self.half_open.remove(&port);
+                self.cookie_sent_at.remove(&port);
+                // nobody will ever accept these, so close them like dropped streams
+                for quad in self.pending.remove(&port).unwrap_or_default() {
+                    if let Some(c) = self.connections.get_mut(&quad) {
+                        let _ = c.conn.close();
+                        c.released = true;
+                        c.half_open = false;
+                        self.on_connection_event(nic, quad)?;
+                    }
+                }
+            }
+            InterfaceRequest::Read {
+                quad,
+                max_length,
+                wait,
+                read,
+            } => {
+                let Some(c) = self.connections.get_mut(&quad) else {
+                    read.complete(Err(terminated()));
+                    return Ok(true);
+                };
+                if let Wait::Never = wait {
+                    if !c.waiting.readers.is_empty()
+                        || !c.conn.availability().contains(Available::READ)
+                    {
+                        read.complete(Err(would_block("no bytes available to read")));
+                        return Ok(true);
+                    }
+                }
+                c.waiting.readers.push_back(Parked {
+                    arg: max_length,
+                    deadline: wait.deadline(),
+                    completion: read,
+                });
+                self.schedule_deadline(wait);
+                self.on_connection_event(nic, quad)?;
+            }
+            InterfaceRequest::RecvUrgent { quad, read } => match self.connections.get_mut(&quad) {
+                Some(c) => read.complete(c.conn.recv_urgent()),
+                None => read.complete(Err(terminated())),
+            },
+            InterfaceRequest::SetOobInline { quad, inline, ack } => {
+                match self.connections.get_mut(&quad) {
+                    Some(c) => {
+                        c.conn.oob_inline = inline;
+                        ack.complete(Ok(()));
+                    }
+                    None => ack.complete(Err(terminated())),
+                }
+            }
+            InterfaceRequest::AtMark { quad, ack } => match self.connections.get(&quad) {
+                Some(c) => ack.complete(Ok(c.conn.urgent_mark == Some(0))),
+                None => ack.complete(Err(terminated())),
+            },
+            InterfaceRequest::SetKeepalive { quad, idle, ack } => {
+                match self.connections.get_mut(&quad) {
+                    Some(c) => {
+                        c.conn.set_keepalive(idle, self.config.clock.now());
+                        ack.complete(Ok(()));
+                        self.on_connection_event(nic, quad)?;
+                    }
+                    None => ack.complete(Err(terminated())),
+                }
+            }
+            InterfaceRequest::Accept { port, wait, read } => {
+                let Some(pending) = self.pending.get(&port) else {
+                    read.complete(Err(io::Error::new(
+                        io::ErrorKind::NotConnected,
+                        "port closed while listener still active",
+                    )));
+                    return Ok(true);
+                };
+                let acceptors = self.acceptors.entry(port).or_default();
+                if let Wait::Never = wait {
+                    if !acceptors.is_empty() || pending.is_empty() {
+                        read.complete(Err(would_block("no pending connections")));
+                        return Ok(true);
+                    }
+                }
+                acceptors.push_back(Parked {
+                    arg: (),
+                    deadline: wait.deadline(),
+                    completion: read,
+                });
+                self.schedule_deadline(wait);
+                self.on_listener_event(port);
+            }
+            InterfaceRequest::Connect { remote, wait, ack } => {
+                let Some(port) = self.ephemeral_port(remote) else {
+                    ack.complete(Err(io::Error::new(
+                        io::ErrorKind::AddrNotAvailable,
+                        "no free local port",
+                    )));
+                    return Ok(true);
+                };
+                let quad = Quad {
+                    src: remote,
+                    dst: (self.addr, port),
+                };
+                let (conn, output) = Connection::connect(
+                    SocketAddrV4::new(self.addr, port),
+                    SocketAddrV4::new(remote.0, remote.1),
+                    &self.config,
+                    self.config.clock.now(),
+                )?;
+                transmit(nic, output)?;
+                let mut c = Socket::new(conn);
+                if let Wait::Never = wait {
+                    // the stream is handed out right away, and the handshake goes on without it
+                    ack.complete(Ok(quad));
+                } else {
+                    c.waiting.connectors.push_back(Parked {
+                        arg: (),
+                        deadline: None,
+                        completion: ack,
+                    });
+                }
+                self.connections.insert(quad, c);
+                self.on_connection_event(nic, quad)?;
+            }
+            InterfaceRequest::Register {
+                key,
+                registration,
+                ack,
+            } => ack.complete(self.register(key, registration)),
+            InterfaceRequest::Deregister { key, poller, ack } => {
+                ack.complete(self.deregister(key, &poller))
+            }
+            InterfaceRequest::Capture { ack, .. } | InterfaceRequest::Tick { ack } => {
+                ack.complete(Err(io::Error::other("must be handled by the packet loop")))
+            }
+            InterfaceRequest::Terminate => return Ok(false),
+        }
+
+        Ok(true)
+    }
+
+    fn park_write(
+        &mut self,
+        nic: &mut impl NetDevice,
+        quad: Quad,
+        outgoing: Outgoing,
+        wait: Wait,
+        ack: Completion<usize>,
+    ) -> io::Result<()> {
+        let Some(c) = self.connections.get_mut(&quad) else {
+            ack.complete(Err(terminated()));
+            return Ok(());
+        };
+        if let Wait::Never = wait {
+            if !c.waiting.writers.is_empty() || !c.conn.availability().contains(Available::WRITE) {
+                ack.complete(Err(would_block("too many bytes buffered")));
+                return Ok(());
+            }
+        }
+        c.waiting.writers.push_back(Parked {
+            arg: outgoing,
+            deadline: wait.deadline(),
+            completion: ack,
+        });
+        self.schedule_deadline(wait);
+        self.on_connection_event(nic, quad)
+    }
+
+    /// Pick a local port for a connection to `remote` that is neither bound nor in use.
+    fn ephemeral_port(&mut self, remote: (Ipv4Addr, u16)) -> Option<u16> {
+        for _ in EPHEMERAL_PORTS {
+            let port = self.next_port;
+            self.next_port = if port == *EPHEMERAL_PORTS.end() {
+                *EPHEMERAL_PORTS.start()
+            } else {
+                port + 1
+            };
+
+            let quad = Quad {
+                src: remote,
+                dst: (self.addr, port),
+            };
+            if !self.pending.contains_key(&port) && !self.connections.contains_key(&quad) {
+                return Some(port);
+            }
+        }
+
+        None
+    }
+
+    /// Make sure the packet thread wakes up in time to fail a request that is parked with `wait`.
+    fn schedule_deadline(&mut self, wait: Wait) {
+        if let Some(deadline) = wait.deadline() {
+            self.timers.schedule_by(Timer::Requests, deadline);
+        }
+    }
+
+    /// Look at every request blocked on `quad`, e.g. after the application has asked for
+    /// something new.
+    fn on_connection_event(&mut self, nic: &mut impl NetDevice, quad: Quad) -> io::Result<()> {
+        self.wake(nic, quad, Wake::all())
+    }
+
+    /// Act on what a segment or timer changed for the application of `quad`.
+    fn on_events(
+        &mut self,
+        nic: &mut impl NetDevice,
+        quad: Quad,
+        events: &[ConnectionEvent],
+    ) -> io::Result<()> {
+        if events.contains(&ConnectionEvent::Established) {
+            if let Some(c) = self.connections.get_mut(&quad) {
+                c.established = true;
+            }
+        }
+        self.wake(nic, quad, Wake::after(events))
+    }
+
+    /// Complete whatever requests of `wake` blocked on `quad` can make progress now, then send
+    /// whatever data has been queued, rearm the connection's timers and tell pollers.
+    fn wake(&mut self, nic: &mut impl NetDevice, quad: Quad, wake: Wake) -> io::Result<()> {
+        self.on_handshake_over(quad);
+        let Some(c) = self.connections.get_mut(&quad) else {
+            return Ok(());
+        };
+        let mut waiting = std::mem::take(&mut c.waiting);
+        // why requests that can never succeed fail, if the connection ended abnormally
+        let failure: Option<fn() -> io::Error> = if c.conn.timed_out {
+            Some(timed_out)
+        } else if c.conn.is_closed() && !c.established {
+            Some(connection_refused)
+        } else if c.conn.reset {
+            Some(connection_reset)
+        } else {
+            None
+        };
+
+        if wake.contains(Wake::READERS) {
+            while let Some(p) = waiting.readers.pop_front() {
+                if p.completion.is_abandoned() {
+                    continue;
+                }
+                if let Some(error) = failure {
+                    p.completion.complete(Err(error()));
+                    continue;
+                }
+                if !c.conn.availability().contains(Available::READ) {
+                    waiting.readers.push_front(p);
+                    break;
+                }
+                // the caller's length is only an upper bound, and may be huge
+                let mut buf = vec![0; p.arg.min(c.conn.incoming.len())];
+                let mark = c.conn.urgent_mark;
+                let n = c.conn.try_read(&mut buf).unwrap_or(0);
+                buf.truncate(n);
+                if let Err(buf) = p.completion.deliver(buf) {
+                    // the reader went away just now, so the data is left for the next one
+                    c.conn.unread(&buf, mark);
+                }
+            }
+        }
+
+        if wake.contains(Wake::WRITERS) {
+            while let Some(p) = waiting.writers.pop_front() {
+                if p.completion.is_abandoned() {
+                    continue;
+                }
+                if let Some(error) = failure {
+                    p.completion.complete(Err(error()));
+                    continue;
+                }
+                if c.conn.closed {
+                    p.completion.complete(Err(io::Error::new(
+                        io::ErrorKind::BrokenPipe,
+                        "connection has been shut down for writing",
+                    )));
+                    continue;
+                }
+                if !c.conn.availability().contains(Available::WRITE) {
+                    waiting.writers.push_front(p);
+                    break;
+                }
+                let n = match p.arg {
+                    Outgoing::Data(bytes) => c.conn.try_write(&bytes).unwrap_or(0),
+                    Outgoing::Urgent(byte) => {
+                        c.conn.send_urgent(byte);
+                        1
+                    }
+                };
+                p.completion.complete(Ok(n));
+            }
+        }
+
+        if wake.contains(Wake::CONNECTORS) {
+            if c.established {
+                waiting
+                    .connectors
+                    .drain(..)
+                    .for_each(|p| p.completion.complete(Ok(quad)));
+            } else if let Some(error) = failure.filter(|_| !waiting.connectors.is_empty()) {
+                waiting
+                    .connectors
+                    .drain(..)
+                    .for_each(|p| p.completion.complete(Err(error())));
+                // there will never be a stream to release it
+                c.released = true;
+            }
+        }
+
+        if c.conn.unacked.is_empty() {
+            waiting
+                .flushers
+                .drain(..)
+                .for_each(|p| p.completion.complete(Ok(())));
+        } else if let Some(error) = failure {
+            waiting
+                .flushers
+                .drain(..)
+                .for_each(|p| p.completion.complete(Err(error())));
+        }
+
+        c.waiting = waiting;
+
+        // new data goes out right away rather than on the next timer
+        let sent = transmit(nic, c.conn.send_pending(self.config.clock.now())?)?;
+        debug_assert!(sent.is_empty(), "sending changed {sent:?}");
+
+        for kind in TimerKind::ALL {
+            let timer = Timer::Connection(quad, kind);
+            match c.conn.deadline(kind) {
+                Some(at) => self.timers.schedule(timer, at),
+                None => self.timers.cancel(timer),
+            }
+        }
+
+        if c.conn.is_closed() && c.released {
+            self.remove(quad);
+            return Ok(());
+        }
+
+        let a = c.conn.availability();
+        self.notify_pollers(poll::SourceKey::Stream(quad), a);
+
+        Ok(())
+    }
+
+    /// Take a connection that has left SYN-RCVD off its listener's half-open backlog. One whose
+    /// handshake failed, e.g. because the peer never answered, is dropped unless it has been
+    /// accepted already.
+    fn on_handshake_over(&mut self, quad: Quad) {
+        let Some(c) = self.connections.get_mut(&quad) else {
+            return;
+        };
+        if !c.half_open || c.conn.state() == tcp::State::SynRcvd {
+            return;
+        }
+        c.half_open = false;
+        let port = quad.dst.1;
+        if let Some(n) = self.half_open.get_mut(&port) {
+            *n -= 1;
+        }
+        if !c.conn.is_closed() {
+            return;
+        }
+        let Some(pending) = self.pending.get_mut(&port) else {
+            return;
+        };
+        if let Some(at) = pending.iter().position(|&q| q == quad) {
+            c.released = true;
+            pending.remove(at);
+            self.on_listener_event(port);
+        }
+    }
+
+    /// Forget a connection that is closed and no longer has a `TcpStream`.
+    fn remove(&mut self, quad: Quad) {
+        if let Some(mut c) = self.connections.remove(&quad) {
+            c.waiting.abort(terminated);
+        }
+        self.registrations.remove(&poll::SourceKey::Stream(quad));
+        for kind in TimerKind::ALL {
+            self.timers.cancel(Timer::Connection(quad, kind));
+        }
+    }
+
+    fn on_timer(&mut self, nic: &mut impl NetDevice, timer: Timer, now: Instant) -> io::Result<()> {
+        match timer {
+            Timer::Connection(quad, _) => {
+                if let Some(c) = self.connections.get_mut(&quad) {
+                    let events = transmit(nic, c.conn.on_timer(now)?)?;
+                    self.on_events(nic, quad, &events)?;
+                }
+            }
+            Timer::Requests => self.expire(now),
+        }
+
+        Ok(())
+    }
+
+    /// Hand pending connections on `port` to blocked `accept` calls, and tell pollers whether
+    /// any are left.
+    fn on_listener_event(&mut self, port: u16) {
+        if let (Some(pending), Some(acceptors)) =
+            (self.pending.get_mut(&port), self.acceptors.get_mut(&port))
+        {
+            while !pending.is_empty() {
+                let Some(p) = acceptors.pop_front() else {
+                    break;
+                };
+                if p.completion.is_abandoned() {
+                    continue;
+                }
+                let quad = pending.pop_front().expect("checked above");
+                if let Err(quad) = p.completion.deliver(quad) {
+                    // the acceptor went away just now, so the connection is left for the next one
+                    pending.push_front(quad);
+                }
+            }
+        }
+
+        let key = poll::SourceKey::Listener(port);
+        self.notify_pollers(key, self.readiness(key));
+    }
+
+    /// Fail blocked requests whose deadline has passed, and wait for the next one.
+    fn expire(&mut self, now: Instant) {
+        for c in self.connections.values_mut() {
+            if !c.waiting.is_empty() {
+                c.waiting.expire(now);
+            }
+        }
+        for acceptors in self.acceptors.values_mut() {
+            request::expire(acceptors, now);
+        }
+
+        let next = self
+            .connections
+            .values()
+            .flat_map(|c| c.waiting.deadlines())
+            .chain(self.acceptors.values().flatten().filter_map(|p| p.deadline))
+            .min();
+        if let Some(next) = next {
+            self.timers.schedule(Timer::Requests, next);
+        }
+    }
+
+    /// Handle one IP packet from the nic; anything but a well-formed TCP segment is dropped.
+    fn on_datagram(&mut self, nic: &mut impl NetDevice, buf: &[u8]) -> io::Result<()> {
+        let Ok((iph, tcph, data)) = connection::parse(buf) else {
+            return Ok(());
+        };
+        if self.config.verify_checksums && !connection::checksums_match(&iph, &tcph, data) {
+            self.counters.bad_checksum.fetch_add(1, Ordering::Relaxed);
+            return Ok(());
+        }
+        let quad = Quad {
+            src: (iph.source_addr(), tcph.source_port()),
+            dst: (iph.destination_addr(), tcph.destination_port()),
+        };
+
+        let now = self.config.clock.now();
+        if let Some(c) = self.connections.get_mut(&quad) {
+            let events = transmit(nic, c.conn.on_segment(buf, now)?)?;
+            return self.on_events(nic, quad, &events);
+        }
+
+        let Some(pending) = self.pending.get_mut(&tcph.destination_port()) else {
+            // nobody is listening
+            return reset(nic, iph, tcph, data);
+        };
+        let port = quad.dst.1;
+        let half_open = self.half_open.entry(port).or_default();
+        let opened = if pending.len().saturating_sub(*half_open) >= self.config.accept_backlog {
+            // nobody is accepting: a SYN is left for the peer to send again, when there may be
+            // room, while an ACK would complete a handshake there is no room for
+            if tcph.syn() {
+                return Ok(());
+            }
+            None
+        } else if tcph.syn() && *half_open >= self.config.syn_backlog {
+            // the backlog is full: keep nothing until the peer proves it got our answer
+            if let Some(output) = Connection::syn_cookie(buf, &self.config, now)? {
+                self.counters.syn_cookies.fetch_add(1, Ordering::Relaxed);
+                self.cookie_sent_at.insert(port, now);
+                // there is no connection for anything to have changed for
+                transmit(nic, output)?;
+                return Ok(());
+            }
+            None
+        } else if tcph.syn() {
+            Connection::accept(buf, &self.config, now)?
+        } else if self
+            .cookie_sent_at
+            .get(&port)
+            .is_some_and(|&at| now.saturating_duration_since(at) < isn::COOKIE_LIFETIME)
+        {
+            // only a listener that has handed out cookies lately can get one back; otherwise
+            // this would be a guess at the MAC of one that was never sent
+            Connection::from_cookie(buf, &self.config, now)?
+        } else {
+            None
+        };
+        let Some((conn, output)) = opened else {
+            return reset(nic, iph, tcph, data);
+        };
+
+        let events = transmit(nic, output)?;
+        let mut socket = Socket::new(conn);
+        if socket.conn.state() == tcp::State::SynRcvd {
+            socket.half_open = true;
+            *half_open += 1;
+        }
+        self.connections.insert(quad, socket);
+        pending.push_back(quad);
+        self.on_events(nic, quad, &events)?;
+        self.on_listener_event(port);
+
+        Ok(())
+    }
 }
 
 pub struct Interface {
     ih: Option<InterfaceHandle>,
     jh: Option<thread::JoinHandle<io::Result<()>>>,
+    counters: Arc<Counters>,
 }
 
 impl Drop for Interface {
     fn drop(&mut self) {
-        self.ih.as_mut().unwrap().manager.lock().unwrap().terminate = true;
+        let _ = self.ih.as_ref().unwrap().send(InterfaceRequest::Terminate);
 
         drop(self.ih.take());
         self.jh
@@ -104,131 +1006,225 @@ impl Drop for Interface {
 }
 
 impl Interface {
+    /// Run the stack on the tun device `tun0`, as 192.168.0.2. The host's end of the link is
+    /// left as it is, so it has to have been given an address and brought up already;
+    /// [`InterfaceBuilder::host_addr`] does that instead.
     pub fn new() -> io::Result<Self> {
-        let mut nic = tun_tap::Iface::without_packet_info("tun0", tun_tap::Mode::Tun)?;
+        InterfaceBuilder::new().build()
+    }
+
+    /// Configure the device and the stack before starting it.
+    pub fn builder() -> InterfaceBuilder {
+        InterfaceBuilder::new()
+    }
 
-        let ih: InterfaceHandle = Arc::default();
+    /// Run the stack on the tap device `name`, answering ARP for `addr` as `mac`.
+    pub fn with_tap(name: &str, addr: Ipv4Addr, mac: [u8; 6]) -> io::Result<Self> {
+        InterfaceBuilder::new()
+            .name(name)
+            .tap(mac)
+            .addr(addr, 24)
+            .build()
+    }
 
-        let jh = {
-            let ih = ih.clone();
-            thread::spawn(move || packet_loop(&mut nic, ih))
-        };
+    /// Run the stack on any device, moving it to the packet thread; `addr` is the source
+    /// address of connections we open.
+    pub fn with_device<D: NetDevice + Send + 'static>(nic: D, addr: Ipv4Addr) -> io::Result<Self> {
+        InterfaceBuilder::new()
+            .addr(addr, 32)
+            .build_with_device(nic)
+    }
+
+    pub(crate) fn start<D: NetDevice + Send + 'static>(
+        nic: D,
+        addr: Ipv4Addr,
+        config: Config,
+    ) -> io::Result<Self> {
+        let (requests, rx) = mpsc::channel();
+        let (wake, wake_rx) = UnixStream::pair()?;
+        wake.set_nonblocking(true)?;
+        wake_rx.set_nonblocking(true)?;
+
+        let ih: InterfaceHandle = Arc::new(Foobar {
+            requests,
+            wake,
+            clock: config.clock.clone(),
+            simulated: false,
+        });
+        if let Some(clock) = config.clock.as_mock() {
+            clock.attach(Arc::downgrade(&ih));
+        }
+
+        let counters = Arc::<Counters>::default();
+        let clock = config.clock.clone();
+        let cm = ConnectionManager::new(addr, config, counters.clone());
+        let jh =
+            thread::spawn(move || packet_loop(&mut Captured::new(nic, clock), cm, rx, wake_rx));
 
         Ok(Self {
             ih: Some(ih),
             jh: Some(jh),
+            counters,
         })
     }
 
+    /// Packets dropped because their IPv4 header or TCP checksum was wrong, so far.
+    pub fn checksum_errors(&self) -> u64 {
+        self.counters.bad_checksum.load(Ordering::Relaxed)
+    }
+
+    /// SYNs answered with a SYN cookie, see
+    /// [`InterfaceBuilder::syn_backlog`](crate::InterfaceBuilder::syn_backlog).
+    pub fn syn_cookies_sent(&self) -> u64 {
+        self.counters.syn_cookies.load(Ordering::Relaxed)
+    }
+
+    /// Deadlines the packet thread is keeping track of, including superseded ones it has not
+    /// thrown away yet.
+    #[doc(hidden)]
+    pub fn queued_timers(&self) -> u64 {
+        self.counters.queued_timers.load(Ordering::Relaxed)
+    }
+
     pub fn bind(&mut self, port: u16) -> io::Result<tcp_listener::TcpListener> {
-        let mut cm = self.ih.as_mut().unwrap().manager.lock().unwrap();
-        match cm.pending.entry(port) {
-            Entry::Vacant(v) => {
-                v.insert(VecDeque::new());
-            }
-            Entry::Occupied(_) => {
-                return Err(io::Error::new(
-                    io::ErrorKind::AddrInUse,
-                    "port already bound",
-                ));
-            }
-        }
-        drop(cm);
+        let ih = self.ih.as_ref().unwrap();
+        ih.call(|ack| InterfaceRequest::Bind { port, ack })?;
         Ok(tcp_listener::TcpListener {
             port,
-            h: self.ih.as_mut().unwrap().clone(),
+            h: ih.clone(),
+            nonblocking: false,
+            accept_timeout: None,
         })
     }
+
+    /// Open a connection to `addr`, blocking until the handshake is over.
+    pub fn connect(&mut self, addr: SocketAddrV4) -> io::Result<tcp_stream::TcpStream> {
+        let ih = self.ih.as_ref().unwrap();
+        let quad = ih.call(|ack| InterfaceRequest::Connect {
+            remote: (*addr.ip(), addr.port()),
+            wait: Wait::Until(None),
+            ack,
+        })?;
+        Ok(tcp_stream::TcpStream::new(quad, ih.clone()))
+    }
+
+    /// Start opening a connection to `addr`, and return a stream in non-blocking mode right
+    /// away. Until the handshake is over, reads fail with `WouldBlock` and writes are queued; if
+    /// the connection is refused, they fail with `ConnectionRefused`.
+    pub fn connect_nonblocking(&mut self, addr: SocketAddrV4) -> io::Result<tcp_stream::TcpStream> {
+        let ih = self.ih.as_ref().unwrap();
+        let quad = ih.call(|ack| InterfaceRequest::Connect {
+            remote: (*addr.ip(), addr.port()),
+            wait: Wait::Never,
+            ack,
+        })?;
+        let mut stream = tcp_stream::TcpStream::new(quad, ih.clone());
+        stream.set_nonblocking(true)?;
+        Ok(stream)
+    }
+
+    /// Write every IP packet the stack sends or receives to `sink` in pcap format, e.g. to a
+    /// `File` that can be opened in Wireshark. Replaces any earlier capture.
+    pub fn capture(&mut self, sink: impl Write + Send + 'static) -> io::Result<()> {
+        let sink: capture::Sink = Box::new(sink);
+        self.ih
+            .as_ref()
+            .unwrap()
+            .call(|ack| InterfaceRequest::Capture {
+                sink: Some(sink),
+                ack,
+            })
+    }
+
+    /// Stop capturing packets, flushing the sink.
+    pub fn stop_capture(&mut self) -> io::Result<()> {
+        self.ih
+            .as_ref()
+            .unwrap()
+            .call(|ack| InterfaceRequest::Capture { sink: None, ack })
+    }
 }
 
-fn packet_loop(nic: &mut tun_tap::Iface, ih: InterfaceHandle) -> io::Result<()> {
-    let mut buf = [0u8; 1504];
+fn packet_loop(
+    nic: &mut Captured<impl NetDevice>,
+    mut cm: ConnectionManager,
+    requests: mpsc::Receiver<InterfaceRequest>,
+    mut wake: UnixStream,
+) -> io::Result<()> {
+    let clock = cm.config.clock.clone();
+    let mut buf = vec![0u8; nic.mtu()];
+    let mut ticks = Vec::new();
 
     loop {
         // we want to read from nic, but we want to make sure that we'll wake up when the next
-        // timer has to be triggered!
+        // timer has to be triggered, or when an application thread makes a request!
+        let timeout = match cm.timers.next_deadline() {
+            // round up, so that we don't wake up just before the deadline and spin
+            Some(at) if clock.as_mock().is_none() => at
+                .saturating_duration_since(clock.now())
+                .as_nanos()
+                .div_ceil(1_000_000)
+                .min(i32::MAX as u128) as i32,
+            // a mock clock wakes us up when it is advanced
+            _ => -1,
+        };
         let raw_fd = RawFdWrapper(nic.as_raw_fd());
-        let mut pfd = [nix::poll::PollFd::new(
-            &raw_fd,
-            nix::poll::PollFlags::POLLIN,
-        )];
-        let n = nix::poll::poll(&mut pfd[..], 1)?;
-        if n == 0 {
-            let mut cmg = ih.manager.lock().unwrap();
-            let cm = &mut *cmg;
-            for connection in cm.connections.values_mut() {
-                connection.on_tick(nic)?;
-            }
-
-            continue;
-        }
-        assert_eq!(n, 1);
-        let nbytes = nic.recv(&mut buf[..])?;
-
-        // let _eth_flags = u16::from_be_bytes([buf[0], buf[1]]);
-        // let eth_proto = u16::from_be_bytes([buf[2], buf[3]]);
-
-        // if eth_proto != 0x0800 {
-        //     // no IPV4
-        //     continue;
-        // }
-
-        match etherparse::Ipv4HeaderSlice::from_slice(&buf[..nbytes]) {
-            Ok(iph) => {
-                let src = iph.source_addr();
-                let dst = iph.destination_addr();
-                if iph.protocol().0 != 0x06 {
-                    eprint!("BAD PROTOCOL");
-                    // not tcp
-                    continue;
+        let mut pfd = [
+            nix::poll::PollFd::new(&raw_fd, nix::poll::PollFlags::POLLIN),
+            nix::poll::PollFd::new(&wake, nix::poll::PollFlags::POLLIN),
+        ];
+        nix::poll::poll(&mut pfd[..], timeout)?;
+        let readable = |pfd: &nix::poll::PollFd| {
+            pfd.revents()
+                .is_some_and(|r| r.intersects(nix::poll::PollFlags::POLLIN))
+        };
+        let (nic_ready, wake_ready) = (readable(&pfd[0]), readable(&pfd[1]));
+
+        if wake_ready {
+            let mut drain = [0u8; 256];
+            loop {
+                match wake.read(&mut drain) {
+                    Ok(n) if n > 0 => continue,
+                    Ok(_) => break,
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                    Err(e) => return Err(e),
                 }
+            }
+        }
 
-                match etherparse::TcpHeaderSlice::from_slice(&buf[iph.slice().len()..nbytes]) {
-                    Ok(tcph) => {
-                        let datai = iph.slice().len() + tcph.slice().len();
-                        let mut cmg = ih.manager.lock().unwrap();
-                        let cm = &mut *cmg;
-                        let quad = Quad {
-                            src: (src, tcph.source_port()),
-                            dst: (dst, tcph.destination_port()),
-                        };
-
-                        match cm.connections.entry(quad) {
-                            Entry::Occupied(mut c) => {
-                                let a =
-                                    c.get_mut().on_packet(nic, iph, tcph, &buf[datai..nbytes])?;
-
-                                // TODO: compare before/after
-                                drop(cmg);
-                                if a.contains(tcp::Available::READ) {
-                                    ih.rcv_var.notify_all();
-                                }
-
-                                if a.contains(tcp::Available::WRITE) {
-                                    // TODO: ih.snd_var.notify_all();
-                                }
-                            }
-                            Entry::Vacant(e) => {
-                                if let Some(pending) = cm.pending.get_mut(&tcph.destination_port())
-                                {
-                                    if let Some(c) = Connection::accept(nic, iph, tcph)? {
-                                        e.insert(c);
-                                        pending.push_back(quad);
-                                        drop(cmg);
-                                        ih.pending_var.notify_all();
-                                    };
-                                }
-                            }
-                        }
-                    }
-                    Err(e) => {
-                        eprintln!("ignoring weird tcp packet: {e}");
+        loop {
+            match requests.try_recv() {
+                Ok(InterfaceRequest::Capture { sink, ack }) => ack.complete(nic.set_sink(sink)),
+                Ok(InterfaceRequest::Tick { ack }) => ticks.push(ack),
+                Ok(request) => {
+                    if !cm.handle(nic, request)? {
+                        return Ok(());
                     }
                 }
+                Err(mpsc::TryRecvError::Empty) => break,
+                Err(mpsc::TryRecvError::Disconnected) => return Ok(()),
             }
-            Err(_e) => {
-                // eprintln!("ignoring weird packet: {e}");
+        }
+
+        if nic_ready {
+            match nic.recv(&mut buf[..]) {
+                Ok(nbytes) => cm.on_datagram(nic, &buf[..nbytes])?,
+                // a device can look readable with nothing to read, e.g. as its peer goes away
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                Err(e) => return Err(e),
             }
         }
+
+        // timers fire no matter how busy the nic is
+        let now = clock.now();
+        while let Some(timer) = cm.timers.pop_expired(now) {
+            cm.on_timer(nic, timer, now)?;
+        }
+        cm.counters
+            .queued_timers
+            .store(cm.timers.len() as u64, Ordering::Relaxed);
+        for ack in ticks.drain(..) {
+            ack.complete(Ok(()));
+        }
     }
 }
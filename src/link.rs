@@ -0,0 +1,81 @@
+//! Configuring the host's end of a tun or tap device, as `ip addr` and `ip link` would.
+
+use std::{io, mem, net::Ipv4Addr, net::UdpSocket, os::fd::AsRawFd};
+
+use nix::libc;
+
+fn ifreq(name: &str) -> io::Result<libc::ifreq> {
+    if name.len() >= libc::IFNAMSIZ {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "device name is too long",
+        ));
+    }
+
+    // SAFETY: ifreq is plain old data, for which all zeroes is valid
+    let mut req: libc::ifreq = unsafe { mem::zeroed() };
+    for (dst, src) in req.ifr_name.iter_mut().zip(name.bytes()) {
+        *dst = src as libc::c_char;
+    }
+    Ok(req)
+}
+
+fn sockaddr(addr: Ipv4Addr) -> libc::sockaddr {
+    let sin = libc::sockaddr_in {
+        sin_family: libc::AF_INET as libc::sa_family_t,
+        sin_port: 0,
+        sin_addr: libc::in_addr {
+            s_addr: u32::from(addr).to_be(),
+        },
+        sin_zero: [0; 8],
+    };
+    // SAFETY: sockaddr_in and sockaddr have the same size, and the kernel reads the address
+    // according to sin_family
+    unsafe { mem::transmute(sin) }
+}
+
+fn ioctl(request: libc::c_ulong, req: &mut libc::ifreq) -> io::Result<()> {
+    // any socket will do for interface ioctls
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+    // SAFETY: every request we make reads or writes an ifreq, which outlives the call
+    if unsafe { libc::ioctl(socket.as_raw_fd(), request as _, req as *mut libc::ifreq) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+pub(crate) fn mtu(name: &str) -> io::Result<usize> {
+    let mut req = ifreq(name)?;
+    ioctl(libc::SIOCGIFMTU, &mut req)?;
+    // SAFETY: SIOCGIFMTU filled in the MTU
+    Ok(unsafe { req.ifr_ifru.ifru_mtu } as usize)
+}
+
+pub(crate) fn set_mtu(name: &str, mtu: usize) -> io::Result<()> {
+    let mut req = ifreq(name)?;
+    req.ifr_ifru.ifru_mtu = mtu
+        .try_into()
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "mtu is too large"))?;
+    ioctl(libc::SIOCSIFMTU, &mut req)
+}
+
+/// Give the host `addr`/`prefix` on device `name`, and bring the link up.
+pub(crate) fn configure(name: &str, addr: Ipv4Addr, prefix: u8) -> io::Result<()> {
+    let mut req = ifreq(name)?;
+    req.ifr_ifru.ifru_addr = sockaddr(addr);
+    ioctl(libc::SIOCSIFADDR, &mut req)?;
+
+    let mut req = ifreq(name)?;
+    req.ifr_ifru.ifru_netmask = sockaddr(netmask(prefix));
+    ioctl(libc::SIOCSIFNETMASK, &mut req)?;
+
+    let mut req = ifreq(name)?;
+    ioctl(libc::SIOCGIFFLAGS, &mut req)?;
+    // SAFETY: SIOCGIFFLAGS filled in the flags
+    unsafe { req.ifr_ifru.ifru_flags |= libc::IFF_UP as libc::c_short };
+    ioctl(libc::SIOCSIFFLAGS, &mut req)
+}
+
+pub(crate) fn netmask(prefix: u8) -> Ipv4Addr {
+    Ipv4Addr::from(u32::MAX.checked_shl(32 - prefix as u32).unwrap_or(0))
+}
@@ -4,13 +4,16 @@ use std::{
 };
 
 fn main() -> io::Result<()> {
-    let mut i = thunder::Interface::new()?;
+    // the stack is 192.168.0.2, and the host reaches it from 192.168.0.1
+    let mut i = thunder::Interface::builder()
+        .host_addr(std::net::Ipv4Addr::new(192, 168, 0, 1))
+        .build()?;
     let mut listener = i.bind(9000)?;
 
     while let Ok(mut stream) = listener.accept() {
         thread::spawn(move || {
             eprintln!("got connection!");
-            stream.write(b"hello from thunder").unwrap();
+            stream.write_all(b"hello from thunder").unwrap();
             stream.shutdown(std::net::Shutdown::Write).unwrap();
             loop {
                 let mut buf = [0u8; 512];
@@ -0,0 +1,141 @@
+use std::{
+    io::{self, Read, Write},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+const MAGIC: u32 = 0xa1b2c3d4;
+/// same format, with nanosecond timestamps
+const MAGIC_NANOS: u32 = 0xa1b23c4d;
+const SNAPLEN: u32 = 65535;
+/// largest record libpcap will write, so anything bigger is a corrupt file
+const MAX_SNAPLEN: u32 = 262144;
+/// bare IPv4/IPv6 packets, with no link-layer header
+pub(crate) const LINKTYPE_RAW: u32 = 101;
+
+/// Writes packets in the classic pcap format (microsecond timestamps), which Wireshark and
+/// tcpdump can open.
+pub struct PcapWriter<W: Write> {
+    out: W,
+}
+
+impl<W: Write> PcapWriter<W> {
+    /// Start a capture of IP packets by writing the file header.
+    pub fn new(mut out: W) -> io::Result<Self> {
+        let mut header = Vec::with_capacity(24);
+        header.extend_from_slice(&MAGIC.to_le_bytes());
+        header.extend_from_slice(&2u16.to_le_bytes());
+        header.extend_from_slice(&4u16.to_le_bytes());
+        // timezone offset and timestamp accuracy, always 0
+        header.extend_from_slice(&[0; 8]);
+        header.extend_from_slice(&SNAPLEN.to_le_bytes());
+        header.extend_from_slice(&LINKTYPE_RAW.to_le_bytes());
+        out.write_all(&header)?;
+
+        Ok(Self { out })
+    }
+
+    pub fn write_packet(&mut self, timestamp: SystemTime, packet: &[u8]) -> io::Result<()> {
+        let since_epoch = timestamp.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let captured = std::cmp::min(packet.len(), SNAPLEN as usize);
+
+        let mut record = Vec::with_capacity(16 + captured);
+        record.extend_from_slice(&(since_epoch.as_secs() as u32).to_le_bytes());
+        record.extend_from_slice(&since_epoch.subsec_micros().to_le_bytes());
+        record.extend_from_slice(&(captured as u32).to_le_bytes());
+        record.extend_from_slice(&(packet.len() as u32).to_le_bytes());
+        record.extend_from_slice(&packet[..captured]);
+        self.out.write_all(&record)
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+}
+
+/// Reads packets from a classic pcap file, in either byte order and with microsecond or
+/// nanosecond timestamps.
+pub struct PcapReader<R: Read> {
+    input: R,
+    big_endian: bool,
+    nanos: bool,
+    link_type: u32,
+}
+
+impl<R: Read> PcapReader<R> {
+    /// Read the file header.
+    pub fn new(mut input: R) -> io::Result<Self> {
+        let mut header = [0u8; 24];
+        input.read_exact(&mut header)?;
+
+        let magic = u32::from_le_bytes(header[..4].try_into().expect("4 bytes"));
+        let (big_endian, nanos) = match (magic, magic.swap_bytes()) {
+            (MAGIC, _) => (false, false),
+            (MAGIC_NANOS, _) => (false, true),
+            (_, MAGIC) => (true, false),
+            (_, MAGIC_NANOS) => (true, true),
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "not a pcap file",
+                ))
+            }
+        };
+
+        let mut reader = Self {
+            input,
+            big_endian,
+            nanos,
+            link_type: 0,
+        };
+        reader.link_type = reader.u32_at(&header, 20);
+        Ok(reader)
+    }
+
+    /// The kind of packets in the file, e.g. 101 for bare IP packets or 1 for Ethernet frames.
+    pub fn link_type(&self) -> u32 {
+        self.link_type
+    }
+
+    /// The next packet and when it was captured, or `None` at the end of the file.
+    pub fn read_packet(&mut self) -> io::Result<Option<(SystemTime, Vec<u8>)>> {
+        let mut header = [0u8; 16];
+        match self.input.read_exact(&mut header) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        }
+
+        let secs = self.u32_at(&header, 0) as u64;
+        let fraction = self.u32_at(&header, 4);
+        let captured = self.u32_at(&header, 8);
+        if captured > MAX_SNAPLEN {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "pcap record is too large",
+            ));
+        }
+
+        let mut packet = vec![0u8; captured as usize];
+        self.input.read_exact(&mut packet)?;
+
+        let since_epoch = if self.nanos {
+            Duration::new(secs, fraction)
+        } else {
+            Duration::new(secs, 0) + Duration::from_micros(fraction as u64)
+        };
+        Ok(Some((UNIX_EPOCH + since_epoch, packet)))
+    }
+
+    fn u32_at(&self, buf: &[u8], at: usize) -> u32 {
+        let bytes = buf[at..at + 4].try_into().expect("4 bytes");
+        if self.big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        }
+    }
+}
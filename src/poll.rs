@@ -0,0 +1,337 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    io,
+    sync::{Arc, Condvar, Mutex, OnceLock, Weak},
+    time::Duration,
+};
+
+use crate::{
+    clock::{Clock, SystemClock},
+    tcp::Available,
+    tcp_listener::TcpListener,
+    tcp_stream::TcpStream,
+    ConnectionManager, InterfaceHandle, InterfaceRequest, Quad,
+};
+
+/// Identifies a registered source in the events returned by [`Poller::wait`].
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Token(pub usize);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Event {
+    token: Token,
+    readiness: Available,
+}
+
+impl Event {
+    pub fn token(&self) -> Token {
+        self.token
+    }
+
+    pub fn is_readable(&self) -> bool {
+        self.readiness.contains(Available::READ)
+    }
+
+    pub fn is_writable(&self) -> bool {
+        self.readiness.contains(Available::WRITE)
+    }
+}
+
+/// What a registration is attached to inside the connection manager.
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+pub enum SourceKey {
+    Stream(Quad),
+    Listener(u16),
+}
+
+pub(crate) struct Registration {
+    poller: Weak<Shared>,
+    token: Token,
+    interest: Available,
+    /// the readiness last seen for this registration, so only a change to ready is reported
+    ready: Available,
+}
+
+#[derive(Default)]
+struct Ready {
+    order: VecDeque<Token>,
+    readiness: HashMap<Token, Available>,
+}
+
+#[derive(Default)]
+pub(crate) struct Shared {
+    ready: Mutex<Ready>,
+    var: Condvar,
+    /// what `wait` measures timeouts with: the clock of the interface of the first source
+    /// registered
+    clock: OnceLock<Arc<dyn Clock>>,
+}
+
+impl Shared {
+    /// Wake `wait` to look at the clock again.
+    pub(crate) fn wake(&self) {
+        let _ready = self.ready.lock().unwrap();
+        self.var.notify_all();
+    }
+
+    fn push(&self, token: Token, readiness: Available) {
+        let mut ready = self.ready.lock().unwrap();
+        let ready = &mut *ready;
+        match ready.readiness.get_mut(&token) {
+            Some(r) => *r |= readiness,
+            None => {
+                ready.readiness.insert(token, readiness);
+                ready.order.push_back(token);
+            }
+        }
+        self.var.notify_one();
+    }
+}
+
+mod sealed {
+    pub trait Source {
+        fn handle(&self) -> &crate::InterfaceHandle;
+        fn key(&self) -> super::SourceKey;
+    }
+}
+
+/// A thunder socket that can be registered with a [`Poller`].
+pub trait Source: sealed::Source {}
+
+impl sealed::Source for TcpStream {
+    fn handle(&self) -> &InterfaceHandle {
+        &self.h
+    }
+
+    fn key(&self) -> SourceKey {
+        SourceKey::Stream(self.quad)
+    }
+}
+
+impl Source for TcpStream {}
+
+impl sealed::Source for TcpListener {
+    fn handle(&self) -> &InterfaceHandle {
+        &self.h
+    }
+
+    fn key(&self) -> SourceKey {
+        SourceKey::Listener(self.port)
+    }
+}
+
+impl Source for TcpListener {}
+
+/// Waits for readiness on many streams and listeners from a single thread.
+///
+/// Readiness is edge-triggered: an event is delivered when a source becomes ready for one of its
+/// interests, and on registration if it already is. A source that stays ready is not reported
+/// again, so sources should be in non-blocking mode and be drained until they return
+/// `WouldBlock` before waiting again. A listener is readable when it has pending connections.
+#[derive(Default)]
+pub struct Poller {
+    shared: Arc<Shared>,
+}
+
+impl Poller {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Starts delivering events for `source` under `token`.
+    pub fn register<S: Source>(
+        &self,
+        source: &S,
+        token: Token,
+        interest: Available,
+    ) -> io::Result<()> {
+        self.shared.clock.get_or_init(|| {
+            let clock = source.handle().clock.clone();
+            if let Some(mock) = clock.as_mock() {
+                mock.attach_poller(Arc::downgrade(&self.shared));
+            }
+            clock
+        });
+
+        let registration = Registration {
+            poller: Arc::downgrade(&self.shared),
+            token,
+            interest,
+            ready: Available::empty(),
+        };
+        source.handle().call(|ack| InterfaceRequest::Register {
+            key: source.key(),
+            registration,
+            ack,
+        })
+    }
+
+    /// Changes the token or interest of an already registered `source`.
+    pub fn reregister<S: Source>(
+        &self,
+        source: &S,
+        token: Token,
+        interest: Available,
+    ) -> io::Result<()> {
+        self.deregister(source)?;
+        self.register(source, token, interest)
+    }
+
+    pub fn deregister<S: Source>(&self, source: &S) -> io::Result<()> {
+        let token = source.handle().call(|ack| InterfaceRequest::Deregister {
+            key: source.key(),
+            poller: Arc::downgrade(&self.shared),
+            ack,
+        })?;
+
+        // don't hand out events for a token that may be reused for another source
+        let mut ready = self.shared.ready.lock().unwrap();
+        if ready.readiness.remove(&token).is_some() {
+            ready.order.retain(|&t| t != token);
+        }
+
+        Ok(())
+    }
+
+    /// Waits until at least one registered source is ready or `timeout` expires, replacing the
+    /// contents of `events` with what was observed.
+    ///
+    /// The timeout follows the clock of the interface the first source was registered on, so
+    /// with a [`MockClock`](crate::MockClock) it expires once the clock has been advanced past
+    /// it.
+    pub fn wait(&self, events: &mut Vec<Event>, timeout: Option<Duration>) -> io::Result<usize> {
+        events.clear();
+
+        let clock = match self.shared.clock.get() {
+            Some(clock) => clock.clone(),
+            None => Arc::new(SystemClock),
+        };
+        let deadline = timeout.map(|t| clock.now() + t);
+        let mut ready = self.shared.ready.lock().unwrap();
+        while ready.order.is_empty() {
+            match deadline {
+                None => ready = self.shared.var.wait(ready).unwrap(),
+                Some(deadline) => {
+                    let now = clock.now();
+                    if now >= deadline {
+                        return Ok(0);
+                    }
+                    ready = match clock.as_mock() {
+                        // the clock wakes us when it is advanced
+                        Some(_) => self.shared.var.wait(ready).unwrap(),
+                        None => {
+                            self.shared
+                                .var
+                                .wait_timeout(ready, deadline - now)
+                                .unwrap()
+                                .0
+                        }
+                    };
+                }
+            }
+        }
+
+        let ready = &mut *ready;
+        events.extend(ready.order.drain(..).map(|token| Event {
+            token,
+            readiness: ready.readiness.remove(&token).unwrap_or(Available::empty()),
+        }));
+
+        Ok(events.len())
+    }
+}
+
+impl ConnectionManager {
+    pub(crate) fn register(
+        &mut self,
+        key: SourceKey,
+        mut registration: Registration,
+    ) -> io::Result<()> {
+        let readiness = self.readiness(key) & registration.interest;
+        registration.ready = readiness;
+        let registrations = self.registrations.entry(key).or_default();
+        if registrations
+            .iter()
+            .any(|r| r.poller.ptr_eq(&registration.poller))
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                "source already registered with this poller",
+            ));
+        }
+
+        if let Some(poller) = registration.poller.upgrade() {
+            if !readiness.is_empty() {
+                poller.push(registration.token, readiness);
+            }
+        }
+        registrations.push(registration);
+
+        Ok(())
+    }
+
+    /// Forget the registration of `key` with `poller`, returning the token it used.
+    pub(crate) fn deregister(
+        &mut self,
+        key: SourceKey,
+        poller: &Weak<Shared>,
+    ) -> io::Result<Token> {
+        let not_registered =
+            || io::Error::new(io::ErrorKind::NotFound, "source was not registered");
+        let registrations = self
+            .registrations
+            .get_mut(&key)
+            .ok_or_else(not_registered)?;
+
+        let i = registrations
+            .iter()
+            .position(|r| r.poller.ptr_eq(poller))
+            .ok_or_else(not_registered)?;
+        let registration = registrations.swap_remove(i);
+        if registrations.is_empty() {
+            self.registrations.remove(&key);
+        }
+
+        Ok(registration.token)
+    }
+
+    pub(crate) fn readiness(&self, key: SourceKey) -> Available {
+        match key {
+            SourceKey::Stream(quad) => self
+                .connections
+                .get(&quad)
+                .map(|c| c.conn.availability())
+                .unwrap_or(Available::READ | Available::WRITE),
+            SourceKey::Listener(port) => match self.pending.get(&port) {
+                Some(pending) if !pending.is_empty() => Available::READ,
+                _ => Available::empty(),
+            },
+        }
+    }
+
+    /// Tell every poller interested in `key` that it has become `readiness`, forgetting pollers
+    /// that have been dropped. Only interests that were not ready before are delivered.
+    pub(crate) fn notify_pollers(&mut self, key: SourceKey, readiness: Available) {
+        let Some(registrations) = self.registrations.get_mut(&key) else {
+            return;
+        };
+
+        registrations.retain_mut(|r| {
+            let Some(poller) = r.poller.upgrade() else {
+                return false;
+            };
+
+            let readiness = readiness & r.interest;
+            let edge = readiness & !r.ready;
+            r.ready = readiness;
+            if !edge.is_empty() {
+                poller.push(r.token, edge);
+            }
+            true
+        });
+
+        if registrations.is_empty() {
+            self.registrations.remove(&key);
+        }
+    }
+}
@@ -0,0 +1,182 @@
+use std::{
+    collections::VecDeque,
+    io::{self, Read, Write},
+    net::Ipv4Addr,
+    os::{
+        fd::{AsRawFd, RawFd},
+        unix::net::UnixStream,
+    },
+    sync::{Arc, Condvar, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    device::NetDevice,
+    pcap::{PcapReader, LINKTYPE_RAW},
+};
+
+#[derive(Default)]
+struct Log {
+    inbound: VecDeque<Vec<u8>>,
+    sent: Vec<Vec<u8>>,
+}
+
+#[derive(Default)]
+struct Shared {
+    log: Mutex<Log>,
+    sent: Condvar,
+}
+
+/// A device whose inbound packets come from the test rather than a link, and that records
+/// everything the stack transmits, so a captured exchange can be played back against the stack
+/// and its answers compared with what it sent the first time.
+///
+/// The answers can only match if the stack picks the same initial sequence numbers as in the
+/// capture, so both runs need a fixed
+/// [`initial_sequence`](crate::InterfaceBuilder::initial_sequence).
+///
+/// ```no_run
+/// use std::{fs::File, net::Ipv4Addr, time::Duration};
+///
+/// let addr = Ipv4Addr::new(10, 0, 0, 1);
+/// let (device, replay) = thunder::Replay::new()?;
+/// let mut iface = thunder::Interface::builder()
+///     .addr(addr, 32)
+///     .initial_sequence(0)
+///     .build_with_device(device)?;
+/// let _listener = iface.bind(7000)?;
+///
+/// let pcap = thunder::PcapReader::new(File::open("bug.pcap")?)?;
+/// let sent = replay.replay(pcap, addr, Duration::from_secs(1))?;
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub struct Replay {
+    shared: Arc<Shared>,
+    /// holds one byte per queued packet, so it is readable while there is something to receive
+    ready: UnixStream,
+}
+
+/// The test's side of a [`Replay`] device, which stays usable after the device has moved to
+/// the packet thread.
+pub struct ReplayHandle {
+    shared: Arc<Shared>,
+    ready: UnixStream,
+}
+
+impl Replay {
+    pub fn new() -> io::Result<(Replay, ReplayHandle)> {
+        let (a, b) = UnixStream::pair()?;
+        a.set_nonblocking(true)?;
+        let shared = Arc::new(Shared::default());
+        Ok((
+            Replay {
+                shared: shared.clone(),
+                ready: a,
+            },
+            ReplayHandle { shared, ready: b },
+        ))
+    }
+}
+
+impl AsRawFd for Replay {
+    fn as_raw_fd(&self) -> RawFd {
+        self.ready.as_raw_fd()
+    }
+}
+
+impl NetDevice for Replay {
+    fn send(&mut self, packet: &[u8]) -> io::Result<usize> {
+        self.shared.log.lock().unwrap().sent.push(packet.to_vec());
+        self.shared.sent.notify_all();
+        Ok(packet.len())
+    }
+
+    fn recv(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.ready.read(&mut [0u8]) {
+            Ok(1) => {}
+            Ok(_) => return Ok(0),
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(0),
+            Err(e) => return Err(e),
+        }
+
+        let Some(packet) = self.shared.log.lock().unwrap().inbound.pop_front() else {
+            return Ok(0);
+        };
+        let len = packet.len().min(buf.len());
+        buf[..len].copy_from_slice(&packet[..len]);
+        Ok(len)
+    }
+
+    fn mtu(&self) -> usize {
+        1500
+    }
+}
+
+impl ReplayHandle {
+    /// Hand `packet` to the stack as if it had arrived on the link.
+    pub fn inject(&self, packet: &[u8]) -> io::Result<()> {
+        self.shared
+            .log
+            .lock()
+            .unwrap()
+            .inbound
+            .push_back(packet.to_vec());
+        (&self.ready).write_all(&[0])
+    }
+
+    /// Every packet the stack has sent so far.
+    pub fn sent(&self) -> Vec<Vec<u8>> {
+        self.shared.log.lock().unwrap().sent.clone()
+    }
+
+    /// Wait until the stack has sent at least `count` packets, and return all of them.
+    pub fn wait_sent(&self, count: usize, timeout: Duration) -> io::Result<Vec<Vec<u8>>> {
+        let deadline = Instant::now() + timeout;
+        let mut log = self.shared.log.lock().unwrap();
+        while log.sent.len() < count {
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    format!("stack sent {} of {} packets", log.sent.len(), count),
+                ));
+            }
+            log = self.shared.sent.wait_timeout(log, left).unwrap().0;
+        }
+        Ok(log.sent.clone())
+    }
+
+    /// Play back the packets in `pcap` that were addressed to `addr`, and return what the stack
+    /// sent in response.
+    ///
+    /// Each packet is injected only once the stack has sent as many packets as `addr` had sent
+    /// by that point in the capture, so the stack sees the same interleaving as the original
+    /// host did; waiting for one of those packets gives up after `timeout`.
+    pub fn replay<R: Read>(
+        &self,
+        mut pcap: PcapReader<R>,
+        addr: Ipv4Addr,
+        timeout: Duration,
+    ) -> io::Result<Vec<Vec<u8>>> {
+        if pcap.link_type() != LINKTYPE_RAW {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "can only replay captures of bare IP packets",
+            ));
+        }
+
+        let mut expected = 0;
+        while let Some((_, packet)) = pcap.read_packet()? {
+            let Ok(iph) = etherparse::Ipv4HeaderSlice::from_slice(&packet) else {
+                continue;
+            };
+            if iph.destination_addr() == addr {
+                self.wait_sent(expected, timeout)?;
+                self.inject(&packet)?;
+            } else if iph.source_addr() == addr {
+                expected += 1;
+            }
+        }
+        self.wait_sent(expected, timeout)
+    }
+}
@@ -0,0 +1,195 @@
+use std::{
+    collections::VecDeque,
+    io,
+    sync::{mpsc, Arc, Mutex},
+    task::{Context, Poll, Waker},
+    time::Instant,
+};
+
+use crate::Quad;
+
+/// How long the packet thread may hold on to a request that cannot complete right away.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Wait {
+    /// fail with `WouldBlock` instead of waiting (non-blocking mode)
+    Never,
+    /// wait until the request completes, or fail with `WouldBlock` once the deadline passes
+    Until(Option<Instant>),
+}
+
+impl Wait {
+    /// Block, for at most `timeout` from `now` if one is given.
+    pub(crate) fn timeout(timeout: Option<std::time::Duration>, now: Instant) -> Self {
+        Wait::Until(timeout.map(|t| now + t))
+    }
+
+    pub(crate) fn deadline(&self) -> Option<Instant> {
+        match *self {
+            Wait::Never => None,
+            Wait::Until(deadline) => deadline,
+        }
+    }
+}
+
+/// Packet thread end of a request: delivers the result and wakes the task waiting for it.
+pub(crate) struct Completion<T> {
+    tx: mpsc::Sender<io::Result<T>>,
+    waker: Arc<Mutex<Option<Waker>>>,
+}
+
+impl<T> Completion<T> {
+    pub(crate) fn complete(self, result: io::Result<T>) {
+        let _ = self.send(result);
+    }
+
+    /// Complete the request with `value`, or hand it back if the requester stopped waiting
+    /// after [`is_abandoned`](Self::is_abandoned) was checked, so that it can go back where it
+    /// came from.
+    pub(crate) fn deliver(self, value: T) -> Result<(), T> {
+        self.send(Ok(value))
+            .map_err(|result| result.expect("sent a value"))
+    }
+
+    fn send(self, result: io::Result<T>) -> Result<(), io::Result<T>> {
+        self.tx.send(result).map_err(|e| e.0)?;
+        if let Some(waker) = self.waker.lock().unwrap().take() {
+            waker.wake();
+        }
+        Ok(())
+    }
+
+    /// Whether the requester has stopped waiting (a dropped future), in which case completing
+    /// the request would consume data nobody is going to see.
+    pub(crate) fn is_abandoned(&self) -> bool {
+        Arc::strong_count(&self.waker) == 1
+    }
+}
+
+/// Application end of a request.
+pub(crate) struct Reply<T> {
+    rx: mpsc::Receiver<io::Result<T>>,
+    waker: Arc<Mutex<Option<Waker>>>,
+}
+
+pub(crate) fn channel<T>() -> (Completion<T>, Reply<T>) {
+    let (tx, rx) = mpsc::channel();
+    let waker = Arc::default();
+    (
+        Completion {
+            tx,
+            waker: Arc::clone(&waker),
+        },
+        Reply { rx, waker },
+    )
+}
+
+fn interface_gone() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::ConnectionAborted,
+        "interface has been shut down",
+    )
+}
+
+impl<T> Reply<T> {
+    /// Block the calling thread until the packet thread answers.
+    pub(crate) fn wait(self) -> io::Result<T> {
+        self.rx.recv().map_err(|_| interface_gone())?
+    }
+
+    pub(crate) fn poll(&self, cx: &mut Context<'_>) -> Poll<io::Result<T>> {
+        // register before checking, so an answer sent in between still wakes us
+        *self.waker.lock().unwrap() = Some(cx.waker().clone());
+        match self.rx.try_recv() {
+            Ok(result) => Poll::Ready(result),
+            Err(mpsc::TryRecvError::Empty) => Poll::Pending,
+            Err(mpsc::TryRecvError::Disconnected) => Poll::Ready(Err(interface_gone())),
+        }
+    }
+}
+
+/// A request the packet thread is holding on to until it can complete.
+pub(crate) struct Parked<A, T> {
+    pub(crate) arg: A,
+    pub(crate) deadline: Option<Instant>,
+    pub(crate) completion: Completion<T>,
+}
+
+pub(crate) enum Outgoing {
+    Data(Vec<u8>),
+    Urgent(u8),
+}
+
+/// Requests blocked on a single connection.
+#[derive(Default)]
+pub(crate) struct Waiting {
+    /// `max_length` of each blocked read
+    pub(crate) readers: VecDeque<Parked<usize, Vec<u8>>>,
+    pub(crate) writers: VecDeque<Parked<Outgoing, usize>>,
+    pub(crate) flushers: VecDeque<Parked<(), ()>>,
+    /// the `connect` call that opened the connection, until the handshake is over
+    pub(crate) connectors: VecDeque<Parked<(), Quad>>,
+}
+
+impl Waiting {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.readers.is_empty()
+            && self.writers.is_empty()
+            && self.flushers.is_empty()
+            && self.connectors.is_empty()
+    }
+
+    pub(crate) fn deadlines(&self) -> impl Iterator<Item = Instant> + '_ {
+        let readers = self.readers.iter().filter_map(|p| p.deadline);
+        let writers = self.writers.iter().filter_map(|p| p.deadline);
+        let flushers = self.flushers.iter().filter_map(|p| p.deadline);
+        let connectors = self.connectors.iter().filter_map(|p| p.deadline);
+        readers.chain(writers).chain(flushers).chain(connectors)
+    }
+
+    /// Fail every request whose deadline has passed.
+    pub(crate) fn expire(&mut self, now: Instant) {
+        expire(&mut self.readers, now);
+        expire(&mut self.writers, now);
+        expire(&mut self.flushers, now);
+        expire(&mut self.connectors, now);
+    }
+
+    /// Fail every request, e.g. because the connection is gone.
+    pub(crate) fn abort(&mut self, e: impl Fn() -> io::Error) {
+        self.readers
+            .drain(..)
+            .for_each(|p| p.completion.complete(Err(e())));
+        self.writers
+            .drain(..)
+            .for_each(|p| p.completion.complete(Err(e())));
+        self.flushers
+            .drain(..)
+            .for_each(|p| p.completion.complete(Err(e())));
+        self.connectors
+            .drain(..)
+            .for_each(|p| p.completion.complete(Err(e())));
+    }
+}
+
+pub(crate) fn expire<A, T>(parked: &mut VecDeque<Parked<A, T>>, now: Instant) {
+    if parked
+        .iter()
+        .all(|p| p.deadline.is_none_or(|d| d > now) && !p.completion.is_abandoned())
+    {
+        return;
+    }
+
+    for p in std::mem::take(parked) {
+        if p.completion.is_abandoned() {
+            continue;
+        }
+        if p.deadline.is_some_and(|d| d <= now) {
+            p.completion.complete(Err(io::Error::new(
+                io::ErrorKind::WouldBlock,
+                "operation timed out",
+            )));
+        } else {
+            parked.push_back(p);
+        }
+    }
+}
@@ -0,0 +1,708 @@
+use std::{
+    collections::VecDeque,
+    fmt, io,
+    net::Ipv4Addr,
+    task::{Context, Poll, Waker},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    clock::Clock,
+    connection,
+    request::{self, Reply, Wait},
+    sim::{Outbox, SimClock},
+    ConnectionManager, InterfaceBuilder, InterfaceRequest, Quad,
+};
+
+/// MTU of the device a script runs against.
+const DEVICE_MTU: usize = 1500;
+/// The peer a script plays.
+const REMOTE: Ipv4Addr = Ipv4Addr::new(192, 0, 2, 1);
+/// Port the peer sends from when it connects to a listener.
+const REMOTE_PORT: u16 = 40000;
+/// Port inbound segments go to before any call has named one.
+const DEFAULT_PORT: u16 = 8080;
+/// How far off the time of an outbound segment may be, as in packetdrill.
+const DEFAULT_TOLERANCE: Duration = Duration::from_millis(4);
+
+/// A packetdrill-style test: segments the peer sends, segments the stack must answer with, and
+/// calls the application makes, each at a point in time.
+///
+/// ```text
+/// // the peer opens a connection
+/// 0     listen(8080) = 0
+/// +0    < S 0:0(0) win 1000
+/// +0    > S. 0:0(0) ack 1 win 1024
+/// +.1   < . 1:1(0) ack 1 win 1000
+/// +0    accept(8080) = 0
+///
+/// // data is acknowledged once the delayed ACK timer fires
+/// +0    < . 1:11(10) ack 1 win 1000
+/// +.04  > . 1:1(0) ack 11 win 1024
+/// +0    read(100) = 10
+/// ```
+///
+/// Each line starts with a time in seconds, either since the script started or, with a `+`, since
+/// the previous line. `<` lines are segments injected into the stack and `>` lines segments it
+/// must send, no more than [`tolerance`](Script::tolerance) away from the given time. Flags are
+/// `S`, `F`, `R`, `P` and `.` for ACK, followed by `start:end(length)` and optionally the
+/// acknowledgment number and window. The stack's sequence numbers are relative to its initial
+/// sequence number, the peer's are as written. The window of an outbound segment is only checked
+/// when the script gives one, as is its ACK number.
+///
+/// The calls are non-blocking and act on the one connection under test; the result after `=` is
+/// either a number or an errno name such as `EAGAIN`, and is not checked if left out:
+///
+/// - `listen(port)` binds a port, and `accept(port)` takes a connection from it
+/// - `connect(port)` starts opening a connection to the peer, and returns `EINPROGRESS`
+/// - `write(n)` writes `n` bytes and `read(n)` reads up to `n`, returning how many
+/// - `shutdown()` sends FIN, and `close()` also gives the connection up
+///
+/// Segments the stack sends that no `>` line expects fail the script, unless it ends first.
+#[derive(Debug, Clone)]
+pub struct Script {
+    lines: Vec<Line>,
+    tolerance: Duration,
+}
+
+#[derive(Debug, Clone)]
+struct Line {
+    number: usize,
+    /// since the script started
+    time: Duration,
+    action: Action,
+}
+
+#[derive(Debug, Clone)]
+enum Action {
+    Inbound(Segment),
+    Outbound(Segment),
+    Call {
+        call: Call,
+        expected: Option<Returned>,
+    },
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Call {
+    Listen(u16),
+    Accept(u16),
+    Connect(u16),
+    Write(usize),
+    Read(usize),
+    Shutdown,
+    Close,
+}
+
+/// What a call returned: a number, or the errno name of its error.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Returned {
+    Value(usize),
+    Errno(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Segment {
+    flags: Flags,
+    seq: u32,
+    len: u32,
+    ack: Option<u32>,
+    win: Option<u16>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Flags {
+    syn: bool,
+    fin: bool,
+    rst: bool,
+    psh: bool,
+    ack: bool,
+}
+
+fn invalid(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, msg.into())
+}
+
+impl Script {
+    /// Parse a script, failing on the first line that does not make sense.
+    pub fn parse(text: &str) -> io::Result<Self> {
+        let mut lines = Vec::new();
+        let mut time = Duration::ZERO;
+        for (i, line) in text.lines().enumerate() {
+            let line = line.split("//").next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let (at, action) = parse_line(line, time)
+                .map_err(|e| io::Error::new(e.kind(), format!("line {}: {e}", i + 1)))?;
+            time = at;
+            lines.push(Line {
+                number: i + 1,
+                time,
+                action,
+            });
+        }
+
+        Ok(Self {
+            lines,
+            tolerance: DEFAULT_TOLERANCE,
+        })
+    }
+
+    /// How far the time an outbound segment is sent at may be from the time the script gives.
+    pub fn tolerance(mut self, tolerance: Duration) -> Self {
+        self.tolerance = tolerance;
+        self
+    }
+
+    /// Run the script against a stack set up by `builder`, on virtual time; the device name and
+    /// clock are ignored. Fails with the number of the line the stack did not live up to.
+    pub fn run(&self, builder: InterfaceBuilder) -> io::Result<()> {
+        let mut run = Run::new(builder)?;
+        for line in &self.lines {
+            run.line(line, self.tolerance)
+                .map_err(|e| io::Error::new(e.kind(), format!("line {}: {e}", line.number)))?;
+        }
+
+        match run.sent.front() {
+            Some((at, packet)) => Err(io::Error::other(format!(
+                "after the last line: unexpected `{}` at {}",
+                run.describe(packet)?,
+                run.seconds(*at)
+            ))),
+            None => Ok(()),
+        }
+    }
+}
+
+fn parse_line(line: &str, previous: Duration) -> io::Result<(Duration, Action)> {
+    let (time, rest) = line
+        .split_once(char::is_whitespace)
+        .ok_or_else(|| invalid("expected a time and an action"))?;
+    let (relative, time) = match time.strip_prefix('+') {
+        Some(time) => (true, time),
+        None => (false, time),
+    };
+    let time = time
+        .parse::<f64>()
+        .ok()
+        .and_then(|t| Duration::try_from_secs_f64(t).ok())
+        .ok_or_else(|| invalid(format!("bad time `{time}`")))?;
+    let at = if relative { previous + time } else { time };
+    if at < previous {
+        return Err(invalid("time goes backwards"));
+    }
+
+    let rest = rest.trim_start();
+    let action = if let Some(segment) = rest.strip_prefix('<') {
+        let segment = Segment::parse(segment)?;
+        if segment.flags.ack != segment.ack.is_some() {
+            return Err(invalid(
+                "an inbound segment with the ACK flag needs an ACK number",
+            ));
+        }
+        Action::Inbound(segment)
+    } else if let Some(segment) = rest.strip_prefix('>') {
+        Action::Outbound(Segment::parse(segment)?)
+    } else {
+        let (call, expected) = parse_call(rest)?;
+        Action::Call { call, expected }
+    };
+
+    Ok((at, action))
+}
+
+fn parse_call(text: &str) -> io::Result<(Call, Option<Returned>)> {
+    let (name, rest) = text
+        .split_once('(')
+        .ok_or_else(|| invalid(format!("unknown action `{text}`")))?;
+    let (args, rest) = rest.split_once(')').ok_or_else(|| invalid("missing `)`"))?;
+    let args = args.trim();
+    let number = || -> io::Result<usize> {
+        args.parse()
+            .map_err(|_| invalid(format!("`{name}` takes a number, not `{args}`")))
+    };
+    let port = || -> io::Result<u16> {
+        args.parse()
+            .map_err(|_| invalid(format!("`{name}` takes a port, not `{args}`")))
+    };
+    let none = |call| {
+        if args.is_empty() {
+            Ok(call)
+        } else {
+            Err(invalid(format!("`{name}` takes no arguments")))
+        }
+    };
+
+    let call = match name.trim() {
+        "listen" => Call::Listen(port()?),
+        "accept" => Call::Accept(port()?),
+        "connect" => Call::Connect(port()?),
+        "write" => Call::Write(number()?),
+        "read" => Call::Read(number()?),
+        "shutdown" => none(Call::Shutdown)?,
+        "close" => none(Call::Close)?,
+        name => return Err(invalid(format!("unknown call `{name}`"))),
+    };
+
+    let rest = rest.trim();
+    let expected = if rest.is_empty() {
+        None
+    } else {
+        let returned = rest
+            .strip_prefix('=')
+            .ok_or_else(|| invalid(format!("expected `= result`, not `{rest}`")))?
+            .trim();
+        Some(match returned.parse() {
+            Ok(n) => Returned::Value(n),
+            Err(_) if returned.starts_with('E') && returned.chars().all(char::is_alphanumeric) => {
+                Returned::Errno(returned.to_string())
+            }
+            Err(_) => return Err(invalid(format!("bad result `{returned}`"))),
+        })
+    };
+
+    Ok((call, expected))
+}
+
+impl Segment {
+    /// Parse e.g. `S. 0:0(0) ack 1 win 1024`.
+    fn parse(text: &str) -> io::Result<Self> {
+        let mut words = text.split_whitespace();
+        let flags = Flags::parse(words.next().ok_or_else(|| invalid("missing flags"))?)?;
+
+        let range = words
+            .next()
+            .ok_or_else(|| invalid("missing sequence numbers"))?;
+        let bad_range = || invalid(format!("expected `start:end(length)`, not `{range}`"));
+        let (start, rest) = range.split_once(':').ok_or_else(bad_range)?;
+        let (end, len) = rest
+            .strip_suffix(')')
+            .and_then(|rest| rest.split_once('('))
+            .ok_or_else(bad_range)?;
+        let [seq, end, len] = [start, end, len].map(|n| n.parse::<u32>());
+        let (Ok(seq), Ok(end), Ok(len)) = (seq, end, len) else {
+            return Err(bad_range());
+        };
+        if end.wrapping_sub(seq) != len {
+            return Err(invalid(format!("`{range}` does not add up")));
+        }
+
+        let mut segment = Segment {
+            flags,
+            seq,
+            len,
+            ack: None,
+            win: None,
+        };
+        while let Some(word) = words.next() {
+            let value = words.next();
+            match word {
+                "ack" => {
+                    segment.ack = Some(
+                        value
+                            .and_then(|v| v.parse().ok())
+                            .ok_or_else(|| invalid("`ack` takes a number"))?,
+                    )
+                }
+                "win" => {
+                    segment.win = Some(
+                        value
+                            .and_then(|v| v.parse().ok())
+                            .ok_or_else(|| invalid("`win` takes a number up to 65535"))?,
+                    )
+                }
+                word if word.starts_with('<') => {
+                    return Err(invalid("TCP options are not supported"))
+                }
+                word => return Err(invalid(format!("unexpected `{word}`"))),
+            }
+        }
+        if segment.ack.is_some() && !flags.ack {
+            return Err(invalid("an ACK number without the ACK flag"));
+        }
+
+        Ok(segment)
+    }
+
+    /// Whether `actual` is what this segment asks for.
+    fn matches(&self, actual: &Segment) -> bool {
+        self.flags == actual.flags
+            && self.seq == actual.seq
+            && self.len == actual.len
+            && self.ack.is_none_or(|ack| actual.ack == Some(ack))
+            && self.win.is_none_or(|win| actual.win == Some(win))
+    }
+}
+
+impl fmt::Display for Segment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let end = self.seq.wrapping_add(self.len);
+        write!(f, "{} {}:{}({})", self.flags, self.seq, end, self.len)?;
+        if let Some(ack) = self.ack {
+            write!(f, " ack {ack}")?;
+        }
+        if let Some(win) = self.win {
+            write!(f, " win {win}")?;
+        }
+        Ok(())
+    }
+}
+
+impl Flags {
+    fn parse(text: &str) -> io::Result<Self> {
+        let mut flags = Flags {
+            syn: false,
+            fin: false,
+            rst: false,
+            psh: false,
+            ack: false,
+        };
+        for c in text.chars() {
+            let flag = match c {
+                'S' => &mut flags.syn,
+                'F' => &mut flags.fin,
+                'R' => &mut flags.rst,
+                'P' => &mut flags.psh,
+                '.' => &mut flags.ack,
+                c => return Err(invalid(format!("unknown flag `{c}`"))),
+            };
+            if std::mem::replace(flag, true) {
+                return Err(invalid(format!("flag `{c}` given twice")));
+            }
+        }
+
+        Ok(flags)
+    }
+}
+
+impl fmt::Display for Flags {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (set, c) in [
+            (self.syn, 'S'),
+            (self.fin, 'F'),
+            (self.rst, 'R'),
+            (self.psh, 'P'),
+            (self.ack, '.'),
+        ] {
+            if set {
+                write!(f, "{c}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Returned {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Returned::Value(n) => write!(f, "{n}"),
+            Returned::Errno(name) => f.write_str(name),
+        }
+    }
+}
+
+fn errno(e: &io::Error) -> &'static str {
+    match e.kind() {
+        io::ErrorKind::WouldBlock => "EAGAIN",
+        io::ErrorKind::NotConnected => "ENOTCONN",
+        io::ErrorKind::ConnectionRefused => "ECONNREFUSED",
+        io::ErrorKind::ConnectionReset => "ECONNRESET",
+        io::ErrorKind::ConnectionAborted => "ECONNABORTED",
+        io::ErrorKind::BrokenPipe => "EPIPE",
+        io::ErrorKind::TimedOut => "ETIMEDOUT",
+        io::ErrorKind::AddrInUse => "EADDRINUSE",
+        io::ErrorKind::AddrNotAvailable => "EADDRNOTAVAIL",
+        io::ErrorKind::InvalidInput => "EINVAL",
+        _ => "EIO",
+    }
+}
+
+/// A stack being put through a script, with the packet thread's work done inline.
+struct Run {
+    addr: Ipv4Addr,
+    cm: ConnectionManager,
+    device: Outbox,
+    clock: SimClock,
+    start: Instant,
+    /// what the stack has sent and no `>` line has matched yet, with when it was sent
+    sent: VecDeque<(Instant, Vec<u8>)>,
+    /// the stack's initial sequence number, once it has sent a SYN
+    iss: Option<u32>,
+    local_port: u16,
+    remote_port: u16,
+    /// the connection the calls act on
+    quad: Option<Quad>,
+    /// kept so the stack does not take the connect call for abandoned
+    connecting: Option<Reply<Quad>>,
+}
+
+impl Run {
+    fn new(builder: InterfaceBuilder) -> io::Result<Self> {
+        let (addr, mut config) = builder.finish(DEVICE_MTU)?;
+        let start = Instant::now();
+        let clock = SimClock::new(start);
+        config.clock = std::sync::Arc::new(clock.clone());
+        let mtu = config.mtu;
+
+        Ok(Self {
+            addr,
+            cm: ConnectionManager::new(addr, config, Default::default()),
+            device: Outbox {
+                packets: Vec::new(),
+                mtu,
+            },
+            clock,
+            start,
+            sent: VecDeque::new(),
+            iss: None,
+            local_port: DEFAULT_PORT,
+            remote_port: REMOTE_PORT,
+            quad: None,
+            connecting: None,
+        })
+    }
+
+    fn line(&mut self, line: &Line, tolerance: Duration) -> io::Result<()> {
+        let at = self.start + line.time;
+        self.advance(at, false)?;
+
+        if let Action::Outbound(expected) = &line.action {
+            if self.sent.is_empty() {
+                self.advance(at + tolerance, true)?;
+            }
+            let Some((sent_at, packet)) = self.sent.pop_front() else {
+                return Err(io::Error::other(format!(
+                    "expected `{expected}` at {}, but nothing was sent",
+                    self.seconds(at)
+                )));
+            };
+            let actual = self.segment(&packet)?;
+            let off = sent_at.max(at) - sent_at.min(at);
+            if !expected.matches(&actual) || off > tolerance {
+                return Err(io::Error::other(format!(
+                    "expected `{expected}` at {}, but `{actual}` was sent at {}",
+                    self.seconds(at),
+                    self.seconds(sent_at)
+                )));
+            }
+            return Ok(());
+        }
+
+        if let Some((sent_at, packet)) = self.sent.front() {
+            if *sent_at + tolerance < at {
+                return Err(io::Error::other(format!(
+                    "unexpected `{}` at {}",
+                    self.describe(packet)?,
+                    self.seconds(*sent_at)
+                )));
+            }
+        }
+
+        match &line.action {
+            Action::Inbound(segment) => {
+                let packet = self.build(segment)?;
+                self.cm.on_datagram(&mut self.device, &packet)?;
+            }
+            Action::Call { call, expected } => {
+                let returned = self.call(*call)?;
+                if let Some(expected) = expected.as_ref().filter(|&e| *e != returned) {
+                    return Err(io::Error::other(format!(
+                        "expected {expected}, but the call returned {returned}"
+                    )));
+                }
+            }
+            Action::Outbound(_) => unreachable!("handled above"),
+        }
+        self.collect();
+
+        Ok(())
+    }
+
+    /// Move the clock to `to`, firing timers as they come due; with `until_sent`, stop early once
+    /// the stack has sent something.
+    fn advance(&mut self, to: Instant, until_sent: bool) -> io::Result<()> {
+        while !until_sent || self.sent.is_empty() {
+            let Some(at) = self.cm.timers.next_deadline().filter(|&at| at <= to) else {
+                self.clock.set(to.max(self.clock.now()));
+                break;
+            };
+            let now = at.max(self.clock.now());
+            self.clock.set(now);
+            while let Some(timer) = self.cm.timers.pop_expired(now) {
+                self.cm.on_timer(&mut self.device, timer, now)?;
+            }
+            self.collect();
+        }
+
+        Ok(())
+    }
+
+    /// Take what the stack has sent from the device.
+    fn collect(&mut self) {
+        let now = self.clock.now();
+        for packet in std::mem::take(&mut self.device.packets) {
+            if let Ok((_, tcph, _)) = connection::parse(&packet) {
+                if tcph.syn() {
+                    self.iss = Some(tcph.sequence_number());
+                    if !tcph.ack() {
+                        self.local_port = tcph.source_port();
+                    }
+                }
+            }
+            self.sent.push_back((now, packet));
+        }
+    }
+
+    fn call(&mut self, call: Call) -> io::Result<Returned> {
+        let quad = self.quad.unwrap_or(Quad {
+            src: (REMOTE, self.remote_port),
+            dst: (self.addr, self.local_port),
+        });
+        let result = match call {
+            Call::Listen(port) => {
+                self.local_port = port;
+                self.remote_port = REMOTE_PORT;
+                self.request(|ack| InterfaceRequest::Bind { port, ack })?
+                    .map(|()| 0)
+            }
+            Call::Accept(port) => {
+                let accepted = self.request(|read| InterfaceRequest::Accept {
+                    port,
+                    wait: Wait::Never,
+                    read,
+                })?;
+                accepted.map(|quad| {
+                    self.quad = Some(quad);
+                    0
+                })
+            }
+            Call::Connect(port) => {
+                self.remote_port = port;
+                let (ack, reply) = request::channel();
+                self.cm.handle(
+                    &mut self.device,
+                    InterfaceRequest::Connect {
+                        remote: (REMOTE, port),
+                        wait: Wait::Until(None),
+                        ack,
+                    },
+                )?;
+                match poll(&reply) {
+                    Poll::Ready(Err(e)) => Err(e),
+                    Poll::Ready(Ok(quad)) => {
+                        self.quad = Some(quad);
+                        Ok(0)
+                    }
+                    Poll::Pending => {
+                        self.connecting = Some(reply);
+                        self.collect();
+                        self.quad = Some(Quad {
+                            src: (REMOTE, port),
+                            dst: (self.addr, self.local_port),
+                        });
+                        return Ok(Returned::Errno("EINPROGRESS".to_string()));
+                    }
+                }
+            }
+            Call::Write(n) => self.request(|ack| InterfaceRequest::Write {
+                quad,
+                bytes: vec![0; n],
+                wait: Wait::Never,
+                ack,
+            })?,
+            Call::Read(n) => self
+                .request(|read| InterfaceRequest::Read {
+                    quad,
+                    max_length: n,
+                    wait: Wait::Never,
+                    read,
+                })?
+                .map(|data| data.len()),
+            Call::Shutdown => self
+                .request(|ack| InterfaceRequest::Shutdown { quad, ack })?
+                .map(|()| 0),
+            Call::Close => {
+                self.quad = None;
+                self.cm
+                    .handle(&mut self.device, InterfaceRequest::Close { quad })?;
+                Ok(0)
+            }
+        };
+
+        Ok(match result {
+            Ok(n) => Returned::Value(n),
+            Err(e) => Returned::Errno(errno(&e).to_string()),
+        })
+    }
+
+    /// Hand the stack a request that must be answered right away.
+    fn request<T>(
+        &mut self,
+        request: impl FnOnce(request::Completion<T>) -> InterfaceRequest,
+    ) -> io::Result<io::Result<T>> {
+        let (completion, reply) = request::channel();
+        self.cm.handle(&mut self.device, request(completion))?;
+        match poll(&reply) {
+            Poll::Ready(result) => Ok(result),
+            Poll::Pending => Err(io::Error::other("the call did not return right away")),
+        }
+    }
+
+    /// An inbound segment from the peer, with its ACK number taken out of relative terms.
+    fn build(&self, segment: &Segment) -> io::Result<Vec<u8>> {
+        let mut tcp = etherparse::TcpHeader::new(
+            self.remote_port,
+            self.local_port,
+            segment.seq,
+            segment.win.unwrap_or(u16::MAX),
+        );
+        tcp.syn = segment.flags.syn;
+        tcp.fin = segment.flags.fin;
+        tcp.rst = segment.flags.rst;
+        tcp.psh = segment.flags.psh;
+        if let Some(ack) = segment.ack {
+            tcp.ack = true;
+            tcp.acknowledgment_number = ack.wrapping_add(self.iss.unwrap_or(0));
+        }
+
+        let mut packet = Vec::new();
+        etherparse::PacketBuilder::ipv4(REMOTE.octets(), self.addr.octets(), 64)
+            .tcp_header(tcp)
+            .write(&mut packet, &vec![0; segment.len as usize])
+            .map_err(io::Error::other)?;
+        Ok(packet)
+    }
+
+    /// An outbound segment, with its sequence number relative to the stack's ISN, as the
+    /// stack itself would parse it.
+    fn segment(&self, packet: &[u8]) -> io::Result<Segment> {
+        let (_, tcph, data) = connection::parse(packet)?;
+        Ok(Segment {
+            flags: Flags {
+                syn: tcph.syn(),
+                fin: tcph.fin(),
+                rst: tcph.rst(),
+                psh: tcph.psh(),
+                ack: tcph.ack(),
+            },
+            seq: tcph.sequence_number().wrapping_sub(self.iss.unwrap_or(0)),
+            len: data.len() as u32,
+            ack: tcph.ack().then_some(tcph.acknowledgment_number()),
+            win: Some(tcph.window_size()),
+        })
+    }
+
+    fn describe(&self, packet: &[u8]) -> io::Result<String> {
+        Ok(self.segment(packet)?.to_string())
+    }
+
+    fn seconds(&self, at: Instant) -> String {
+        format!("{:.3}s", (at - self.start).as_secs_f64())
+    }
+}
+
+fn poll<T>(reply: &Reply<T>) -> Poll<io::Result<T>> {
+    reply.poll(&mut Context::from_waker(Waker::noop()))
+}
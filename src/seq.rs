@@ -0,0 +1,105 @@
+use std::{
+    fmt,
+    ops::{Add, AddAssign, Sub, SubAssign},
+};
+
+/// A TCP sequence number.
+///
+/// Sequence numbers live on a circle of 2^32 and wrap, so they are compared with serial number
+/// arithmetic (RFC 1982): `a` is before `b` when `b` is less than 2^31 ahead of `a`. That order is
+/// not transitive, which is why there is no `PartialOrd`; use [`is_before`](Self::is_before) and
+/// friends instead. Two numbers exactly 2^31 apart are neither before nor after each other.
+///
+/// ```
+/// use thunder::SeqNum;
+///
+/// let a = SeqNum::new(u32::MAX - 1);
+/// let b = a + 10;
+/// assert!(a.is_before(b));
+/// assert_eq!(b.get(), 8);
+/// assert_eq!(b - a, 10);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct SeqNum(u32);
+
+impl SeqNum {
+    pub const fn new(n: u32) -> Self {
+        Self(n)
+    }
+
+    /// The number as it goes on the wire.
+    pub const fn get(self) -> u32 {
+        self.0
+    }
+
+    /// Whether `self` comes strictly before `other`.
+    pub fn is_before(self, other: SeqNum) -> bool {
+        let ahead = other.0.wrapping_sub(self.0);
+        ahead != 0 && ahead < 1 << 31
+    }
+
+    /// Whether `self` comes strictly after `other`.
+    pub fn is_after(self, other: SeqNum) -> bool {
+        other.is_before(self)
+    }
+
+    /// Whether `self` lies strictly between `start` and `end`.
+    pub fn is_between(self, start: SeqNum, end: SeqNum) -> bool {
+        start.is_before(self) && self.is_before(end)
+    }
+}
+
+impl Add<u32> for SeqNum {
+    type Output = SeqNum;
+
+    fn add(self, n: u32) -> SeqNum {
+        SeqNum(self.0.wrapping_add(n))
+    }
+}
+
+impl AddAssign<u32> for SeqNum {
+    fn add_assign(&mut self, n: u32) {
+        *self = *self + n;
+    }
+}
+
+impl Sub<u32> for SeqNum {
+    type Output = SeqNum;
+
+    fn sub(self, n: u32) -> SeqNum {
+        SeqNum(self.0.wrapping_sub(n))
+    }
+}
+
+impl SubAssign<u32> for SeqNum {
+    fn sub_assign(&mut self, n: u32) {
+        *self = *self - n;
+    }
+}
+
+/// How far `self` is ahead of `rhs`, going forwards round the circle.
+impl Sub for SeqNum {
+    type Output = u32;
+
+    fn sub(self, rhs: SeqNum) -> u32 {
+        self.0.wrapping_sub(rhs.0)
+    }
+}
+
+impl From<u32> for SeqNum {
+    fn from(n: u32) -> Self {
+        Self(n)
+    }
+}
+
+impl From<SeqNum> for u32 {
+    fn from(n: SeqNum) -> Self {
+        n.0
+    }
+}
+
+impl fmt::Display for SeqNum {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
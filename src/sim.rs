@@ -0,0 +1,708 @@
+use std::{
+    cmp::{Ordering, Reverse},
+    collections::{BTreeSet, BinaryHeap, HashMap, VecDeque},
+    future::{poll_fn, Future},
+    io::{self, Read},
+    net::{Ipv4Addr, SocketAddrV4},
+    os::{
+        fd::{AsRawFd, RawFd},
+        unix::net::UnixStream,
+    },
+    pin::{pin, Pin},
+    sync::{
+        atomic::{self, AtomicBool},
+        mpsc, Arc, Mutex,
+    },
+    task::{Context, Poll, Wake, Waker},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    clock::Clock, request::Wait, rng::Rng, tcp_listener::TcpListener, tcp_stream::TcpStream,
+    ConnectionManager, Foobar, InterfaceBuilder, InterfaceHandle, InterfaceRequest, NetDevice,
+};
+
+/// MTU of a host's device in a simulation; [`InterfaceBuilder::mtu`] can lower it.
+const DEVICE_MTU: usize = 1500;
+/// Packets a link queues up by default while it is busy sending.
+const DEFAULT_QUEUE: usize = 100;
+
+/// One direction of a connection between two nodes of a [`Simulator`]: packets wait in a
+/// queue of limited length until the link is free to send them, take their size over the
+/// bandwidth to send, and arrive after the latency.
+#[derive(Debug, Clone)]
+pub struct Link {
+    /// bits per second
+    bandwidth: u64,
+    latency: Duration,
+    /// packets waiting to be sent, beyond which new ones are dropped
+    queue: usize,
+    loss: f64,
+}
+
+impl Link {
+    /// A link sending `bandwidth` bits per second, whose packets arrive `latency` after they
+    /// have been sent.
+    pub fn new(bandwidth: u64, latency: Duration) -> Self {
+        Self {
+            bandwidth,
+            latency,
+            queue: DEFAULT_QUEUE,
+            loss: 0.0,
+        }
+    }
+
+    /// Drop packets that arrive while `packets` are already waiting to be sent.
+    pub fn queue(mut self, packets: usize) -> Self {
+        self.queue = packets;
+        self
+    }
+
+    /// Lose packets on the wire with probability `p`, after they have been sent.
+    pub fn loss(mut self, p: f64) -> Self {
+        self.loss = p;
+        self
+    }
+
+    fn check(&self) -> io::Result<()> {
+        let invalid = |msg| Err(io::Error::new(io::ErrorKind::InvalidInput, msg));
+
+        if self.bandwidth == 0 {
+            return invalid("link has no bandwidth");
+        }
+        if !(0.0..=1.0).contains(&self.loss) {
+            return invalid("probability is not between 0 and 1");
+        }
+
+        Ok(())
+    }
+
+    /// How long sending `bytes` keeps the link busy.
+    fn transmission(&self, bytes: usize) -> Duration {
+        let nanos = bytes as u128 * 8 * 1_000_000_000 / self.bandwidth as u128;
+        Duration::from_nanos(nanos.min(u64::MAX as u128) as u64)
+    }
+}
+
+/// What happened on one direction of a link so far.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LinkStats {
+    /// packets that made it across
+    pub delivered: u64,
+    pub bytes: u64,
+    /// packets dropped because the queue was full
+    pub dropped: u64,
+    /// packets lost on the wire
+    pub lost: u64,
+}
+
+/// A node of a [`Simulator`], to connect with [`Simulator::link`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct NodeId(usize);
+
+/// A stack in a [`Simulator`], to open connections from inside its tasks.
+///
+/// The streams and listeners it hands out work as usual, except that only their async
+/// methods can be used: anything that would block fails with `Unsupported`, since the
+/// simulation only moves on while tasks are waiting.
+#[derive(Clone)]
+pub struct Host {
+    id: NodeId,
+    addr: Ipv4Addr,
+    ih: InterfaceHandle,
+}
+
+impl Host {
+    pub fn id(&self) -> NodeId {
+        self.id
+    }
+
+    pub fn addr(&self) -> Ipv4Addr {
+        self.addr
+    }
+
+    pub async fn bind(&self, port: u16) -> io::Result<TcpListener> {
+        let reply = self.ih.start(|ack| InterfaceRequest::Bind { port, ack })?;
+        poll_fn(|cx| reply.poll(cx)).await?;
+        Ok(TcpListener {
+            port,
+            h: self.ih.clone(),
+            nonblocking: false,
+            accept_timeout: None,
+        })
+    }
+
+    /// Open a connection to `addr`, resolving once the handshake is over.
+    pub async fn connect(&self, addr: SocketAddrV4) -> io::Result<TcpStream> {
+        let reply = self.ih.start(|ack| InterfaceRequest::Connect {
+            remote: (*addr.ip(), addr.port()),
+            wait: Wait::Until(None),
+            ack,
+        })?;
+        let quad = poll_fn(|cx| reply.poll(cx)).await?;
+        Ok(TcpStream::new(quad, self.ih.clone()))
+    }
+}
+
+/// The time inside a simulation, which only moves when the simulator gets to the next event.
+#[derive(Clone)]
+pub(crate) struct SimClock(Arc<Mutex<Instant>>);
+
+impl SimClock {
+    pub(crate) fn new(start: Instant) -> Self {
+        Self(Arc::new(Mutex::new(start)))
+    }
+
+    pub(crate) fn set(&self, at: Instant) {
+        *self.0.lock().unwrap() = at;
+    }
+}
+
+impl Clock for SimClock {
+    fn now(&self) -> Instant {
+        *self.0.lock().unwrap()
+    }
+}
+
+/// The device of a simulated host, which holds on to what the stack sends until the simulator
+/// puts it on a link.
+pub(crate) struct Outbox {
+    pub(crate) packets: Vec<Vec<u8>>,
+    pub(crate) mtu: usize,
+}
+
+impl AsRawFd for Outbox {
+    /// Never polled: the simulator hands packets to the stack itself.
+    fn as_raw_fd(&self) -> RawFd {
+        -1
+    }
+}
+
+impl NetDevice for Outbox {
+    fn send(&mut self, packet: &[u8]) -> io::Result<usize> {
+        self.packets.push(packet.to_vec());
+        Ok(packet.len())
+    }
+
+    fn recv(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+        Err(io::ErrorKind::WouldBlock.into())
+    }
+
+    fn mtu(&self) -> usize {
+        self.mtu
+    }
+}
+
+/// Everything the packet thread would own for an interface.
+struct Stack {
+    addr: Ipv4Addr,
+    cm: ConnectionManager,
+    device: Outbox,
+    requests: mpsc::Receiver<InterfaceRequest>,
+    /// only drained, since the simulator checks for requests whenever a task has run
+    wake: UnixStream,
+    /// when the earliest timer event scheduled for the stack is due
+    timer: Option<Instant>,
+}
+
+enum Node {
+    Host(Box<Stack>),
+    /// forwards packets towards their destination
+    Router,
+}
+
+/// One direction of a link, and the packets it is busy with.
+struct Channel {
+    from: usize,
+    to: usize,
+    link: Link,
+    /// when each packet in the queue will have been sent, in order
+    queue: VecDeque<Instant>,
+    stats: LinkStats,
+}
+
+enum Event {
+    Arrival { node: usize, packet: Vec<u8> },
+    Timer { node: usize },
+}
+
+/// an event due at `at`; `id` keeps events due at the same time in order
+struct Scheduled {
+    at: Instant,
+    id: u64,
+    event: Event,
+}
+
+impl PartialEq for Scheduled {
+    fn eq(&self, other: &Self) -> bool {
+        (self.at, self.id) == (other.at, other.id)
+    }
+}
+
+impl Eq for Scheduled {}
+
+impl PartialOrd for Scheduled {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Scheduled {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.at, self.id).cmp(&(other.at, other.id))
+    }
+}
+
+struct Task {
+    future: Pin<Box<dyn Future<Output = ()>>>,
+    waker: Waker,
+}
+
+/// Marks its task ready to be polled again.
+struct TaskWaker {
+    id: usize,
+    ready: Arc<Mutex<BTreeSet<usize>>>,
+}
+
+/// Wakes up the future a [`Simulator::block_on`] call is waiting for.
+struct Woken(AtomicBool);
+
+impl Wake for Woken {
+    fn wake(self: Arc<Self>) {
+        self.0.store(true, atomic::Ordering::SeqCst);
+    }
+}
+
+impl Wake for TaskWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.ready.lock().unwrap().insert(self.id);
+    }
+}
+
+/// Runs stacks on virtual time, connected by [`Link`]s, all on the calling thread: time jumps
+/// straight to the next packet arrival or timer, so long transfers take as long as the stacks
+/// need to handle their packets, and a run is the same every time for the same seed.
+///
+/// ```
+/// use std::{net::{Ipv4Addr, SocketAddrV4}, time::Duration};
+/// use thunder::{Interface, Link, Simulator};
+///
+/// let mut sim = Simulator::new(42);
+/// let server = sim.add_host(Interface::builder().addr(Ipv4Addr::new(10, 0, 0, 1), 24))?;
+/// let client = sim.add_host(Interface::builder().addr(Ipv4Addr::new(10, 0, 0, 2), 24))?;
+/// sim.link(server.id(), client.id(), Link::new(10_000_000, Duration::from_millis(5)))?;
+///
+/// let mut listener = sim.block_on(server.bind(7000))??;
+/// sim.spawn(async move {
+///     let mut stream = listener.accept_async().await.unwrap();
+///     stream.write_async(b"hello").await.unwrap();
+/// });
+/// let mut stream = sim.block_on(client.connect(SocketAddrV4::new(server.addr(), 7000)))??;
+/// let mut buf = [0u8; 5];
+/// assert_eq!(sim.block_on(stream.read_async(&mut buf))??, 5);
+/// assert_eq!(&buf, b"hello");
+/// # Ok::<(), std::io::Error>(())
+/// ```
+///
+/// Applications run as tasks, which use the async methods of streams and listeners.
+pub struct Simulator {
+    seed: u64,
+    rng: Rng,
+    clock: SimClock,
+    start: Instant,
+    nodes: Vec<Node>,
+    channels: Vec<Channel>,
+    /// the channel to send packets from a node to an address on, if there is a way there
+    routes: HashMap<(usize, Ipv4Addr), Option<usize>>,
+    events: BinaryHeap<Reverse<Scheduled>>,
+    next_event: u64,
+    tasks: Vec<Option<Task>>,
+    ready: Arc<Mutex<BTreeSet<usize>>>,
+}
+
+impl Simulator {
+    /// An empty network, whose random decisions all follow from `seed`.
+    pub fn new(seed: u64) -> Self {
+        let start = Instant::now();
+        Self {
+            seed,
+            rng: Rng::new(seed),
+            clock: SimClock::new(start),
+            start,
+            nodes: Vec::new(),
+            channels: Vec::new(),
+            routes: HashMap::new(),
+            events: BinaryHeap::new(),
+            next_event: 0,
+            tasks: Vec::new(),
+            ready: Arc::default(),
+        }
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Virtual time since the simulation started.
+    pub fn elapsed(&self) -> Duration {
+        self.clock.now() - self.start
+    }
+
+    /// Add a stack set up by `builder`; the device name, host address and clock are ignored.
+    pub fn add_host(&mut self, builder: InterfaceBuilder) -> io::Result<Host> {
+        // sequence numbers have to repeat along with everything else
+        let (addr, mut config) = builder
+            .default_isn_seed(self.seed.wrapping_add(self.nodes.len() as u64))
+            .finish(DEVICE_MTU)?;
+        if self.host(addr).is_some() {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                "another host has this address",
+            ));
+        }
+        config.clock = Arc::new(self.clock.clone());
+
+        let (requests, rx) = mpsc::channel();
+        let (wake, wake_rx) = UnixStream::pair()?;
+        wake.set_nonblocking(true)?;
+        wake_rx.set_nonblocking(true)?;
+        let ih: InterfaceHandle = Arc::new(Foobar {
+            requests,
+            wake,
+            clock: config.clock.clone(),
+            simulated: true,
+        });
+
+        let id = NodeId(self.nodes.len());
+        let mtu = config.mtu;
+        self.nodes.push(Node::Host(Box::new(Stack {
+            addr,
+            cm: ConnectionManager::new(addr, config, Default::default()),
+            device: Outbox {
+                packets: Vec::new(),
+                mtu,
+            },
+            requests: rx,
+            wake: wake_rx,
+            timer: None,
+        })));
+        self.routes.clear();
+
+        Ok(Host { id, addr, ih })
+    }
+
+    /// Add a router, which forwards packets along the shortest path to their destination.
+    pub fn add_router(&mut self) -> NodeId {
+        self.nodes.push(Node::Router);
+        self.routes.clear();
+        NodeId(self.nodes.len() - 1)
+    }
+
+    /// Connect two nodes by a link, with the same characteristics both ways.
+    pub fn link(&mut self, a: NodeId, b: NodeId, link: Link) -> io::Result<()> {
+        link.check()?;
+        if a == b || a.0 >= self.nodes.len() || b.0 >= self.nodes.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "link does not connect two nodes of this simulation",
+            ));
+        }
+
+        for (from, to) in [(a.0, b.0), (b.0, a.0)] {
+            self.channels.push(Channel {
+                from,
+                to,
+                link: link.clone(),
+                queue: VecDeque::new(),
+                stats: LinkStats::default(),
+            });
+        }
+        self.routes.clear();
+
+        Ok(())
+    }
+
+    /// What happened to the packets sent from `from` to `to` so far, if they are linked.
+    pub fn link_stats(&self, from: NodeId, to: NodeId) -> Option<LinkStats> {
+        self.channels
+            .iter()
+            .find(|c| (c.from, c.to) == (from.0, to.0))
+            .map(|c| c.stats)
+    }
+
+    /// Run `task` as part of the simulation, starting with the next call to one of the `run`
+    /// methods.
+    pub fn spawn(&mut self, task: impl Future<Output = ()> + 'static) {
+        let id = self.tasks.len();
+        let waker = Waker::from(Arc::new(TaskWaker {
+            id,
+            ready: self.ready.clone(),
+        }));
+        self.tasks.push(Some(Task {
+            future: Box::pin(task),
+            waker,
+        }));
+        self.ready.lock().unwrap().insert(id);
+    }
+
+    /// Run until every task has finished.
+    ///
+    /// Fails if tasks are still waiting once nothing is left to happen, which would otherwise
+    /// be a deadlock.
+    pub fn run(&mut self) -> io::Result<()> {
+        self.run_until(None)
+    }
+
+    /// Run for `duration` of virtual time, e.g. to let connections finish closing.
+    pub fn run_for(&mut self, duration: Duration) -> io::Result<()> {
+        self.run_until(Some(self.clock.now() + duration))
+    }
+
+    /// Run the simulation until `future` resolves, and return its output; the tasks spawned
+    /// keep running alongside it.
+    pub fn block_on<F: Future>(&mut self, future: F) -> io::Result<F::Output> {
+        let mut future = pin!(future);
+        let woken = Arc::new(Woken(AtomicBool::new(true)));
+        let waker = Waker::from(woken.clone());
+
+        loop {
+            if woken.0.swap(false, atomic::Ordering::SeqCst) {
+                if let Poll::Ready(output) = future.as_mut().poll(&mut Context::from_waker(&waker))
+                {
+                    return Ok(output);
+                }
+                continue;
+            }
+            if !self.step(None)? && !woken.0.load(atomic::Ordering::SeqCst) {
+                return Err(stalled());
+            }
+        }
+    }
+
+    fn run_until(&mut self, deadline: Option<Instant>) -> io::Result<()> {
+        loop {
+            if deadline.is_none() && self.tasks.iter().all(Option::is_none) {
+                return Ok(());
+            }
+            if !self.step(deadline)? {
+                return match deadline {
+                    Some(deadline) => {
+                        self.clock.set(deadline);
+                        Ok(())
+                    }
+                    None => Err(stalled()),
+                };
+            }
+        }
+    }
+
+    /// Let the tasks and stacks do everything they can right now, then move on to the next
+    /// event, as long as it is due before `deadline`. Returns `false` if there is none.
+    fn step(&mut self, deadline: Option<Instant>) -> io::Result<bool> {
+        if self.settle()? {
+            return Ok(true);
+        }
+
+        let Some(Reverse(next)) = self.events.peek() else {
+            return Ok(false);
+        };
+        if deadline.is_some_and(|deadline| next.at > deadline) {
+            return Ok(false);
+        }
+        let Reverse(next) = self.events.pop().expect("just peeked");
+        self.clock.set(next.at);
+
+        match next.event {
+            Event::Arrival { node, packet } => self.arrive(node, packet)?,
+            Event::Timer { node } => {
+                let now = next.at;
+                if let Node::Host(stack) = &mut self.nodes[node] {
+                    if stack.timer == Some(now) {
+                        stack.timer = None;
+                    }
+                    while let Some(timer) = stack.cm.timers.pop_expired(now) {
+                        stack.cm.on_timer(&mut stack.device, timer, now)?;
+                    }
+                }
+                self.flush(node)?;
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// Poll the tasks that are ready, and handle the requests they make, until they are all
+    /// waiting. Returns whether anything happened.
+    fn settle(&mut self) -> io::Result<bool> {
+        let mut busy = false;
+        loop {
+            let ready = std::mem::take(&mut *self.ready.lock().unwrap());
+            for &id in &ready {
+                let Some(task) = &mut self.tasks[id] else {
+                    continue;
+                };
+                let mut cx = Context::from_waker(&task.waker);
+                if task.future.as_mut().poll(&mut cx).is_ready() {
+                    self.tasks[id] = None;
+                }
+            }
+
+            let mut handled = false;
+            for node in 0..self.nodes.len() {
+                handled |= self.handle_requests(node)?;
+            }
+
+            if ready.is_empty() && !handled {
+                return Ok(busy);
+            }
+            busy = true;
+        }
+    }
+
+    /// Do what the packet thread would with the requests of a host's tasks.
+    fn handle_requests(&mut self, node: usize) -> io::Result<bool> {
+        let Node::Host(stack) = &mut self.nodes[node] else {
+            return Ok(false);
+        };
+
+        let mut drain = [0u8; 256];
+        while matches!(stack.wake.read(&mut drain), Ok(1..)) {}
+
+        let mut handled = false;
+        while let Ok(request) = stack.requests.try_recv() {
+            stack.cm.handle(&mut stack.device, request)?;
+            handled = true;
+        }
+        if handled {
+            self.flush(node)?;
+        }
+
+        Ok(handled)
+    }
+
+    /// Hand a packet that came off a link to its node.
+    fn arrive(&mut self, node: usize, packet: Vec<u8>) -> io::Result<()> {
+        match &mut self.nodes[node] {
+            Node::Host(stack) => {
+                // hosts don't forward
+                if destination(&packet) == Some(stack.addr) {
+                    stack.cm.on_datagram(&mut stack.device, &packet)?;
+                }
+            }
+            Node::Router => self.forward(node, packet),
+        }
+        self.flush(node)
+    }
+
+    /// Put what a host has sent on its way, and make sure its next timer will fire.
+    fn flush(&mut self, node: usize) -> io::Result<()> {
+        let Node::Host(stack) = &mut self.nodes[node] else {
+            return Ok(());
+        };
+
+        let packets = std::mem::take(&mut stack.device.packets);
+        // timers already due fire right away
+        let timer = (stack.cm.timers.next_deadline()).map(|at| at.max(self.clock.now()));
+        if let Some(at) = timer.filter(|&at| stack.timer.is_none_or(|t| at < t)) {
+            stack.timer = Some(at);
+            self.schedule(at, Event::Timer { node });
+        }
+
+        for packet in packets {
+            self.forward(node, packet);
+        }
+
+        Ok(())
+    }
+
+    /// Queue a packet on the link towards its destination, dropping it if there is none or
+    /// the queue is full.
+    fn forward(&mut self, node: usize, packet: Vec<u8>) {
+        let Some(dst) = destination(&packet) else {
+            return;
+        };
+        let Some(c) = self.route(node, dst) else {
+            return;
+        };
+
+        let now = self.clock.now();
+        let channel = &mut self.channels[c];
+        while channel.queue.front().is_some_and(|&sent| sent <= now) {
+            channel.queue.pop_front();
+        }
+        if channel.queue.len() >= channel.link.queue {
+            channel.stats.dropped += 1;
+            return;
+        }
+
+        let start = channel.queue.back().map_or(now, |&busy| busy.max(now));
+        let sent = start + channel.link.transmission(packet.len());
+        channel.queue.push_back(sent);
+        if self.rng.chance(channel.link.loss) {
+            channel.stats.lost += 1;
+            return;
+        }
+        channel.stats.delivered += 1;
+        channel.stats.bytes += packet.len() as u64;
+
+        let (to, at) = (channel.to, sent + channel.link.latency);
+        self.schedule(at, Event::Arrival { node: to, packet });
+    }
+
+    /// The channel on the shortest path from `node` to the host with address `dst`.
+    fn route(&mut self, node: usize, dst: Ipv4Addr) -> Option<usize> {
+        if let Some(&route) = self.routes.get(&(node, dst)) {
+            return route;
+        }
+
+        let route = self.host(dst).and_then(|target| {
+            // breadth-first from the destination, so every node learns its next hop towards it
+            let mut next_hop = vec![None; self.nodes.len()];
+            let mut seen = vec![false; self.nodes.len()];
+            let mut frontier = VecDeque::from([target]);
+            seen[target] = true;
+            while let Some(n) = frontier.pop_front() {
+                for (c, channel) in self.channels.iter().enumerate() {
+                    if channel.to == n && !seen[channel.from] {
+                        seen[channel.from] = true;
+                        next_hop[channel.from] = Some(c);
+                        frontier.push_back(channel.from);
+                    }
+                }
+            }
+            next_hop[node]
+        });
+        self.routes.insert((node, dst), route);
+
+        route
+    }
+
+    fn host(&self, addr: Ipv4Addr) -> Option<usize> {
+        self.nodes
+            .iter()
+            .position(|node| matches!(node, Node::Host(stack) if stack.addr == addr))
+    }
+
+    fn schedule(&mut self, at: Instant, event: Event) {
+        self.events.push(Reverse(Scheduled {
+            at,
+            id: self.next_event,
+            event,
+        }));
+        self.next_event += 1;
+    }
+}
+
+fn destination(packet: &[u8]) -> Option<Ipv4Addr> {
+    etherparse::Ipv4HeaderSlice::from_slice(packet)
+        .ok()
+        .map(|iph| iph.destination_addr())
+}
+
+fn stalled() -> io::Error {
+    io::Error::other("simulation stalled: tasks are waiting, but nothing is left to happen")
+}
@@ -1,40 +1,74 @@
-use std::{collections::BTreeMap, time};
+use std::{collections::VecDeque, time};
 
 use bitflags::bitflags;
 
+use crate::seq::SeqNum;
+
 bitflags! {
+    /// Readiness of a socket, also used as the interest when registering with a `Poller`.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
     pub struct Available: u8 {
         const READ = 0b00000001;
         const WRITE = 0b00000010;
     }
 }
 
+/// Connection states (RFC 793 S3.2), as reported by [`Connection::state`](crate::Connection::state).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum State {
     // Listen,
+    SynSent,
     SynRcvd,
     Estab,
     FinWait1,
     FinWait2,
+    Closing,
     TimeWait,
+    CloseWait,
+    LastAck,
+    /// TIME-WAIT has run out, or the peer stopped answering keepalive probes
+    Closed,
 }
 
 impl State {
     pub fn is_synchronized(&self) -> bool {
         match *self {
-            State::SynRcvd => false,
-            State::Estab | State::FinWait1 | State::FinWait2 | State::TimeWait => true,
+            State::SynSent | State::SynRcvd | State::Closed => false,
+            State::Estab
+            | State::FinWait1
+            | State::FinWait2
+            | State::Closing
+            | State::TimeWait
+            | State::CloseWait
+            | State::LastAck => true,
         }
     }
 }
 
 pub struct Timers {
-    pub send_times: BTreeMap<u32, time::Instant>,
+    /// when each unacknowledged segment was last sent, by starting sequence number, in sequence
+    /// order, and whether that was a retransmission
+    pub send_times: VecDeque<(SeqNum, time::Instant, bool)>,
     pub srtt: f64,
+    /// when to retransmit from SND.UNA, set while anything is unacknowledged
+    pub retransmit: Option<time::Instant>,
+    /// retransmissions since the last new acknowledgment; each one doubles the timeout
+    pub backoff: u32,
+    /// when to send an ACK that has been held back in the hope of piggybacking it on data
+    pub delayed_ack: Option<time::Instant>,
+    /// how long the connection may be idle before it is probed, if keepalive is enabled
+    pub keepalive_idle: Option<time::Duration>,
+    /// when to send the next keepalive probe
+    pub keepalive: Option<time::Instant>,
+    /// keepalive probes the peer has not answered
+    pub keepalive_probes: u32,
+    /// when TIME-WAIT is over
+    pub time_wait: Option<time::Instant>,
 }
 
 /// State of Send Sequence Space (RFC 793 S3.2 F4)
 ///
-/// ```
+/// ```text
 ///      1         2          3          4
 /// ----------|----------|----------|----------
 ///        SND.UNA    SND.NXT    SND.UNA
@@ -47,24 +81,24 @@ pub struct Timers {
 /// ```
 pub struct SendSequenceSpace {
     /// send unacknowledged
-    pub una: u32,
+    pub una: SeqNum,
     /// send next
-    pub nxt: u32,
+    pub nxt: SeqNum,
     /// send window
     pub wnd: u16,
-    /// send urgent pointer
-    pub up: bool,
+    /// send urgent pointer, set while urgent data has not been acknowledged
+    pub up: Option<SeqNum>,
     /// segment sequence number used for last window update
-    pub wl1: usize,
+    pub wl1: SeqNum,
     /// segment acknowledgment number used for last window update
-    pub wl2: usize,
+    pub wl2: SeqNum,
     /// initial send sequence number
-    pub iss: u32,
+    pub iss: SeqNum,
 }
 
 /// State of Receive Sequence Space (RFC 793 S3.2 F5)
 ///
-/// ```
+/// ```text
 ///    1          2          3
 ///----------|----------|----------
 ///       RCV.NXT    RCV.NXT
@@ -76,11 +110,11 @@ pub struct SendSequenceSpace {
 /// ```
 pub struct RecvSequenceSpace {
     /// receive next
-    pub nxt: u32,
+    pub nxt: SeqNum,
     /// receive window
     pub wnd: u16,
-    /// receive urgent pointer
-    pub up: bool,
+    /// receive urgent pointer, set while the urgent byte has not arrived yet
+    pub up: Option<SeqNum>,
     /// initial receive sequence number
-    pub irs: u32,
+    pub irs: SeqNum,
 }
@@ -1,47 +1,73 @@
-use std::io;
+use std::{io, time::Duration};
 
 use crate::{
+    check_timeout,
+    future::AcceptFuture,
+    request::Wait,
     tcp_stream::{self},
-    InterfaceHandle,
+    InterfaceHandle, InterfaceRequest, Quad,
 };
 
 pub struct TcpListener {
     pub port: u16,
-    pub h: InterfaceHandle,
+    pub(crate) h: InterfaceHandle,
+    pub(crate) nonblocking: bool,
+    pub(crate) accept_timeout: Option<Duration>,
 }
 
 impl Drop for TcpListener {
     fn drop(&mut self) {
-        let mut cm = self.h.manager.lock().unwrap();
-        let pending = cm
-            .pending
-            .remove(&self.port)
-            .expect("port closed while listener still active");
-
-        for _quad in pending {
-            unimplemented!()
-        }
+        let _ = self.h.send(InterfaceRequest::Unbind { port: self.port });
     }
 }
 
 impl TcpListener {
     pub fn accept(&mut self) -> io::Result<tcp_stream::TcpStream> {
-        let mut cm = self.h.manager.lock().unwrap();
-
-        loop {
-            if let Some(quad) = cm
-                .pending
-                .get_mut(&self.port)
-                .expect("port closed while listener still active")
-                .pop_front()
-            {
-                return Ok(tcp_stream::TcpStream {
-                    quad,
-                    h: self.h.clone(),
-                });
-            };
-
-            cm = self.h.pending_var.wait(cm).unwrap();
+        let wait = if self.nonblocking {
+            Wait::Never
+        } else {
+            Wait::timeout(self.accept_timeout, self.h.now())
+        };
+        let quad = self.h.call(|read| InterfaceRequest::Accept {
+            port: self.port,
+            wait,
+            read,
+        })?;
+
+        Ok(self.stream(quad))
+    }
+
+    /// Accept a connection from async code, without blocking the calling thread.
+    pub fn accept_async(&mut self) -> AcceptFuture<'_> {
+        AcceptFuture {
+            listener: self,
+            reply: None,
         }
     }
+
+    pub(crate) fn stream(&self, quad: Quad) -> tcp_stream::TcpStream {
+        tcp_stream::TcpStream::new(quad, self.h.clone())
+    }
+
+    /// Moves this listener into or out of non-blocking mode, in which `accept` returns
+    /// `WouldBlock` instead of waiting for a connection.
+    ///
+    /// Accepted streams always start out in blocking mode.
+    pub fn set_nonblocking(&mut self, nonblocking: bool) -> io::Result<()> {
+        self.nonblocking = nonblocking;
+        Ok(())
+    }
+
+    /// Sets how long `accept` waits for a connection before failing with `WouldBlock`.
+    ///
+    /// `None` waits indefinitely; a zero duration is rejected with `InvalidInput`.
+    pub fn set_accept_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        check_timeout(timeout)?;
+        self.accept_timeout = timeout;
+        Ok(())
+    }
+
+    pub fn accept_timeout(&self) -> io::Result<Option<Duration>> {
+        Ok(self.accept_timeout)
+    }
 }
@@ -1,117 +1,209 @@
 use std::{
     io::{self, Read, Write},
     net::Shutdown,
+    time::Duration,
 };
 
-use crate::{InterfaceHandle, Quad, SENDQUEUE_SIZE};
+use crate::{
+    check_timeout,
+    future::{ReadFuture, WriteFuture},
+    request::Wait,
+    InterfaceHandle, InterfaceRequest, Quad,
+};
 
 pub struct TcpStream {
     pub quad: Quad,
-    pub h: InterfaceHandle,
+    pub(crate) h: InterfaceHandle,
+    pub(crate) nonblocking: bool,
+    pub(crate) read_timeout: Option<Duration>,
+    pub(crate) write_timeout: Option<Duration>,
 }
 
 impl Drop for TcpStream {
     fn drop(&mut self) {
-        let _cm = self.h.manager.lock().unwrap();
-
-        // TODO: send FIN on cm.connections[quad]
-        // TODO: _eventually_ remove self.quad from cm.connections
+        // sends FIN, unless the stream was shut down already
+        let _ = self.h.send(InterfaceRequest::Close { quad: self.quad });
     }
 }
 
 impl Read for TcpStream {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        let mut cm = self.h.manager.lock().unwrap();
-        loop {
-            let c = cm.connections.get_mut(&self.quad).ok_or_else(|| {
-                io::Error::new(
-                    io::ErrorKind::ConnectionAborted,
-                    "stream was terminated unexpectedly",
-                )
-            })?;
-
-            if c.is_rcv_closed() && c.incoming.is_empty() {
-                // no more data to read, and no need to block
-                return Ok(0);
-            }
-
-            if !c.incoming.is_empty() {
-                let mut nread = 0;
-                let (head, tail) = c.incoming.as_slices();
-
-                let hread = std::cmp::min(buf.len(), head.len());
-                buf[..hread].copy_from_slice(&head[..hread]);
-                nread += hread;
-
-                let tread = std::cmp::min(buf.len() - nread, tail.len());
-                buf[hread..(hread + tread)].copy_from_slice(&tail[..tread]);
-                nread += tread;
-
-                drop(c.incoming.drain(..nread));
-
-                return Ok(nread);
-            }
-
-            cm = self.h.rcv_var.wait(cm).unwrap();
+        if buf.is_empty() {
+            return Ok(0);
         }
+
+        let data = self.h.call(|read| InterfaceRequest::Read {
+            quad: self.quad,
+            max_length: buf.len(),
+            wait: self.wait(self.read_timeout),
+            read,
+        })?;
+        buf[..data.len()].copy_from_slice(&data);
+
+        Ok(data.len())
     }
 }
 
 impl Write for TcpStream {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        let mut cm = self.h.manager.lock().unwrap();
-        let c = cm.connections.get_mut(&self.quad).ok_or_else(|| {
-            io::Error::new(
-                io::ErrorKind::ConnectionAborted,
-                "stream was terminated unexpectedly",
-            )
-        })?;
-
-        if c.unacked.len() > SENDQUEUE_SIZE {
-            // TODO: block
-            return Err(io::Error::new(
-                io::ErrorKind::WouldBlock,
-                "too many bytes buffered",
-            ));
+        if buf.is_empty() {
+            return Ok(0);
         }
 
-        let nwrite = std::cmp::min(buf.len(), SENDQUEUE_SIZE - c.unacked.len());
-        c.unacked.extend(&buf[..nwrite]);
-
-        Ok(nwrite)
+        self.h.call(|ack| InterfaceRequest::Write {
+            quad: self.quad,
+            bytes: buf.to_vec(),
+            wait: self.wait(self.write_timeout),
+            ack,
+        })
     }
 
     fn flush(&mut self) -> io::Result<()> {
-        let mut cm = self.h.manager.lock().unwrap();
-        let c = cm.connections.get_mut(&self.quad).ok_or_else(|| {
-            io::Error::new(
-                io::ErrorKind::ConnectionAborted,
-                "stream was terminated unexpectedly",
-            )
-        })?;
+        self.h.call(|ack| InterfaceRequest::Flush {
+            quad: self.quad,
+            wait: self.wait(self.write_timeout),
+            ack,
+        })
+    }
+}
 
-        if c.unacked.is_empty() {
-            Ok(())
+impl TcpStream {
+    pub(crate) fn new(quad: Quad, h: InterfaceHandle) -> Self {
+        TcpStream {
+            quad,
+            h,
+            nonblocking: false,
+            read_timeout: None,
+            write_timeout: None,
+        }
+    }
+
+    fn wait(&self, timeout: Option<Duration>) -> Wait {
+        if self.nonblocking {
+            Wait::Never
         } else {
-            // TODO: block
-            Err(io::Error::new(
-                io::ErrorKind::WouldBlock,
-                "too many bytes buffered",
-            ))
+            Wait::timeout(timeout, self.h.now())
         }
     }
-}
 
-impl TcpStream {
+    /// Read from async code; resolves once data is available or the peer has closed, regardless
+    /// of blocking mode and timeouts.
+    pub fn read_async<'a>(&'a mut self, buf: &'a mut [u8]) -> ReadFuture<'a> {
+        ReadFuture {
+            stream: self,
+            buf,
+            reply: None,
+        }
+    }
+
+    /// Write from async code; resolves once at least part of `buf` fits in the send queue.
+    pub fn write_async<'a>(&'a mut self, buf: &'a [u8]) -> WriteFuture<'a> {
+        WriteFuture {
+            stream: self,
+            buf,
+            reply: None,
+        }
+    }
+
+    /// Moves this stream into or out of non-blocking mode.
+    ///
+    /// In non-blocking mode `read`, `write`, `flush` and `send_urgent` return `WouldBlock`
+    /// instead of waiting, and `write` accepts only as many bytes as fit in the send queue.
+    pub fn set_nonblocking(&mut self, nonblocking: bool) -> io::Result<()> {
+        self.nonblocking = nonblocking;
+        Ok(())
+    }
+
+    /// Sets how long `read` waits for data before failing with `WouldBlock`.
+    ///
+    /// `None` waits indefinitely; a zero duration is rejected with `InvalidInput`.
+    pub fn set_read_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        check_timeout(timeout)?;
+        self.read_timeout = timeout;
+        Ok(())
+    }
+
+    pub fn read_timeout(&self) -> io::Result<Option<Duration>> {
+        Ok(self.read_timeout)
+    }
+
+    /// Sets how long `write`, `flush` and `send_urgent` wait for the peer to acknowledge data
+    /// before failing with `WouldBlock`.
+    ///
+    /// `None` waits indefinitely; a zero duration is rejected with `InvalidInput`.
+    pub fn set_write_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        check_timeout(timeout)?;
+        self.write_timeout = timeout;
+        Ok(())
+    }
+
+    pub fn write_timeout(&self) -> io::Result<Option<Duration>> {
+        Ok(self.write_timeout)
+    }
+
+    /// Probe the peer once the connection has been idle for `idle`, and close it when the probes
+    /// go unanswered.
+    ///
+    /// `None` turns keepalive off (the default); a zero duration is rejected with `InvalidInput`.
+    pub fn set_keepalive(&mut self, idle: Option<Duration>) -> io::Result<()> {
+        check_timeout(idle)?;
+        self.h.call(|ack| InterfaceRequest::SetKeepalive {
+            quad: self.quad,
+            idle,
+            ack,
+        })
+    }
+
     pub fn shutdown(&mut self, _how: Shutdown) -> io::Result<()> {
-        let mut cm = self.h.manager.lock().unwrap();
-        let c = cm.connections.get_mut(&mut self.quad).ok_or_else(|| {
-            io::Error::new(
-                io::ErrorKind::ConnectionAborted,
-                "stream was terminated unexpextedly",
-            )
+        self.h.call(|ack| InterfaceRequest::Shutdown {
+            quad: self.quad,
+            ack,
+        })
+    }
+
+    /// Send a single byte of urgent (out-of-band) data, blocking until there is room for it.
+    ///
+    /// The byte is queued behind any data already written, and the urgent pointer is advertised
+    /// to the peer until the byte has been acknowledged.
+    pub fn send_urgent(&mut self, byte: u8) -> io::Result<()> {
+        self.h.call(|ack| InterfaceRequest::SendUrgent {
+            quad: self.quad,
+            byte,
+            wait: self.wait(self.write_timeout),
+            ack,
         })?;
 
-        c.close()
+        Ok(())
+    }
+
+    /// Read the urgent byte the peer has sent out of band.
+    ///
+    /// Returns `WouldBlock` if the peer has signalled urgent data that has not arrived yet, and
+    /// `InvalidInput` if there is no urgent data or it is being delivered inline.
+    pub fn recv_urgent(&mut self) -> io::Result<u8> {
+        self.h.call(|read| InterfaceRequest::RecvUrgent {
+            quad: self.quad,
+            read,
+        })
+    }
+
+    /// Deliver urgent bytes as part of the normal data stream instead of through `recv_urgent`.
+    pub fn set_oob_inline(&mut self, inline: bool) -> io::Result<()> {
+        self.h.call(|ack| InterfaceRequest::SetOobInline {
+            quad: self.quad,
+            inline,
+            ack,
+        })
+    }
+
+    /// Whether the next byte to be read is the one at the urgent mark.
+    ///
+    /// Reads never cross the mark, so a reader discarding data up to the mark (like telnet's
+    /// "Synch") can read until this returns `true`.
+    pub fn at_mark(&self) -> io::Result<bool> {
+        self.h.call(|ack| InterfaceRequest::AtMark {
+            quad: self.quad,
+            ack,
+        })
     }
 }
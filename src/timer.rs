@@ -0,0 +1,113 @@
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap},
+    hash::Hash,
+    time::Instant,
+};
+
+use crate::Quad;
+
+/// The per-connection deadlines the packet thread keeps track of.
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum TimerKind {
+    Retransmit,
+    DelayedAck,
+    Keepalive,
+    TimeWait,
+}
+
+impl TimerKind {
+    pub(crate) const ALL: [TimerKind; 4] = [
+        TimerKind::Retransmit,
+        TimerKind::DelayedAck,
+        TimerKind::Keepalive,
+        TimerKind::TimeWait,
+    ];
+}
+
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum Timer {
+    Connection(Quad, TimerKind),
+    /// the earliest deadline of any blocked application request
+    Requests,
+}
+
+/// Deadlines ordered in a min-heap, so the packet thread can sleep exactly until the next one.
+///
+/// Rescheduling or cancelling a timer leaves its old heap entry behind; stale entries are
+/// recognised by not matching `armed` and skipped when they reach the top. Deadlines that keep
+/// moving later, like keepalives on a busy connection, leave entries that take long to get
+/// there, so the heap is rebuilt from `armed` once most of it is stale.
+pub(crate) struct TimerQueue<K> {
+    heap: BinaryHeap<Reverse<(Instant, K)>>,
+    armed: HashMap<K, Instant>,
+}
+
+/// How many times more heap entries than armed timers there may be before the heap is rebuilt,
+/// and how many stale entries are always fine.
+const STALE_FACTOR: usize = 2;
+const STALE_SLACK: usize = 64;
+
+impl<K> Default for TimerQueue<K> {
+    fn default() -> Self {
+        Self {
+            heap: BinaryHeap::new(),
+            armed: HashMap::new(),
+        }
+    }
+}
+
+impl<K: Copy + Ord + Hash> TimerQueue<K> {
+    pub(crate) fn schedule(&mut self, key: K, at: Instant) {
+        if self.armed.insert(key, at) == Some(at) {
+            return;
+        }
+        self.heap.push(Reverse((at, key)));
+        if self.heap.len() > STALE_FACTOR * self.armed.len() + STALE_SLACK {
+            self.heap = self
+                .armed
+                .iter()
+                .map(|(&key, &at)| Reverse((at, key)))
+                .collect();
+        }
+    }
+
+    /// Schedule `key` at `at`, unless it is already due earlier.
+    pub(crate) fn schedule_by(&mut self, key: K, at: Instant) {
+        match self.armed.get(&key) {
+            Some(&armed) if armed <= at => {}
+            _ => self.schedule(key, at),
+        }
+    }
+
+    pub(crate) fn cancel(&mut self, key: K) {
+        self.armed.remove(&key);
+    }
+
+    /// Entries in the heap, stale ones included.
+    pub(crate) fn len(&self) -> usize {
+        self.heap.len()
+    }
+
+    pub(crate) fn next_deadline(&mut self) -> Option<Instant> {
+        while let Some(Reverse((at, key))) = self.heap.peek() {
+            if self.armed.get(key) == Some(at) {
+                return Some(*at);
+            }
+            self.heap.pop();
+        }
+
+        None
+    }
+
+    /// Disarm and return the next timer that is due at `now`.
+    pub(crate) fn pop_expired(&mut self, now: Instant) -> Option<K> {
+        if self.next_deadline()? > now {
+            return None;
+        }
+
+        let Reverse((_, key)) = self.heap.pop().expect("next_deadline found an entry");
+        self.armed.remove(&key);
+        Some(key)
+    }
+}
@@ -0,0 +1,128 @@
+use std::{
+    io::{self, Read, Write},
+    net::{Ipv4Addr, SocketAddrV4},
+    thread,
+    time::Duration,
+};
+
+use thunder::{Interface, Loopback, Replay};
+
+const SERVER: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 1);
+const CLIENT: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 2);
+
+#[test]
+fn stacks_with_different_settings_talk_to_each_other() {
+    let (a, b) = Loopback::pair().unwrap();
+    let mut server = Interface::builder()
+        .addr(SERVER, 24)
+        .mtu(576)
+        .send_buffer(256)
+        .build_with_device(a)
+        .unwrap();
+    let mut client = Interface::builder()
+        .addr(CLIENT, 24)
+        .send_buffer(64 * 1024)
+        .recv_window(8192)
+        .delayed_ack(Duration::from_millis(1))
+        .build_with_device(b)
+        .unwrap();
+    let mut listener = server.bind(7000).unwrap();
+
+    let accepted = thread::spawn(move || {
+        let mut stream = listener.accept().unwrap();
+        let mut data = Vec::new();
+        stream.read_to_end(&mut data).unwrap();
+        stream.write_all(&data).unwrap();
+    });
+
+    let sent: Vec<u8> = (0..4000u32).map(|i| i as u8).collect();
+    let mut stream = client.connect(SocketAddrV4::new(SERVER, 7000)).unwrap();
+    stream.write_all(&sent).unwrap();
+    stream.shutdown(std::net::Shutdown::Write).unwrap();
+    let mut echoed = Vec::new();
+    stream.read_to_end(&mut echoed).unwrap();
+    assert_eq!(echoed, sent);
+
+    accepted.join().unwrap();
+}
+
+#[test]
+fn segments_fit_the_configured_mtu() {
+    let (device, replay) = Replay::new().unwrap();
+    let mut server = Interface::builder()
+        .addr(SERVER, 24)
+        .mtu(100)
+        .build_with_device(device)
+        .unwrap();
+    let mut listener = server.bind(7000).unwrap();
+    let accepted = thread::spawn(move || {
+        let mut stream = listener.accept().unwrap();
+        stream.write_all(&[7; 500]).unwrap();
+    });
+
+    // a client that opens its window wide and never acknowledges the data
+    let mut peer = etherparse::TcpHeader::new(50000, 7000, 1000, u16::MAX);
+    peer.syn = true;
+    replay.inject(&packet(&peer)).unwrap();
+    let syn_ack = replay.wait_sent(1, Duration::from_secs(5)).unwrap();
+    let syn_ack = etherparse::TcpHeaderSlice::from_slice(&syn_ack[0][20..]).unwrap();
+    peer.syn = false;
+    peer.ack = true;
+    peer.sequence_number = 1001;
+    peer.acknowledgment_number = syn_ack.sequence_number().wrapping_add(1);
+    replay.inject(&packet(&peer)).unwrap();
+    accepted.join().unwrap();
+
+    let sent = replay.wait_sent(3, Duration::from_secs(5)).unwrap();
+    for packet in &sent {
+        assert!(packet.len() <= 100, "{} byte packet", packet.len());
+    }
+}
+
+fn packet(tcp: &etherparse::TcpHeader) -> Vec<u8> {
+    let ip = etherparse::Ipv4Header::new(
+        tcp.header_len() as u16,
+        64,
+        etherparse::IpNumber::TCP,
+        CLIENT.octets(),
+        SERVER.octets(),
+    )
+    .unwrap();
+    let mut tcp = tcp.clone();
+    tcp.checksum = tcp.calc_checksum_ipv4(&ip, &[]).unwrap();
+    let mut packet = Vec::new();
+    ip.write(&mut packet).unwrap();
+    tcp.write(&mut packet).unwrap();
+    packet
+}
+
+#[test]
+fn rejects_inconsistent_settings() {
+    let (a, _b) = Loopback::pair().unwrap();
+    let err = Interface::builder()
+        .addr(SERVER, 24)
+        .mtu(20)
+        .build_with_device(a)
+        .err()
+        .unwrap();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+    let (a, _b) = Loopback::pair().unwrap();
+    let err = Interface::builder()
+        .addr(SERVER, 24)
+        .rto(Duration::from_secs(2), Duration::from_secs(1))
+        .build_with_device(a)
+        .err()
+        .unwrap();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+    // checked before any device is opened
+    let err = Interface::builder()
+        .name("thunder-test0")
+        .addr(SERVER, 24)
+        .host_addr(Ipv4Addr::new(10, 0, 1, 1))
+        .build()
+        .err()
+        .unwrap();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+}
@@ -0,0 +1,106 @@
+use std::{
+    io::{self, Read, Write},
+    net::{Ipv4Addr, SocketAddrV4},
+    sync::{Arc, Mutex},
+    thread,
+};
+
+use thunder::{Interface, Loopback};
+
+const SERVER: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 1);
+const CLIENT: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 2);
+
+/// A sink the test can still read after handing it to the stack.
+#[derive(Clone, Default)]
+struct Shared(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Shared {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn u32_at(buf: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(buf[at..at + 4].try_into().unwrap())
+}
+
+/// Split a pcap file into its packets.
+fn packets(pcap: &[u8]) -> Vec<&[u8]> {
+    let mut packets = Vec::new();
+    let mut at = 24;
+    while at < pcap.len() {
+        let len = u32_at(pcap, at + 8) as usize;
+        assert_eq!(len, u32_at(pcap, at + 12) as usize, "truncated packet");
+        packets.push(&pcap[at + 16..at + 16 + len]);
+        at += 16 + len;
+    }
+    packets
+}
+
+#[test]
+fn captures_both_directions() {
+    let (a, b) = Loopback::pair().unwrap();
+    let mut server = Interface::with_device(a, SERVER).unwrap();
+    let mut client = Interface::with_device(b, CLIENT).unwrap();
+    let mut listener = server.bind(7000).unwrap();
+
+    let capture = Shared::default();
+    client.capture(capture.clone()).unwrap();
+
+    let accepted = thread::spawn(move || {
+        let mut stream = listener.accept().unwrap();
+        let mut buf = [0u8; 4];
+        stream.read_exact(&mut buf).unwrap();
+        stream.write_all(&buf).unwrap();
+    });
+
+    let mut stream = client.connect(SocketAddrV4::new(SERVER, 7000)).unwrap();
+    stream.write_all(b"ping").unwrap();
+    let mut buf = [0u8; 4];
+    stream.read_exact(&mut buf).unwrap();
+    accepted.join().unwrap();
+    client.stop_capture().unwrap();
+
+    let pcap = capture.0.lock().unwrap().clone();
+    assert_eq!(u32_at(&pcap, 0), 0xa1b2c3d4);
+    // LINKTYPE_RAW
+    assert_eq!(u32_at(&pcap, 20), 101);
+
+    let packets = packets(&pcap);
+    let syn = etherparse::SlicedPacket::from_ip(packets[0]).unwrap();
+    let Some(etherparse::NetSlice::Ipv4(ip)) = syn.net else {
+        panic!("not IPv4");
+    };
+    assert_eq!(ip.header().destination_addr(), SERVER);
+    let Some(etherparse::TransportSlice::Tcp(tcp)) = syn.transport else {
+        panic!("not TCP");
+    };
+    assert!(tcp.syn() && !tcp.ack());
+    assert_eq!(tcp.destination_port(), 7000);
+
+    let syn_ack = etherparse::SlicedPacket::from_ip(packets[1]).unwrap();
+    let Some(etherparse::TransportSlice::Tcp(tcp)) = syn_ack.transport else {
+        panic!("not TCP");
+    };
+    assert!(tcp.syn() && tcp.ack());
+    assert_eq!(tcp.source_port(), 7000);
+
+    // the data went out and came back
+    let payloads: Vec<&[u8]> = packets
+        .iter()
+        .filter_map(
+            |p| match etherparse::SlicedPacket::from_ip(p).unwrap().transport {
+                Some(etherparse::TransportSlice::Tcp(tcp)) if !tcp.payload().is_empty() => {
+                    Some(tcp.payload())
+                }
+                _ => None,
+            },
+        )
+        .collect();
+    assert_eq!(payloads, [b"ping", b"ping"]);
+}
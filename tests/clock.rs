@@ -0,0 +1,176 @@
+use std::{
+    io::{self, Read, Write},
+    net::{Ipv4Addr, SocketAddrV4},
+    sync::{mpsc, Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use thunder::{
+    Available, Interface, Loopback, MockClock, PcapReader, Poller, Replay, TcpStream, Token,
+};
+
+const SERVER: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 1);
+const CLIENT: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 2);
+
+#[test]
+fn unanswered_syn_is_retransmitted_with_backoff() {
+    let clock = MockClock::new();
+    let (device, replay) = Replay::new().unwrap();
+    let mut client = Interface::builder()
+        .addr(CLIENT, 24)
+        .initial_rtt(Duration::from_millis(100))
+        .rto(Duration::from_secs(1), Duration::from_secs(60))
+        .retries(4, 15)
+        .clock(clock.clone())
+        .build_with_device(device)
+        .unwrap();
+
+    let connect = thread::spawn(move || client.connect(SocketAddrV4::new(SERVER, 7000)));
+    replay.wait_sent(1, Duration::from_secs(5)).unwrap();
+
+    clock.advance(Duration::from_millis(999));
+    assert_eq!(replay.sent().len(), 1);
+
+    // each retransmission waits twice as long as the one before
+    let mut rto = Duration::from_secs(1);
+    for sent in 2..6 {
+        clock.advance(Duration::from_millis(1));
+        assert_eq!(replay.sent().len(), sent);
+        assert_eq!(
+            replay.sent()[sent - 1],
+            replay.sent()[0],
+            "not the same SYN"
+        );
+
+        clock.advance(2 * rto - Duration::from_millis(1));
+        assert_eq!(replay.sent().len(), sent);
+        rto *= 2;
+    }
+
+    // nobody answered the last one either, so the connect gives up
+    clock.advance(Duration::from_millis(1));
+    let Err(err) = connect.join().unwrap() else {
+        panic!("connected to nobody");
+    };
+    assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+    assert_eq!(replay.sent().len(), 5);
+}
+
+#[test]
+fn read_timeout_follows_the_clock() {
+    let clock = MockClock::new();
+    let (a, b) = Loopback::pair().unwrap();
+    let mut server = Interface::builder()
+        .addr(SERVER, 24)
+        .clock(clock.clone())
+        .build_with_device(a)
+        .unwrap();
+    let mut client = Interface::builder()
+        .addr(CLIENT, 24)
+        .clock(clock.clone())
+        .build_with_device(b)
+        .unwrap();
+    let mut listener = server.bind(7000).unwrap();
+
+    let _stream = client.connect(SocketAddrV4::new(SERVER, 7000)).unwrap();
+    let mut accepted = listener.accept().unwrap();
+    accepted
+        .set_read_timeout(Some(Duration::from_secs(3600)))
+        .unwrap();
+
+    let reader = thread::spawn(move || accepted.read(&mut [0u8; 8]).unwrap_err().kind());
+    // the read has to be waiting before the hour can pass
+    while !reader.is_finished() {
+        clock.advance(Duration::from_secs(3600));
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert_eq!(reader.join().unwrap(), io::ErrorKind::WouldBlock);
+}
+
+/// Two interfaces on `clock`, and both ends of a connection between them.
+fn connected(clock: &MockClock) -> (Interface, Interface, TcpStream, TcpStream) {
+    let (a, b) = Loopback::pair().unwrap();
+    let mut server = Interface::builder()
+        .addr(SERVER, 24)
+        .clock(clock.clone())
+        .build_with_device(a)
+        .unwrap();
+    let mut client = Interface::builder()
+        .addr(CLIENT, 24)
+        .clock(clock.clone())
+        .build_with_device(b)
+        .unwrap();
+    let mut listener = server.bind(7000).unwrap();
+    let stream = client.connect(SocketAddrV4::new(SERVER, 7000)).unwrap();
+    let accepted = listener.accept().unwrap();
+    (server, client, stream, accepted)
+}
+
+#[test]
+fn poll_timeout_follows_the_clock() {
+    let clock = MockClock::new();
+    let (_server, _client, _stream, accepted) = connected(&clock);
+    let poller = Poller::new();
+    poller
+        .register(&accepted, Token(0), Available::READ)
+        .unwrap();
+
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let mut events = Vec::new();
+        let n = poller.wait(&mut events, Some(Duration::from_millis(10)));
+        tx.send(n.unwrap()).unwrap();
+    });
+    // far longer than the timeout, but the clock has not moved
+    assert!(rx.recv_timeout(Duration::from_millis(200)).is_err());
+
+    clock.advance(Duration::from_millis(10));
+    assert_eq!(rx.recv_timeout(Duration::from_secs(5)).unwrap(), 0);
+}
+
+/// A sink the test can still read after handing it to the stack.
+#[derive(Clone, Default)]
+struct Sink(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Sink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn capture_timestamps_follow_the_clock() {
+    let clock = MockClock::new();
+    let (_server, mut client, mut stream, mut accepted) = connected(&clock);
+    let sink = Sink::default();
+    client.capture(sink.clone()).unwrap();
+
+    let mut buf = [0u8; 5];
+    stream.write_all(b"now").unwrap();
+    accepted.read_exact(&mut buf[..3]).unwrap();
+    // also sends the ACK that was held back
+    clock.advance(Duration::from_secs(90));
+    stream.write_all(b"later").unwrap();
+    accepted.read_exact(&mut buf).unwrap();
+    client.stop_capture().unwrap();
+
+    let pcap = sink.0.lock().unwrap().clone();
+    let mut reader = PcapReader::new(&pcap[..]).unwrap();
+    let mut times = Vec::new();
+    while let Some((time, _)) = reader.read_packet().unwrap() {
+        times.push(time);
+    }
+    assert!(times.len() >= 2, "{times:?}");
+    for &later in &times[1..] {
+        assert_eq!(
+            later.duration_since(times[0]).unwrap(),
+            Duration::from_secs(90)
+        );
+    }
+}
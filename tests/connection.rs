@@ -0,0 +1,597 @@
+use std::{
+    io,
+    net::{Ipv4Addr, SocketAddrV4},
+    time::{Duration, Instant},
+};
+
+use thunder::{Config, Connection, ConnectionEvent, Interface, Output, State};
+
+const CLIENT: SocketAddrV4 = SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 2), 40000);
+const SERVER: SocketAddrV4 = SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 1), 7000);
+
+fn config() -> Config {
+    Interface::builder()
+        .delayed_ack(Duration::from_millis(40))
+        .initial_rtt(Duration::from_millis(100))
+        .rto(Duration::from_millis(200), Duration::from_secs(10))
+        .msl(Duration::from_secs(1))
+        .config()
+        .unwrap()
+}
+
+/// The segment's flags, e.g. "SA" for SYN-ACK, and its payload.
+fn segment(packet: &[u8]) -> (String, Vec<u8>) {
+    let iph = etherparse::Ipv4HeaderSlice::from_slice(packet).unwrap();
+    let tcph = etherparse::TcpHeaderSlice::from_slice(&packet[iph.slice().len()..]).unwrap();
+    let flags = [
+        (tcph.syn(), 'S'),
+        (tcph.fin(), 'F'),
+        (tcph.rst(), 'R'),
+        (tcph.ack(), 'A'),
+    ]
+    .into_iter()
+    .filter_map(|(set, c)| set.then_some(c))
+    .collect();
+    let data = packet[iph.slice().len() + tcph.slice().len()..].to_vec();
+    (flags, data)
+}
+
+fn only(output: &Output) -> &[u8] {
+    assert_eq!(output.segments.len(), 1, "{output:?}");
+    &output.segments[0]
+}
+
+/// A client and a server connection that have completed the handshake at `now`.
+fn established(now: Instant) -> (Connection, Connection) {
+    let (mut client, syn) = Connection::connect(CLIENT, SERVER, &config(), now).unwrap();
+    assert_eq!(segment(only(&syn)).0, "S");
+
+    let (mut server, syn_ack) = Connection::accept(only(&syn), &config(), now)
+        .unwrap()
+        .unwrap();
+    assert_eq!(segment(only(&syn_ack)).0, "SA");
+    assert_eq!(server.state(), State::SynRcvd);
+
+    let ack = client.on_segment(only(&syn_ack), now).unwrap();
+    assert_eq!(segment(only(&ack)).0, "A");
+    assert_eq!(ack.events, [ConnectionEvent::Established]);
+
+    let done = server.on_segment(only(&ack), now).unwrap();
+    assert!(done.segments.is_empty());
+    assert_eq!(done.events, [ConnectionEvent::Established]);
+
+    (client, server)
+}
+
+#[test]
+fn data_is_acknowledged_after_the_delay() {
+    let now = Instant::now();
+    let (mut client, mut server) = established(now);
+
+    assert_eq!(client.try_write(b"hello"), Some(5));
+    let sent = client.send_pending(now).unwrap();
+    assert_eq!(segment(only(&sent)), ("A".to_string(), b"hello".to_vec()));
+
+    let received = server.on_segment(only(&sent), now).unwrap();
+    assert!(received.segments.is_empty(), "the ACK waits for data");
+    assert_eq!(received.events, [ConnectionEvent::Readable]);
+    let mut buf = [0u8; 16];
+    assert_eq!(server.try_read(&mut buf), Some(5));
+    assert_eq!(&buf[..5], b"hello");
+    assert_eq!(server.try_read(&mut buf), None);
+
+    let at = server.next_deadline().unwrap();
+    assert_eq!(at, now + Duration::from_millis(40));
+    assert!(server
+        .on_timer(at - Duration::from_millis(1))
+        .unwrap()
+        .segments
+        .is_empty());
+    let ack = server.on_timer(at).unwrap();
+    assert_eq!(segment(only(&ack)).0, "A");
+
+    assert!(client
+        .on_segment(only(&ack), at)
+        .unwrap()
+        .segments
+        .is_empty());
+    assert_eq!(client.next_deadline(), None, "nothing left to retransmit");
+}
+
+#[test]
+fn lost_segment_is_retransmitted() {
+    let now = Instant::now();
+    let (mut client, mut server) = established(now);
+
+    client.try_write(b"lost").unwrap();
+    let lost = client.send_pending(now).unwrap();
+
+    let at = client.next_deadline().unwrap();
+    assert!(at > now);
+    let again = client.on_timer(at).unwrap();
+    assert_eq!(only(&again), only(&lost));
+
+    let received = server.on_segment(only(&again), at).unwrap();
+    assert_eq!(received.events, [ConnectionEvent::Readable]);
+    // the retransmission backed off
+    assert!(client.next_deadline().unwrap() - at > at - now);
+}
+
+#[test]
+fn a_peer_that_never_acknowledges_times_out() {
+    let now = Instant::now();
+    let (mut client, _server) = established(now);
+
+    client.try_write(b"lost").unwrap();
+    let lost = client.send_pending(now).unwrap();
+    // as many retransmissions as the default allows
+    for _ in 0..15 {
+        let at = client.next_deadline().unwrap();
+        assert_eq!(only(&client.on_timer(at).unwrap()), only(&lost));
+    }
+
+    let at = client.next_deadline().unwrap();
+    let gave_up = client.on_timer(at).unwrap();
+    assert!(gave_up.segments.is_empty());
+    // reads no longer block, since they fail
+    assert_eq!(
+        gave_up.events,
+        [
+            ConnectionEvent::Readable,
+            ConnectionEvent::TimedOut,
+            ConnectionEvent::Closed
+        ]
+    );
+    assert_eq!(client.next_deadline(), None);
+}
+
+/// A reset from `SERVER` to `CLIENT` with sequence number `seq`.
+fn rst(seq: u32) -> Vec<u8> {
+    let mut packet = Vec::new();
+    etherparse::PacketBuilder::ipv4(SERVER.ip().octets(), CLIENT.ip().octets(), 64)
+        .tcp(SERVER.port(), CLIENT.port(), seq, 1000)
+        .rst()
+        .write(&mut packet, &[])
+        .unwrap();
+    packet
+}
+
+/// The client's RCV.NXT: where the server's next data would start.
+fn client_rcv_nxt(server: &mut Connection, now: Instant) -> u32 {
+    server.try_write(b"x").unwrap();
+    let data = server.send_pending(now).unwrap();
+    seq_of(only(&data))
+}
+
+#[test]
+fn a_reset_at_rcv_nxt_closes_the_connection() {
+    let now = Instant::now();
+    let (mut client, mut server) = established(now);
+    let nxt = client_rcv_nxt(&mut server, now);
+
+    let reset = client.on_segment(&rst(nxt), now).unwrap();
+    assert!(reset.segments.is_empty());
+    assert!(reset.events.contains(&ConnectionEvent::Closed));
+    assert_eq!(client.state(), State::Closed);
+}
+
+#[test]
+fn a_reset_elsewhere_in_the_window_is_challenged() {
+    let now = Instant::now();
+    let (mut client, mut server) = established(now);
+    let nxt = client_rcv_nxt(&mut server, now);
+
+    let challenged = client.on_segment(&rst(nxt.wrapping_add(100)), now).unwrap();
+    assert_eq!(segment(only(&challenged)).0, "A");
+    assert!(challenged.events.is_empty());
+    assert_eq!(client.state(), State::Estab);
+}
+
+#[test]
+fn a_reset_outside_the_window_is_ignored() {
+    let now = Instant::now();
+    let (mut client, mut server) = established(now);
+    let nxt = client_rcv_nxt(&mut server, now);
+
+    for seq in [nxt.wrapping_sub(1), nxt.wrapping_add(1 << 20)] {
+        let ignored = client.on_segment(&rst(seq), now).unwrap();
+        assert!(ignored.segments.is_empty(), "{seq}");
+        assert!(ignored.events.is_empty(), "{seq}");
+    }
+    assert_eq!(client.state(), State::Estab);
+}
+
+/// An ACK from `SERVER` to `CLIENT` that opens the window to `window` bytes.
+fn ack(seq: u32, ackn: u32, window: u16) -> Vec<u8> {
+    let mut packet = Vec::new();
+    etherparse::PacketBuilder::ipv4(SERVER.ip().octets(), CLIENT.ip().octets(), 64)
+        .tcp(SERVER.port(), CLIENT.port(), seq, window)
+        .ack(ackn)
+        .write(&mut packet, &[])
+        .unwrap();
+    packet
+}
+
+fn sent_bytes(output: &Output) -> usize {
+    output.segments.iter().map(|s| segment(s).1.len()).sum()
+}
+
+#[test]
+fn the_peers_window_limits_what_is_sent() {
+    let now = Instant::now();
+    let (mut client, mut server) = established(now);
+    let seq = client_rcv_nxt(&mut server, now);
+
+    client.try_write(&[1; 300]).unwrap();
+    let first = client.send_pending(now).unwrap();
+    let end = seq_of(only(&first)) + 300;
+
+    // the window closes with everything acknowledged: only a probe goes out
+    let closed = client.on_segment(&ack(seq, end, 0), now).unwrap();
+    assert!(closed.segments.is_empty());
+    client.try_write(&[2; 300]).unwrap();
+    assert!(client.send_pending(now).unwrap().segments.is_empty());
+    let at = client.next_deadline().expect("a window probe");
+    let probe = client.on_timer(at).unwrap();
+    assert_eq!(segment(only(&probe)), ("A".to_string(), vec![2]));
+
+    // an ACK older than the one that closed the window leaves it closed
+    let stale = client.on_segment(&ack(seq, end - 300, 1000), at).unwrap();
+    assert!(stale.segments.is_empty());
+    assert!(client.send_pending(at).unwrap().segments.is_empty());
+
+    // it opens far enough for a hundred bytes past the probe
+    client.on_segment(&ack(seq, end + 1, 100), at).unwrap();
+    assert_eq!(sent_bytes(&client.send_pending(at).unwrap()), 100);
+}
+
+#[test]
+fn retransmitted_segments_are_not_timed() {
+    let now = Instant::now();
+    let (mut client, mut server) = established(now);
+    let seq = client_rcv_nxt(&mut server, now);
+
+    client.try_write(b"slow").unwrap();
+    let sent = client.send_pending(now).unwrap();
+    let end = seq_of(only(&sent)) + 4;
+    let first_rto = client.next_deadline().unwrap() - now;
+    let at = client.next_deadline().unwrap();
+    client.on_timer(at).unwrap();
+
+    // the ACK might be for the first transmission, which would make the round trip seconds long
+    let later = at + Duration::from_secs(3);
+    client.on_segment(&ack(seq, end, 1000), later).unwrap();
+
+    client.try_write(b"fast").unwrap();
+    client.send_pending(later).unwrap();
+    assert_eq!(client.next_deadline().unwrap() - later, first_rto);
+}
+
+#[test]
+fn both_sides_close() {
+    let now = Instant::now();
+    let (mut client, mut server) = established(now);
+
+    client.close().unwrap();
+    let fin = client.send_pending(now).unwrap();
+    assert_eq!(segment(only(&fin)).0, "FA");
+    assert_eq!(client.state(), State::FinWait1);
+
+    let eof = server.on_segment(only(&fin), now).unwrap();
+    assert_eq!(eof.events, [ConnectionEvent::Readable]);
+    assert_eq!(server.try_read(&mut [0u8; 8]), Some(0));
+    assert_eq!(server.state(), State::CloseWait);
+    client.on_segment(only(&eof), now).unwrap();
+    assert_eq!(client.state(), State::FinWait2);
+
+    server.close().unwrap();
+    let fin = server.send_pending(now).unwrap();
+    let ack = client.on_segment(only(&fin), now).unwrap();
+    assert_eq!(client.state(), State::TimeWait);
+    assert_eq!(
+        server.on_segment(only(&ack), now).unwrap().events,
+        [ConnectionEvent::Closed]
+    );
+
+    // TIME-WAIT lasts twice the maximum segment lifetime
+    let at = client.next_deadline().unwrap();
+    assert_eq!(at, now + Duration::from_secs(2));
+    assert_eq!(
+        client.on_timer(at).unwrap().events,
+        [ConnectionEvent::Closed]
+    );
+    assert!(client.is_closed());
+}
+
+#[test]
+fn only_a_syn_opens_a_connection() {
+    let now = Instant::now();
+    let (mut client, _server) = established(now);
+
+    client.try_write(b"x").unwrap();
+    let data = client.send_pending(now).unwrap();
+    assert!(Connection::accept(only(&data), &config(), now)
+        .unwrap()
+        .is_none());
+
+    let err = Connection::accept(b"not a packet", &config(), now)
+        .err()
+        .unwrap();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+}
+
+/// A SYN from `CLIENT` to `SERVER` with sequence number `seq`.
+fn syn(seq: u32) -> Vec<u8> {
+    let mut packet = Vec::new();
+    etherparse::PacketBuilder::ipv4(CLIENT.ip().octets(), SERVER.ip().octets(), 64)
+        .tcp(CLIENT.port(), SERVER.port(), seq, 1000)
+        .syn()
+        .write(&mut packet, &[])
+        .unwrap();
+    packet
+}
+
+#[test]
+fn the_sequence_space_wraps_during_the_handshake() {
+    let now = Instant::now();
+    let (_, syn_ack) = Connection::accept(&syn(u32::MAX), &config(), now)
+        .unwrap()
+        .unwrap();
+
+    let iph = etherparse::Ipv4HeaderSlice::from_slice(only(&syn_ack)).unwrap();
+    let tcph =
+        etherparse::TcpHeaderSlice::from_slice(&only(&syn_ack)[iph.slice().len()..]).unwrap();
+    assert_eq!(tcph.acknowledgment_number(), 0);
+}
+
+#[test]
+fn malformed_packets_are_refused() {
+    let now = Instant::now();
+    let mut packet = syn(1);
+
+    // padding after the IPv4 total length is not part of the segment
+    packet.extend_from_slice(&[0xff; 6]);
+    let (_, syn_ack) = Connection::accept(&packet, &config(), now)
+        .unwrap()
+        .unwrap();
+    assert_eq!(segment(only(&syn_ack)).0, "SA");
+
+    // a total length beyond the end of the packet
+    packet.truncate(30);
+    let err = Connection::accept(&packet, &config(), now).err().unwrap();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+    // a fragment
+    let mut packet = syn(1);
+    packet[6] |= 0x20;
+    let err = Connection::accept(&packet, &config(), now).err().unwrap();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn nothing_is_written_after_close() {
+    let now = Instant::now();
+    let (mut client, _server) = established(now);
+
+    client.close().unwrap();
+    let fin = client.send_pending(now).unwrap();
+    assert_eq!(segment(only(&fin)).0, "FA");
+
+    assert_eq!(client.try_write(b"late"), Some(0));
+    assert!(client.send_pending(now).unwrap().segments.is_empty());
+}
+
+/// The sequence number of the SYN that opens a connection from `local` to `remote` at `now`.
+fn iss(config: &Config, local: SocketAddrV4, remote: SocketAddrV4, now: Instant) -> u32 {
+    let (_, syn) = Connection::connect(local, remote, config, now).unwrap();
+    let packet = only(&syn);
+    etherparse::TcpHeaderSlice::from_slice(&packet[20..])
+        .unwrap()
+        .sequence_number()
+}
+
+fn seeded(seed: u64) -> Config {
+    Interface::builder().isn_seed(seed).config().unwrap()
+}
+
+#[test]
+fn initial_sequence_numbers_depend_on_the_quad_and_the_secret() {
+    let now = Instant::now();
+    let other = SocketAddrV4::new(*CLIENT.ip(), CLIENT.port() + 1);
+
+    let first = iss(&seeded(1), CLIENT, SERVER, now);
+    assert_ne!(first, iss(&seeded(1), other, SERVER, now));
+    // a seed gives the same numbers every time
+    assert_eq!(first, iss(&seeded(1), CLIENT, SERVER, now));
+    assert_ne!(first, iss(&seeded(2), CLIENT, SERVER, now));
+    // and without one the secret is random
+    assert_ne!(
+        iss(&config(), CLIENT, SERVER, now),
+        iss(&config(), CLIENT, SERVER, now)
+    );
+}
+
+#[test]
+fn initial_sequence_numbers_advance_with_time() {
+    let now = Instant::now();
+    let config = seeded(1);
+    let first = iss(&config, CLIENT, SERVER, now);
+    // one tick every 4 microseconds
+    let later = iss(&config, CLIENT, SERVER, now + Duration::from_secs(1));
+    assert_eq!(later.wrapping_sub(first), 250_000);
+}
+
+#[test]
+fn a_fixed_initial_sequence_number_wins() {
+    let config = Interface::builder()
+        .isn_seed(1)
+        .initial_sequence(u32::MAX)
+        .config()
+        .unwrap();
+    assert_eq!(iss(&config, CLIENT, SERVER, Instant::now()), u32::MAX);
+}
+
+/// The sequence number of a segment.
+fn seq_of(packet: &[u8]) -> u32 {
+    let iph = etherparse::Ipv4HeaderSlice::from_slice(packet).unwrap();
+    etherparse::TcpHeaderSlice::from_slice(&packet[iph.slice().len()..])
+        .unwrap()
+        .sequence_number()
+}
+
+#[test]
+fn a_syn_cookie_brings_the_connection_back() {
+    let now = Instant::now();
+    let config = config();
+    let (mut client, syn) = Connection::connect(CLIENT, SERVER, &config, now).unwrap();
+    let syn_ack = Connection::syn_cookie(only(&syn), &config, now)
+        .unwrap()
+        .unwrap();
+    assert_eq!(segment(only(&syn_ack)).0, "SA");
+
+    let ack = client.on_segment(only(&syn_ack), now).unwrap();
+    assert_eq!(ack.events, [ConnectionEvent::Established]);
+    let later = now + Duration::from_secs(1);
+    let (mut server, done) = Connection::from_cookie(only(&ack), &config, later)
+        .unwrap()
+        .unwrap();
+    assert!(done.segments.is_empty());
+    assert_eq!(done.events, [ConnectionEvent::Established]);
+    assert_eq!(server.state(), State::Estab);
+
+    // and it works like any other
+    client.try_write(b"hello").unwrap();
+    let sent = client.send_pending(later).unwrap();
+    server.on_segment(only(&sent), later).unwrap();
+    let mut buf = [0u8; 16];
+    assert_eq!(server.try_read(&mut buf), Some(5));
+    server.try_write(b"back").unwrap();
+    let reply = server.send_pending(later).unwrap();
+    assert_eq!(segment(only(&reply)), ("A".to_string(), b"back".to_vec()));
+    client.on_segment(only(&reply), later).unwrap();
+    assert_eq!(client.try_read(&mut buf), Some(4));
+}
+
+#[test]
+fn forged_and_stale_cookies_are_refused() {
+    let now = Instant::now();
+    let config = config();
+    let (mut client, syn) = Connection::connect(CLIENT, SERVER, &config, now).unwrap();
+    let syn_ack = Connection::syn_cookie(only(&syn), &config, now)
+        .unwrap()
+        .unwrap();
+    let ack = client.on_segment(only(&syn_ack), now).unwrap();
+    let ack = only(&ack);
+
+    let mut forged = ack.to_vec();
+    let ackn = u32::from_be_bytes(forged[28..32].try_into().unwrap()) ^ 0x100;
+    forged[28..32].copy_from_slice(&ackn.to_be_bytes());
+    assert!(Connection::from_cookie(&forged, &config, now)
+        .unwrap()
+        .is_none());
+
+    // cookies last a few minutes
+    let stale = now + Duration::from_secs(10 * 60);
+    assert!(Connection::from_cookie(ack, &config, stale)
+        .unwrap()
+        .is_none());
+    let recent = now + Duration::from_secs(100);
+    assert!(Connection::from_cookie(ack, &config, recent)
+        .unwrap()
+        .is_some());
+    // a cookie is only good for its own quad, under the secret that made it
+    let mut other_port = ack.to_vec();
+    other_port[20..22].copy_from_slice(&(CLIENT.port() + 1).to_be_bytes());
+    assert!(Connection::from_cookie(&other_port, &config, now)
+        .unwrap()
+        .is_none());
+    let other_secret = Interface::builder().isn_seed(7).config().unwrap();
+    assert!(Connection::from_cookie(ack, &other_secret, now)
+        .unwrap()
+        .is_none());
+}
+
+#[test]
+fn a_syn_cookie_remembers_the_peers_mss() {
+    let now = Instant::now();
+    let config = config();
+    let mut syn = Vec::new();
+    etherparse::PacketBuilder::ipv4(CLIENT.ip().octets(), SERVER.ip().octets(), 64)
+        .tcp(CLIENT.port(), SERVER.port(), 1000, 4096)
+        .syn()
+        .options(&[etherparse::TcpOptionElement::MaximumSegmentSize(1000)])
+        .unwrap()
+        .write(&mut syn, &[])
+        .unwrap();
+    let syn_ack = Connection::syn_cookie(&syn, &config, now).unwrap().unwrap();
+
+    let mut ack = Vec::new();
+    etherparse::PacketBuilder::ipv4(CLIENT.ip().octets(), SERVER.ip().octets(), 64)
+        .tcp(CLIENT.port(), SERVER.port(), 1001, 4096)
+        .ack(seq_of(only(&syn_ack)).wrapping_add(1))
+        .write(&mut ack, &[])
+        .unwrap();
+    let (mut server, _) = Connection::from_cookie(&ack, &config, now)
+        .unwrap()
+        .unwrap();
+
+    // 1000 rounds down to the largest MSS a cookie can hold below it
+    assert_eq!(server.try_write(&[7; 1024]), Some(1024));
+    let sent = server.send_pending(now).unwrap();
+    let sizes: Vec<_> = sent.segments.iter().map(|s| segment(s).1.len()).collect();
+    assert_eq!(sizes, [536, 488]);
+}
+
+/// The urgent pointer of a segment, if its URG flag is set.
+fn urgent_pointer(packet: &[u8]) -> Option<u16> {
+    let tcph = etherparse::TcpHeaderSlice::from_slice(&packet[20..]).unwrap();
+    tcph.urg().then(|| tcph.urgent_pointer())
+}
+
+#[test]
+fn the_urgent_pointer_is_sent_until_the_byte_is_acknowledged() {
+    let now = Instant::now();
+    let (mut client, mut server) = established(now);
+
+    client.try_write(b"ab").unwrap();
+    client.send_urgent(b'!');
+    let sent = client.send_pending(now).unwrap();
+    // it points just past the urgent byte
+    assert_eq!(urgent_pointer(only(&sent)), Some(3));
+
+    server.on_segment(only(&sent), now).unwrap();
+    let mut buf = [0u8; 16];
+    assert_eq!(server.try_read(&mut buf), Some(2));
+    assert_eq!(server.recv_urgent().unwrap(), b'!');
+    let ack = server.on_timer(server.next_deadline().unwrap()).unwrap();
+    client.on_segment(only(&ack), now).unwrap();
+
+    client.try_write(b"cd").unwrap();
+    let sent = client.send_pending(now).unwrap();
+    assert_eq!(urgent_pointer(only(&sent)), None);
+}
+
+#[test]
+fn the_urgent_byte_may_come_after_the_pointer() {
+    let now = Instant::now();
+    // 28 bytes of data to a segment
+    let config = Interface::builder().mtu(68).config().unwrap();
+    let (mut client, syn) = Connection::connect(CLIENT, SERVER, &config, now).unwrap();
+    let (mut server, syn_ack) = Connection::accept(only(&syn), &config, now)
+        .unwrap()
+        .unwrap();
+    let ack = client.on_segment(only(&syn_ack), now).unwrap();
+    server.on_segment(only(&ack), now).unwrap();
+
+    client.try_write(&[b'.'; 28]).unwrap();
+    client.send_urgent(b'!');
+    let sent = client.send_pending(now).unwrap();
+    assert_eq!(sent.segments.len(), 2);
+
+    server.on_segment(&sent.segments[0], now).unwrap();
+    let err = server.recv_urgent().unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+
+    server.on_segment(&sent.segments[1], now).unwrap();
+    assert_eq!(server.recv_urgent().unwrap(), b'!');
+}
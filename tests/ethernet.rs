@@ -0,0 +1,153 @@
+use std::{
+    io::{self, Read, Write},
+    net::{Ipv4Addr, SocketAddrV4},
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
+
+use thunder::{Ethernet, Interface, Loopback, MockClock, NetDevice};
+
+const SERVER: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 1);
+const SERVER_MAC: [u8; 6] = [0x02, 0, 0, 0, 0, 1];
+const CLIENT: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 2);
+const CLIENT_MAC: [u8; 6] = [0x02, 0, 0, 0, 0, 2];
+
+fn recv_frame(device: &mut Loopback) -> Vec<u8> {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    let mut buf = [0u8; 1514];
+    loop {
+        match device.recv(&mut buf) {
+            Ok(n) => return buf[..n].to_vec(),
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock && Instant::now() < deadline => {
+                thread::sleep(Duration::from_millis(10));
+            }
+            Err(e) => panic!("no frame: {e}"),
+        }
+    }
+}
+
+#[test]
+fn answers_arp_requests_for_its_address() {
+    let (a, mut wire) = Loopback::pair().unwrap();
+    let _server = Interface::with_device(Ethernet::new(a, SERVER_MAC, SERVER), SERVER).unwrap();
+
+    let mut request = Vec::new();
+    request.extend_from_slice(&[0xff; 6]);
+    request.extend_from_slice(&CLIENT_MAC);
+    request.extend_from_slice(&[0x08, 0x06]);
+    request.extend_from_slice(&[0, 1, 8, 0, 6, 4, 0, 1]);
+    request.extend_from_slice(&CLIENT_MAC);
+    request.extend_from_slice(&CLIENT.octets());
+    request.extend_from_slice(&[0; 6]);
+    request.extend_from_slice(&SERVER.octets());
+    wire.send(&request).unwrap();
+
+    let reply = recv_frame(&mut wire);
+    assert_eq!(reply[..6], CLIENT_MAC);
+    assert_eq!(reply[6..12], SERVER_MAC);
+    assert_eq!(reply[12..14], [0x08, 0x06]);
+    // a reply, from the server's addresses to the client's
+    assert_eq!(reply[14..22], [0, 1, 8, 0, 6, 4, 0, 2]);
+    assert_eq!(reply[22..28], SERVER_MAC);
+    assert_eq!(reply[28..32], SERVER.octets());
+    assert_eq!(reply[32..38], CLIENT_MAC);
+    assert_eq!(reply[38..42], CLIENT.octets());
+}
+
+#[test]
+fn resolves_the_peer_before_connecting() {
+    let (a, b) = Loopback::pair().unwrap();
+    let mut server = Interface::with_device(Ethernet::new(a, SERVER_MAC, SERVER), SERVER).unwrap();
+    let mut client = Interface::with_device(Ethernet::new(b, CLIENT_MAC, CLIENT), CLIENT).unwrap();
+    let mut listener = server.bind(7000).unwrap();
+
+    let accepted = thread::spawn(move || {
+        let mut stream = listener.accept().unwrap();
+        let mut buf = [0u8; 4];
+        stream.read_exact(&mut buf).unwrap();
+        stream.write_all(&buf).unwrap();
+    });
+
+    let mut stream = client.connect(SocketAddrV4::new(SERVER, 7000)).unwrap();
+    stream.write_all(b"ping").unwrap();
+    let mut buf = [0u8; 4];
+    stream.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"ping");
+
+    accepted.join().unwrap();
+}
+
+/// An ARP request for `SERVER` from `mac` at `addr`.
+fn arp_request(mac: [u8; 6], addr: Ipv4Addr) -> Vec<u8> {
+    let mut request = Vec::new();
+    request.extend_from_slice(&[0xff; 6]);
+    request.extend_from_slice(&mac);
+    request.extend_from_slice(&[0x08, 0x06]);
+    request.extend_from_slice(&[0, 1, 8, 0, 6, 4, 0, 1]);
+    request.extend_from_slice(&mac);
+    request.extend_from_slice(&addr.octets());
+    request.extend_from_slice(&[0; 6]);
+    request.extend_from_slice(&SERVER.octets());
+    request
+}
+
+/// A host that claims to be at the `i`th address of 10.1.0.0/16.
+fn spoofed(i: u16) -> ([u8; 6], Ipv4Addr) {
+    let [hi, lo] = i.to_be_bytes();
+    ([0x02, 1, 0, 0, hi, lo], Ipv4Addr::new(10, 1, hi, lo))
+}
+
+/// Have `eth` handle requests from the spoofed `hosts`, throwing away its replies.
+fn flood(eth: &mut Ethernet<Loopback>, wire: &mut Loopback, hosts: std::ops::Range<u16>) {
+    let mut buf = [0u8; 1514];
+    for i in hosts {
+        let (mac, addr) = spoofed(i);
+        wire.send(&arp_request(mac, addr)).unwrap();
+        assert_eq!(eth.recv(&mut buf).unwrap(), 0);
+        recv_frame(wire);
+    }
+}
+
+/// The frame `eth` sends for an IPv4 packet to `dst`: straight to the MAC address it knows
+/// for it, or an ARP request if it does not know one.
+fn frame_to(eth: &mut Ethernet<Loopback>, wire: &mut Loopback, dst: Ipv4Addr) -> Vec<u8> {
+    let mut packet = Vec::new();
+    etherparse::PacketBuilder::ipv4(SERVER.octets(), dst.octets(), 64)
+        .tcp(7000, 40000, 0, 1024)
+        .write(&mut packet, &[])
+        .unwrap();
+    eth.send(&packet).unwrap();
+    recv_frame(wire)
+}
+
+#[test]
+fn spoofed_arp_requests_do_not_fill_the_table() {
+    let (a, mut wire) = Loopback::pair().unwrap();
+    let mut eth = Ethernet::new(a, SERVER_MAC, SERVER);
+    flood(&mut eth, &mut wire, 0..1000);
+
+    // the first requests were learned, the last ones found no room
+    let (mac, addr) = spoofed(0);
+    let frame = frame_to(&mut eth, &mut wire, addr);
+    assert_eq!((&frame[..6], &frame[12..14]), (&mac[..], &[0x08, 0x00][..]));
+    let frame = frame_to(&mut eth, &mut wire, spoofed(999).1);
+    assert_eq!(
+        (&frame[..6], &frame[12..14]),
+        (&[0xff; 6][..], &[0x08, 0x06][..])
+    );
+}
+
+#[test]
+fn expired_arp_entries_make_room() {
+    let clock = MockClock::new();
+    let (a, mut wire) = Loopback::pair().unwrap();
+    let mut eth = Ethernet::new(a, SERVER_MAC, SERVER).with_clock(Arc::new(clock.clone()));
+    flood(&mut eth, &mut wire, 0..1000);
+
+    clock.advance(Duration::from_secs(3600));
+    flood(&mut eth, &mut wire, 1000..1001);
+    let (mac, addr) = spoofed(1000);
+    let frame = frame_to(&mut eth, &mut wire, addr);
+    assert_eq!((&frame[..6], &frame[12..14]), (&mac[..], &[0x08, 0x00][..]));
+}
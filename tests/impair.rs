@@ -0,0 +1,157 @@
+use std::{
+    io::{self, Read, Write},
+    net::{Ipv4Addr, SocketAddrV4},
+    os::fd::{AsRawFd, BorrowedFd},
+    thread,
+    time::{Duration, Instant},
+};
+
+use thunder::{Impaired, Impairment, Interface, Loopback, NetDevice};
+
+const SERVER: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 1);
+const CLIENT: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 2);
+
+/// Everything that arrives on `device` within `wait`.
+fn drain(device: &mut Loopback, wait: Duration) -> Vec<Vec<u8>> {
+    let deadline = Instant::now() + wait;
+    let mut packets = Vec::new();
+    let mut buf = [0u8; 1500];
+    while Instant::now() < deadline {
+        match device.recv(&mut buf) {
+            Ok(n) => packets.push(buf[..n].to_vec()),
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                thread::sleep(Duration::from_millis(1))
+            }
+            Err(e) => panic!("{e}"),
+        }
+    }
+    packets
+}
+
+#[test]
+fn corruption_flips_one_bit() {
+    let (a, mut b) = Loopback::pair().unwrap();
+    let mut impaired = Impaired::new(a, Impairment::new().corrupt(1.0)).unwrap();
+
+    let packet = [0x5a; 64];
+    impaired.send(&packet).unwrap();
+    let received = drain(&mut b, Duration::from_millis(50));
+    assert_eq!(received.len(), 1);
+    let flipped: u32 = packet
+        .iter()
+        .zip(&received[0])
+        .map(|(x, y)| (x ^ y).count_ones())
+        .sum();
+    assert_eq!(flipped, 1);
+}
+
+#[test]
+fn the_same_seed_loses_the_same_packets() {
+    let survivors = |seed| {
+        let (a, mut b) = Loopback::pair().unwrap();
+        let mut impaired =
+            Impaired::new(a, Impairment::new().loss(0.5).duplicate(0.2).seed(seed)).unwrap();
+        for i in 0..100u8 {
+            impaired.send(&[i]).unwrap();
+        }
+        drain(&mut b, Duration::from_millis(50))
+    };
+
+    let first = survivors(7);
+    assert!(first.len() > 20 && first.len() < 100, "{}", first.len());
+    assert_eq!(first, survivors(7));
+    assert_ne!(first, survivors(8));
+}
+
+#[test]
+fn delayed_packets_wake_the_device_up() {
+    let (a, mut b) = Loopback::pair().unwrap();
+    let delay = Duration::from_millis(30);
+    let mut impaired = Impaired::new(a, Impairment::new().delay(delay, Duration::ZERO)).unwrap();
+
+    let sent_at = Instant::now();
+    impaired.send(b"late").unwrap();
+    assert!(drain(&mut b, Duration::ZERO).is_empty());
+
+    // the packet thread would poll the device, and find it readable once the packet is due
+    let fd = unsafe { BorrowedFd::borrow_raw(impaired.as_raw_fd()) };
+    let mut pfd = [nix::poll::PollFd::new(&fd, nix::poll::PollFlags::POLLIN)];
+    assert_eq!(nix::poll::poll(&mut pfd, 1000).unwrap(), 1);
+    assert!(sent_at.elapsed() >= delay);
+    assert_eq!(impaired.recv(&mut [0u8; 1500]).unwrap(), 0);
+
+    assert_eq!(drain(&mut b, Duration::from_millis(10)), [b"late"]);
+}
+
+#[test]
+fn transfer_survives_a_bad_link() {
+    let bad = Impairment::new()
+        .loss(0.05)
+        .duplicate(0.05)
+        .reorder(0.05, Duration::from_millis(5))
+        .delay(Duration::from_millis(1), Duration::from_millis(1))
+        .seed(1);
+    let (a, b) = Loopback::pair().unwrap();
+    let mut server = Interface::builder()
+        .addr(SERVER, 24)
+        .initial_rtt(Duration::from_millis(10))
+        .rto(Duration::from_millis(50), Duration::from_millis(500))
+        .build_with_device(Impaired::new(a, bad).unwrap())
+        .unwrap();
+    let mut client = Interface::builder()
+        .addr(CLIENT, 24)
+        .initial_rtt(Duration::from_millis(10))
+        .rto(Duration::from_millis(50), Duration::from_millis(500))
+        .build_with_device(b)
+        .unwrap();
+    let mut listener = server.bind(7000).unwrap();
+
+    let accepted = thread::spawn(move || {
+        let mut stream = listener.accept().unwrap();
+        let mut data = Vec::new();
+        stream.read_to_end(&mut data).unwrap();
+        data
+    });
+
+    let sent: Vec<u8> = (0..20_000u32).map(|i| (i % 251) as u8).collect();
+    let mut stream = client.connect(SocketAddrV4::new(SERVER, 7000)).unwrap();
+    stream.write_all(&sent).unwrap();
+    stream.shutdown(std::net::Shutdown::Write).unwrap();
+
+    assert_eq!(accepted.join().unwrap(), sent);
+}
+
+#[test]
+fn corrupted_packets_are_dropped() {
+    let (a, b) = Loopback::pair().unwrap();
+    let corrupting = Impairment::new().corrupt(0.2).seed(3);
+    let mut server = Interface::builder()
+        .addr(SERVER, 24)
+        .initial_rtt(Duration::from_millis(10))
+        .rto(Duration::from_millis(50), Duration::from_millis(500))
+        .build_with_device(Impaired::new(a, corrupting).unwrap())
+        .unwrap();
+    let mut client = Interface::builder()
+        .addr(CLIENT, 24)
+        .initial_rtt(Duration::from_millis(10))
+        .rto(Duration::from_millis(50), Duration::from_millis(500))
+        .build_with_device(b)
+        .unwrap();
+    let mut listener = server.bind(7000).unwrap();
+
+    let accepted = thread::spawn(move || {
+        let mut stream = listener.accept().unwrap();
+        let mut data = Vec::new();
+        stream.read_to_end(&mut data).unwrap();
+        data
+    });
+
+    let sent: Vec<u8> = (0..20_000u32).map(|i| (i % 251) as u8).collect();
+    let mut stream = client.connect(SocketAddrV4::new(SERVER, 7000)).unwrap();
+    stream.write_all(&sent).unwrap();
+    stream.shutdown(std::net::Shutdown::Write).unwrap();
+
+    // retransmissions make up for what was dropped
+    assert_eq!(accepted.join().unwrap(), sent);
+    assert!(server.checksum_errors() + client.checksum_errors() > 0);
+}
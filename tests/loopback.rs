@@ -0,0 +1,196 @@
+use std::{
+    io::{self, Read, Write},
+    net::{Ipv4Addr, Shutdown, SocketAddrV4},
+    sync::mpsc,
+    thread,
+    time::Duration,
+};
+
+use thunder::{Interface, Loopback};
+
+const SERVER: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 1);
+const CLIENT: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 2);
+
+fn stacks() -> (Interface, Interface) {
+    let (a, b) = Loopback::pair().unwrap();
+    (
+        Interface::with_device(a, SERVER).unwrap(),
+        Interface::with_device(b, CLIENT).unwrap(),
+    )
+}
+
+#[test]
+fn exchange_data_and_close() {
+    let (mut server, mut client) = stacks();
+    let mut listener = server.bind(7000).unwrap();
+
+    let accepted = thread::spawn(move || {
+        let mut stream = listener.accept().unwrap();
+        let mut buf = [0u8; 5];
+        stream.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"hello");
+        stream.write_all(b"world").unwrap();
+
+        // the client shuts down first
+        assert_eq!(stream.read(&mut buf).unwrap(), 0);
+        stream.shutdown(Shutdown::Write).unwrap();
+    });
+
+    let mut stream = client.connect(SocketAddrV4::new(SERVER, 7000)).unwrap();
+    stream.write_all(b"hello").unwrap();
+    let mut buf = [0u8; 5];
+    stream.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"world");
+
+    stream.shutdown(Shutdown::Write).unwrap();
+    assert_eq!(stream.read(&mut buf).unwrap(), 0);
+
+    accepted.join().unwrap();
+}
+
+#[test]
+fn transfer_more_than_the_send_queue() {
+    let (mut server, mut client) = stacks();
+    let mut listener = server.bind(7000).unwrap();
+    let data: Vec<u8> = (0..16 * 1024).map(|i| (i % 251) as u8).collect();
+
+    let expected = data.clone();
+    let accepted = thread::spawn(move || {
+        let mut stream = listener.accept().unwrap();
+        let mut received = Vec::new();
+        stream.read_to_end(&mut received).unwrap();
+        assert_eq!(received, expected);
+    });
+
+    let mut stream = client.connect(SocketAddrV4::new(SERVER, 7000)).unwrap();
+    stream.write_all(&data).unwrap();
+    stream.shutdown(Shutdown::Write).unwrap();
+
+    accepted.join().unwrap();
+}
+
+#[test]
+fn connect_to_closed_port_is_refused() {
+    let (_server, mut client) = stacks();
+
+    let err = client
+        .connect(SocketAddrV4::new(SERVER, 7000))
+        .err()
+        .unwrap();
+    assert_eq!(err.kind(), io::ErrorKind::ConnectionRefused);
+}
+
+#[test]
+fn urgent_byte_out_of_band() {
+    let (mut server, mut client) = stacks();
+    let mut listener = server.bind(7000).unwrap();
+    let (sent, all_sent) = mpsc::channel();
+
+    let accepted = thread::spawn(move || {
+        let mut stream = listener.accept().unwrap();
+        all_sent.recv().unwrap();
+
+        // reads stop at the mark
+        let mut buf = [0u8; 16];
+        assert_eq!(stream.read(&mut buf).unwrap(), 2);
+        assert_eq!(&buf[..2], b"ab");
+        assert!(stream.at_mark().unwrap());
+        assert_eq!(stream.recv_urgent().unwrap(), b'!');
+        let err = stream.recv_urgent().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+        assert_eq!(stream.read(&mut buf).unwrap(), 2);
+        assert_eq!(&buf[..2], b"cd");
+        assert!(!stream.at_mark().unwrap());
+    });
+
+    let mut stream = client.connect(SocketAddrV4::new(SERVER, 7000)).unwrap();
+    stream.write_all(b"ab").unwrap();
+    stream.send_urgent(b'!').unwrap();
+    stream.write_all(b"cd").unwrap();
+    // everything has arrived once it is acknowledged
+    stream.flush().unwrap();
+    sent.send(()).unwrap();
+
+    accepted.join().unwrap();
+}
+
+#[test]
+fn urgent_byte_inline() {
+    let (mut server, mut client) = stacks();
+    let mut listener = server.bind(7000).unwrap();
+    let (inline, is_inline) = mpsc::channel();
+    let (sent, all_sent) = mpsc::channel();
+
+    let accepted = thread::spawn(move || {
+        let mut stream = listener.accept().unwrap();
+        stream.set_oob_inline(true).unwrap();
+        inline.send(()).unwrap();
+        all_sent.recv().unwrap();
+
+        let mut buf = [0u8; 16];
+        assert_eq!(stream.read(&mut buf).unwrap(), 2);
+        assert_eq!(&buf[..2], b"ab");
+        assert!(stream.at_mark().unwrap());
+        let err = stream.recv_urgent().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+        // the urgent byte is part of the stream
+        assert_eq!(stream.read(&mut buf).unwrap(), 3);
+        assert_eq!(&buf[..3], b"!cd");
+    });
+
+    let mut stream = client.connect(SocketAddrV4::new(SERVER, 7000)).unwrap();
+    is_inline.recv().unwrap();
+    stream.write_all(b"ab").unwrap();
+    stream.send_urgent(b'!').unwrap();
+    stream.write_all(b"cd").unwrap();
+    stream.flush().unwrap();
+    sent.send(()).unwrap();
+
+    accepted.join().unwrap();
+}
+
+#[test]
+fn connections_never_accepted_are_closed_with_the_listener() {
+    let (mut server, mut client) = stacks();
+    let listener = server.bind(7000).unwrap();
+
+    let mut stream = client.connect(SocketAddrV4::new(SERVER, 7000)).unwrap();
+    drop(listener);
+
+    let mut buf = [0u8; 4];
+    assert_eq!(stream.read(&mut buf).unwrap(), 0);
+}
+
+#[test]
+fn keepalive_deadlines_do_not_pile_up_on_a_busy_connection() {
+    let (mut server, mut client) = stacks();
+    let mut listener = server.bind(7000).unwrap();
+    let idle = Some(Duration::from_secs(7200));
+
+    let accepted = thread::spawn(move || {
+        let mut stream = listener.accept().unwrap();
+        stream.set_keepalive(idle).unwrap();
+        let mut buf = [0u8; 1];
+        while stream.read(&mut buf).unwrap() > 0 {
+            stream.write_all(&buf).unwrap();
+        }
+    });
+
+    let mut stream = client.connect(SocketAddrV4::new(SERVER, 7000)).unwrap();
+    stream.set_keepalive(idle).unwrap();
+    // every segment pushes both keepalives back
+    let mut buf = [0u8; 1];
+    for i in 0..1000 {
+        stream.write_all(&[i as u8]).unwrap();
+        stream.read_exact(&mut buf).unwrap();
+    }
+    // while the connection is still up, since closing it makes every entry stale
+    for stack in [&server, &client] {
+        assert!(stack.queued_timers() < 100, "{}", stack.queued_timers());
+    }
+
+    stream.shutdown(Shutdown::Write).unwrap();
+    accepted.join().unwrap();
+}
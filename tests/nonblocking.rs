@@ -0,0 +1,118 @@
+use std::{
+    io::{self, Read, Write},
+    net::{Ipv4Addr, SocketAddrV4},
+    thread,
+    time::Duration,
+};
+
+use thunder::{Interface, Loopback, Replay, ReplayHandle};
+
+const SERVER: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 1);
+const CLIENT: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 2);
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+fn would_block<T>(result: io::Result<T>) {
+    match result {
+        Ok(_) => panic!("expected WouldBlock, got Ok"),
+        Err(e) => assert_eq!(e.kind(), io::ErrorKind::WouldBlock),
+    }
+}
+
+/// A client whose peer is played by the test, which has seen its SYN.
+fn connecting() -> (Interface, thunder::TcpStream, ReplayHandle) {
+    let (device, replay) = Replay::new().unwrap();
+    let mut client = Interface::with_device(device, CLIENT).unwrap();
+    let stream = client
+        .connect_nonblocking(SocketAddrV4::new(SERVER, 7000))
+        .unwrap();
+    replay.wait_sent(1, TIMEOUT).unwrap();
+    (client, stream, replay)
+}
+
+/// The peer's answer to the SYN the client sent first.
+fn syn_ack(replay: &ReplayHandle) -> Vec<u8> {
+    let sent = replay.wait_sent(1, TIMEOUT).unwrap();
+    let syn = etherparse::TcpHeaderSlice::from_slice(&sent[0][20..]).unwrap();
+    assert!(syn.syn());
+    let mut packet = Vec::new();
+    etherparse::PacketBuilder::ipv4(SERVER.octets(), CLIENT.octets(), 64)
+        .tcp(7000, syn.source_port(), 5000, 60000)
+        .syn()
+        .ack(syn.sequence_number().wrapping_add(1))
+        .write(&mut packet, &[])
+        .unwrap();
+    packet
+}
+
+#[test]
+fn reading_an_empty_stream_would_block() {
+    let (a, b) = Loopback::pair().unwrap();
+    let mut server = Interface::with_device(a, SERVER).unwrap();
+    let mut client = Interface::with_device(b, CLIENT).unwrap();
+    let mut listener = server.bind(7000).unwrap();
+    listener.set_nonblocking(true).unwrap();
+    would_block(listener.accept());
+
+    let mut stream = client.connect(SocketAddrV4::new(SERVER, 7000)).unwrap();
+    let mut accepted = listener.accept().unwrap();
+    accepted.set_nonblocking(true).unwrap();
+    let mut buf = [0u8; 8];
+    would_block(accepted.read(&mut buf));
+
+    stream.write_all(b"hi").unwrap();
+    stream.flush().unwrap();
+    assert_eq!(accepted.read(&mut buf).unwrap(), 2);
+    assert_eq!(&buf[..2], b"hi");
+    would_block(accepted.read(&mut buf));
+}
+
+#[test]
+fn writing_to_a_full_send_queue_would_block() {
+    let (_client, mut stream, replay) = connecting();
+    replay.inject(&syn_ack(&replay)).unwrap();
+    // the peer never acknowledges anything, so the queue only fills up
+    replay.wait_sent(2, TIMEOUT).unwrap();
+
+    let data = [7u8; 4096];
+    let written = stream.write(&data).unwrap();
+    assert!(written > 0 && written < data.len(), "wrote {written}");
+    would_block(stream.write(&data));
+}
+
+#[test]
+fn a_connect_in_progress_queues_writes() {
+    let (_client, mut stream, replay) = connecting();
+
+    let mut buf = [0u8; 8];
+    would_block(stream.read(&mut buf));
+    assert_eq!(stream.write(b"early").unwrap(), 5);
+    // nothing goes out but the SYN
+    assert!(replay.wait_sent(2, Duration::from_millis(100)).is_err());
+
+    replay.inject(&syn_ack(&replay)).unwrap();
+    let sent = replay.wait_sent(3, TIMEOUT).unwrap();
+    let data = &sent[2];
+    let tcph = etherparse::TcpHeaderSlice::from_slice(&data[20..]).unwrap();
+    assert_eq!(&data[20 + tcph.slice().len()..], b"early");
+}
+
+#[test]
+fn a_refused_connect_fails_the_next_read() {
+    let (a, b) = Loopback::pair().unwrap();
+    let _server = Interface::with_device(a, SERVER).unwrap();
+    let mut client = Interface::with_device(b, CLIENT).unwrap();
+    let mut stream = client
+        .connect_nonblocking(SocketAddrV4::new(SERVER, 7000))
+        .unwrap();
+
+    let mut buf = [0u8; 8];
+    let err = loop {
+        match stream.read(&mut buf) {
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                thread::sleep(Duration::from_millis(1))
+            }
+            result => break result.unwrap_err(),
+        }
+    };
+    assert_eq!(err.kind(), io::ErrorKind::ConnectionRefused);
+}
@@ -0,0 +1,135 @@
+use std::{
+    io::{self, Read, Write},
+    net::{Ipv4Addr, SocketAddrV4},
+    time::Duration,
+};
+
+use thunder::{Available, Interface, Loopback, Poller, TcpListener, TcpStream, Token};
+
+const SERVER: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 1);
+const CLIENT: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 2);
+const QUIET: Duration = Duration::from_millis(100);
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+struct Pair {
+    _server: Interface,
+    client_interface: Interface,
+    listener: TcpListener,
+    client: TcpStream,
+    accepted: TcpStream,
+}
+
+/// A connection over loopback, with the accepted end in non-blocking mode.
+fn pair() -> Pair {
+    let (a, b) = Loopback::pair().unwrap();
+    let mut server = Interface::with_device(a, SERVER).unwrap();
+    let mut client = Interface::with_device(b, CLIENT).unwrap();
+    let mut listener = server.bind(7000).unwrap();
+    let stream = client.connect(SocketAddrV4::new(SERVER, 7000)).unwrap();
+    let mut accepted = listener.accept().unwrap();
+    accepted.set_nonblocking(true).unwrap();
+    Pair {
+        _server: server,
+        client_interface: client,
+        listener,
+        client: stream,
+        accepted,
+    }
+}
+
+fn send(stream: &mut TcpStream, data: &[u8]) {
+    stream.write_all(data).unwrap();
+    stream.flush().unwrap();
+}
+
+fn drain(stream: &mut TcpStream) -> Vec<u8> {
+    let mut data = Vec::new();
+    let mut buf = [0u8; 64];
+    loop {
+        match stream.read(&mut buf) {
+            Ok(n) => data.extend_from_slice(&buf[..n]),
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => return data,
+            Err(e) => panic!("read failed: {e}"),
+        }
+    }
+}
+
+#[test]
+fn a_drained_stream_is_readable_again_once_data_arrives() {
+    let mut p = pair();
+    let poller = Poller::new();
+    poller
+        .register(&p.accepted, Token(1), Available::READ)
+        .unwrap();
+    assert!(drain(&mut p.accepted).is_empty());
+
+    let mut events = Vec::new();
+    assert_eq!(poller.wait(&mut events, Some(QUIET)).unwrap(), 0);
+
+    send(&mut p.client, b"hello");
+    assert_eq!(poller.wait(&mut events, Some(TIMEOUT)).unwrap(), 1);
+    assert_eq!(events[0].token(), Token(1));
+    assert!(events[0].is_readable());
+    assert_eq!(drain(&mut p.accepted), b"hello");
+}
+
+#[test]
+fn a_stream_that_stays_readable_is_reported_once() {
+    let mut p = pair();
+    let poller = Poller::new();
+    poller
+        .register(&p.accepted, Token(1), Available::READ)
+        .unwrap();
+
+    let mut events = Vec::new();
+    send(&mut p.client, b"one");
+    assert_eq!(poller.wait(&mut events, Some(TIMEOUT)).unwrap(), 1);
+    // not drained, so more data is no news
+    send(&mut p.client, b"two");
+    assert_eq!(poller.wait(&mut events, Some(QUIET)).unwrap(), 0);
+
+    assert_eq!(drain(&mut p.accepted), b"onetwo");
+    send(&mut p.client, b"three");
+    assert_eq!(poller.wait(&mut events, Some(TIMEOUT)).unwrap(), 1);
+}
+
+#[test]
+fn a_deregistered_stream_is_not_reported() {
+    let mut p = pair();
+    let poller = Poller::new();
+    poller
+        .register(&p.accepted, Token(1), Available::READ)
+        .unwrap();
+    poller.deregister(&p.accepted).unwrap();
+
+    send(&mut p.client, b"hello");
+    let mut events = Vec::new();
+    assert_eq!(poller.wait(&mut events, Some(QUIET)).unwrap(), 0);
+    assert_eq!(
+        poller.deregister(&p.accepted).unwrap_err().kind(),
+        io::ErrorKind::NotFound
+    );
+}
+
+#[test]
+fn a_listener_is_readable_with_pending_connections() {
+    let mut p = pair();
+    p.listener.set_nonblocking(true).unwrap();
+    let poller = Poller::new();
+    poller
+        .register(&p.listener, Token(0), Available::READ)
+        .unwrap();
+
+    let mut events = Vec::new();
+    assert_eq!(poller.wait(&mut events, Some(QUIET)).unwrap(), 0);
+
+    let _second = p
+        .client_interface
+        .connect(SocketAddrV4::new(SERVER, 7000))
+        .unwrap();
+    assert_eq!(poller.wait(&mut events, Some(TIMEOUT)).unwrap(), 1);
+    assert_eq!(events[0].token(), Token(0));
+    assert!(events[0].is_readable());
+    p.listener.accept().unwrap();
+    assert!(matches!(p.listener.accept(), Err(e) if e.kind() == io::ErrorKind::WouldBlock));
+}
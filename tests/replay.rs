@@ -0,0 +1,355 @@
+use std::{
+    io::{self, Read, Write},
+    net::{Ipv4Addr, Shutdown, SocketAddrV4},
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use thunder::{
+    Connection, Interface, Loopback, MockClock, NetDevice, PcapReader, Replay, TcpListener,
+};
+
+const SERVER: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 1);
+const CLIENT: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 2);
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Default)]
+struct Shared(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Shared {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Echo one message back, then close once the client has.
+fn echo_once(mut listener: TcpListener) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        let mut stream = listener.accept().unwrap();
+        let mut buf = [0u8; 4];
+        stream.read_exact(&mut buf).unwrap();
+        stream.write_all(&buf).unwrap();
+        assert_eq!(stream.read(&mut buf).unwrap(), 0);
+    })
+}
+
+/// A server that starts every connection at the same sequence number, so that its answers to a
+/// replay can match the capture.
+fn fixed_iss_server<D: NetDevice + Send + 'static>(device: D) -> Interface {
+    Interface::builder()
+        .addr(SERVER, 32)
+        .initial_sequence(1000)
+        .build_with_device(device)
+        .unwrap()
+}
+
+/// The packets `addr` sent in a capture.
+fn sent_by(pcap: &[u8], addr: Ipv4Addr) -> Vec<Vec<u8>> {
+    let mut reader = PcapReader::new(pcap).unwrap();
+    let mut packets = Vec::new();
+    while let Some((_, packet)) = reader.read_packet().unwrap() {
+        let iph = etherparse::Ipv4HeaderSlice::from_slice(&packet).unwrap();
+        if iph.source_addr() == addr {
+            packets.push(packet);
+        }
+    }
+    packets
+}
+
+#[test]
+fn replaying_a_capture_reproduces_the_stacks_answers() {
+    // record a whole connection from the server's side
+    let (a, b) = Loopback::pair().unwrap();
+    let mut server = fixed_iss_server(a);
+    let mut client = Interface::with_device(b, CLIENT).unwrap();
+    let capture = Shared::default();
+    server.capture(capture.clone()).unwrap();
+    let accepted = echo_once(server.bind(7000).unwrap());
+
+    let mut stream = client.connect(SocketAddrV4::new(SERVER, 7000)).unwrap();
+    stream.write_all(b"ping").unwrap();
+    let mut buf = [0u8; 4];
+    stream.read_exact(&mut buf).unwrap();
+    stream.shutdown(Shutdown::Write).unwrap();
+    assert_eq!(stream.read(&mut buf).unwrap(), 0);
+    accepted.join().unwrap();
+    server.stop_capture().unwrap();
+    let pcap = capture.0.lock().unwrap().clone();
+
+    // then play the client's half back to a fresh server
+    let (device, replay) = Replay::new().unwrap();
+    let mut server = fixed_iss_server(device);
+    let accepted = echo_once(server.bind(7000).unwrap());
+
+    let sent = replay
+        .replay(PcapReader::new(&pcap[..]).unwrap(), SERVER, TIMEOUT)
+        .unwrap();
+    accepted.join().unwrap();
+
+    assert_eq!(sent, sent_by(&pcap, SERVER));
+}
+
+#[test]
+fn replayed_syn_to_a_closed_port_is_reset() {
+    let syn = {
+        let mut tcp = etherparse::TcpHeader::new(50000, 7000, 1000, 1024);
+        tcp.syn = true;
+        let ip = etherparse::Ipv4Header::new(
+            tcp.header_len() as u16,
+            64,
+            etherparse::IpNumber::TCP,
+            CLIENT.octets(),
+            SERVER.octets(),
+        )
+        .unwrap();
+        tcp.checksum = tcp.calc_checksum_ipv4(&ip, &[]).unwrap();
+        let mut packet = Vec::new();
+        ip.write(&mut packet).unwrap();
+        tcp.write(&mut packet).unwrap();
+        packet
+    };
+
+    let (device, replay) = Replay::new().unwrap();
+    let _server = Interface::with_device(device, SERVER).unwrap();
+    replay.inject(&syn).unwrap();
+
+    let sent = replay.wait_sent(1, TIMEOUT).unwrap();
+    let rst = etherparse::TcpHeaderSlice::from_slice(&sent[0][20..]).unwrap();
+    assert!(rst.rst() && rst.ack());
+    assert_eq!(rst.acknowledgment_number(), 1001);
+}
+
+#[test]
+fn packets_with_bad_checksums_are_dropped() {
+    let mut syn = Vec::new();
+    etherparse::PacketBuilder::ipv4(CLIENT.octets(), SERVER.octets(), 64)
+        .tcp(40000, 7000, 1000, 1024)
+        .syn()
+        .write(&mut syn, &[])
+        .unwrap();
+    // the TCP checksum
+    syn[36] ^= 0xff;
+
+    let (device, replay) = Replay::new().unwrap();
+    let server = Interface::with_device(device, SERVER).unwrap();
+    replay.inject(&syn).unwrap();
+    assert!(replay.wait_sent(1, Duration::from_millis(200)).is_err());
+    assert_eq!(server.checksum_errors(), 1);
+
+    // unless the device is trusted with them
+    let (device, replay) = Replay::new().unwrap();
+    let server = Interface::builder()
+        .addr(SERVER, 32)
+        .verify_checksums(false)
+        .build_with_device(device)
+        .unwrap();
+    replay.inject(&syn).unwrap();
+    let sent = replay.wait_sent(1, TIMEOUT).unwrap();
+    assert!(etherparse::TcpHeaderSlice::from_slice(&sent[0][20..])
+        .unwrap()
+        .rst());
+    assert_eq!(server.checksum_errors(), 0);
+}
+
+#[test]
+fn syns_beyond_the_backlog_are_answered_with_cookies() {
+    let (device, replay) = Replay::new().unwrap();
+    let mut server = Interface::builder()
+        .addr(SERVER, 32)
+        .syn_backlog(4)
+        .build_with_device(device)
+        .unwrap();
+    let _listener = server.bind(7000).unwrap();
+
+    let ports = 40000..40020;
+    for port in ports.clone() {
+        let mut syn = Vec::new();
+        etherparse::PacketBuilder::ipv4(CLIENT.octets(), SERVER.octets(), 64)
+            .tcp(port, 7000, 1000, 1024)
+            .syn()
+            .write(&mut syn, &[])
+            .unwrap();
+        replay.inject(&syn).unwrap();
+    }
+    let sent = replay.wait_sent(ports.len(), TIMEOUT).unwrap();
+    let syn_acks: Vec<_> = sent
+        .iter()
+        .map(|packet| etherparse::TcpHeaderSlice::from_slice(&packet[20..]).unwrap())
+        .collect();
+    assert!(syn_acks.iter().all(|tcph| tcph.syn() && tcph.ack()));
+    assert_eq!(server.syn_cookies_sent(), 16);
+
+    // the last peer completes the handshake, and sends data along with its ACK
+    let cookie = syn_acks.last().unwrap();
+    let mut ack = Vec::new();
+    etherparse::PacketBuilder::ipv4(CLIENT.octets(), SERVER.octets(), 64)
+        .tcp(cookie.destination_port(), 7000, 1001, 1024)
+        .ack(cookie.sequence_number().wrapping_add(1))
+        .write(&mut ack, b"hi")
+        .unwrap();
+    replay.inject(&ack).unwrap();
+
+    let sent = replay.wait_sent(ports.len() + 1, TIMEOUT).unwrap();
+    let reply = etherparse::TcpHeaderSlice::from_slice(&sent[ports.len()][20..]).unwrap();
+    assert_eq!(reply.destination_port(), cookie.destination_port());
+    assert!(reply.ack() && !reply.rst());
+    assert_eq!(reply.acknowledgment_number(), 1003);
+}
+
+/// A SYN from `CLIENT:port` to `SERVER:7000`.
+fn syn_from(port: u16) -> Vec<u8> {
+    let mut syn = Vec::new();
+    etherparse::PacketBuilder::ipv4(CLIENT.octets(), SERVER.octets(), 64)
+        .tcp(port, 7000, 1000, 1024)
+        .syn()
+        .write(&mut syn, &[])
+        .unwrap();
+    syn
+}
+
+/// Where a segment the server sent goes, and whether it is a SYN-ACK.
+fn syn_ack_to(packet: &[u8]) -> (u16, bool) {
+    let tcph = etherparse::TcpHeaderSlice::from_slice(&packet[20..]).unwrap();
+    (tcph.destination_port(), tcph.syn() && tcph.ack())
+}
+
+/// The ACK that completes the handshake `syn_ack` answered, with `data` along with it.
+fn ack_of(syn_ack: &[u8], data: &[u8]) -> Vec<u8> {
+    let tcph = etherparse::TcpHeaderSlice::from_slice(&syn_ack[20..]).unwrap();
+    let mut ack = Vec::new();
+    etherparse::PacketBuilder::ipv4(CLIENT.octets(), SERVER.octets(), 64)
+        .tcp(tcph.destination_port(), 7000, 1001, 1024)
+        .ack(tcph.sequence_number().wrapping_add(1))
+        .write(&mut ack, data)
+        .unwrap();
+    ack
+}
+
+#[test]
+fn completed_handshakes_leave_the_backlog() {
+    let (device, replay) = Replay::new().unwrap();
+    let mut server = Interface::builder()
+        .addr(SERVER, 32)
+        .syn_backlog(2)
+        .build_with_device(device)
+        .unwrap();
+    let _listener = server.bind(7000).unwrap();
+
+    for port in [40000, 40001] {
+        replay.inject(&syn_from(port)).unwrap();
+    }
+    for syn_ack in replay.wait_sent(2, TIMEOUT).unwrap() {
+        replay.inject(&ack_of(&syn_ack, &[])).unwrap();
+    }
+
+    // both are established, so the backlog is empty again
+    replay.inject(&syn_from(40002)).unwrap();
+    let sent = replay.wait_sent(3, TIMEOUT).unwrap();
+    assert_eq!(syn_ack_to(&sent[2]), (40002, true));
+    assert_eq!(server.syn_cookies_sent(), 0);
+}
+
+#[test]
+fn unanswered_syn_acks_time_out_of_the_backlog() {
+    let clock = MockClock::new();
+    let (device, replay) = Replay::new().unwrap();
+    let mut server = Interface::builder()
+        .addr(SERVER, 32)
+        .syn_backlog(1)
+        .retries(2, 15)
+        .clock(clock.clone())
+        .build_with_device(device)
+        .unwrap();
+    let mut listener = server.bind(7000).unwrap();
+    listener.set_nonblocking(true).unwrap();
+
+    replay.inject(&syn_from(40000)).unwrap();
+    replay.inject(&syn_from(40001)).unwrap();
+    replay.wait_sent(2, TIMEOUT).unwrap();
+    assert_eq!(server.syn_cookies_sent(), 1);
+
+    // the SYN-ACK goes out twice more, then the server gives up on the peer
+    for _ in 0..3 {
+        clock.advance(Duration::from_secs(60));
+    }
+    let sent = replay.wait_sent(4, TIMEOUT).unwrap();
+    assert_eq!(syn_ack_to(&sent[2]), (40000, true));
+    assert_eq!(syn_ack_to(&sent[3]), (40000, true));
+    let err = listener.accept().err().expect("nothing to accept");
+    assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+
+    replay.inject(&syn_from(40002)).unwrap();
+    let sent = replay.wait_sent(5, TIMEOUT).unwrap();
+    assert_eq!(syn_ack_to(&sent[4]), (40002, true));
+    assert_eq!(server.syn_cookies_sent(), 1);
+}
+
+#[test]
+fn cookies_are_only_taken_back_while_they_are_handed_out() {
+    // a cookie for 40000 made with the server's secret, as an attacker would have to guess it
+    let secret = Interface::builder().isn_seed(7).config().unwrap();
+    let forged = Connection::syn_cookie(&syn_from(40000), &secret, Instant::now())
+        .unwrap()
+        .unwrap();
+    let forged_ack = ack_of(&forged.segments[0], b"hi");
+    let server = |backlog| {
+        let (device, replay) = Replay::new().unwrap();
+        let mut server = Interface::builder()
+            .addr(SERVER, 32)
+            .isn_seed(7)
+            .syn_backlog(backlog)
+            .build_with_device(device)
+            .unwrap();
+        let listener = server.bind(7000).unwrap();
+        (server, listener, replay)
+    };
+
+    // without a flood, there is no cookie to bring back
+    let (_server, _listener, replay) = server(128);
+    replay.inject(&forged_ack).unwrap();
+    let sent = replay.wait_sent(1, TIMEOUT).unwrap();
+    assert!(etherparse::TcpHeaderSlice::from_slice(&sent[0][20..])
+        .unwrap()
+        .rst());
+
+    // the same ACK is taken once the server is handing out cookies
+    let (server, _listener, replay) = server(0);
+    replay.inject(&syn_from(40001)).unwrap();
+    replay.inject(&forged_ack).unwrap();
+    let sent = replay.wait_sent(2, TIMEOUT).unwrap();
+    assert_eq!(server.syn_cookies_sent(), 1);
+    let reply = etherparse::TcpHeaderSlice::from_slice(&sent[1][20..]).unwrap();
+    assert_eq!(reply.destination_port(), 40000);
+    assert!(reply.ack() && !reply.rst());
+    assert_eq!(reply.acknowledgment_number(), 1003);
+}
+
+#[test]
+fn a_full_accept_queue_turns_connections_away() {
+    let (device, replay) = Replay::new().unwrap();
+    let mut server = Interface::builder()
+        .addr(SERVER, 32)
+        .accept_backlog(1)
+        .build_with_device(device)
+        .unwrap();
+    let mut listener = server.bind(7000).unwrap();
+
+    replay.inject(&syn_from(40000)).unwrap();
+    let sent = replay.wait_sent(1, TIMEOUT).unwrap();
+    replay.inject(&ack_of(&sent[0], &[])).unwrap();
+
+    // the SYN is dropped, for the peer to send again
+    replay.inject(&syn_from(40001)).unwrap();
+    assert!(replay.wait_sent(2, Duration::from_millis(200)).is_err());
+
+    let _accepted = listener.accept().unwrap();
+    replay.inject(&syn_from(40001)).unwrap();
+    let sent = replay.wait_sent(2, TIMEOUT).unwrap();
+    assert_eq!(syn_ack_to(&sent[1]), (40001, true));
+}
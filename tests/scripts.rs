@@ -0,0 +1,86 @@
+use std::{fs, net::Ipv4Addr, path::Path, time::Duration};
+
+use thunder::{Interface, InterfaceBuilder, Script};
+
+fn stack() -> InterfaceBuilder {
+    Interface::builder()
+        .addr(Ipv4Addr::new(10, 0, 0, 1), 24)
+        .delayed_ack(Duration::from_millis(40))
+        .initial_rtt(Duration::from_millis(100))
+        .rto(Duration::from_millis(200), Duration::from_secs(10))
+        .msl(Duration::from_secs(1))
+}
+
+/// Every script in `tests/scripts` passes.
+#[test]
+fn scripts() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/scripts");
+    let mut paths: Vec<_> = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "pkt"))
+        .collect();
+    paths.sort();
+    assert!(!paths.is_empty());
+
+    let failures: Vec<_> = paths
+        .iter()
+        .filter_map(|path| {
+            let text = fs::read_to_string(path).unwrap();
+            let result = Script::parse(&text).and_then(|script| script.run(stack()));
+            result.err().map(|e| format!("{}: {e}", path.display()))
+        })
+        .collect();
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
+
+#[test]
+fn a_wrong_expectation_names_its_line() {
+    let script = Script::parse(
+        "0 listen(8080) = 0
+         +0 < S 0:0(0) win 1000
+         +0 > S. 0:0(0) ack 2",
+    )
+    .unwrap();
+    let err = script.run(stack()).unwrap_err().to_string();
+    assert!(
+        err.starts_with("line 3: expected `S. 0:0(0) ack 2`"),
+        "{err}"
+    );
+}
+
+#[test]
+fn an_unexpected_segment_fails() {
+    let script = Script::parse("0 < S 0:0(0) win 1000").unwrap();
+    let err = script.run(stack()).unwrap_err().to_string();
+    assert!(err.contains("unexpected `R. 0:0(0) ack 1"), "{err}");
+}
+
+#[test]
+fn segments_must_be_on_time() {
+    let script = Script::parse(
+        "0 connect(7000)
+         +0 > S 0:0(0)
+         +.1 > S 0:0(0)",
+    )
+    .unwrap();
+    let err = script.run(stack()).unwrap_err().to_string();
+    assert!(err.contains("nothing was sent"), "{err}");
+
+    let script = script.tolerance(Duration::from_millis(100));
+    script.run(stack()).unwrap();
+}
+
+#[test]
+fn bad_lines_are_rejected() {
+    for (text, msg) in [
+        ("0 < S 0:1(0)", "does not add up"),
+        ("+0 < . 1:1(0) win 1", "needs an ACK number"),
+        ("0 > S 0:0(0) <mss 1460>", "options"),
+        ("0 listen() = 0", "takes a port"),
+        ("1 close()\n0 close()", "backwards"),
+    ] {
+        let err = Script::parse(text).unwrap_err();
+        assert!(err.to_string().contains(msg), "{text}: {err}");
+    }
+}
@@ -0,0 +1,81 @@
+use proptest::prelude::*;
+use thunder::SeqNum;
+
+/// Sequence numbers close to the wrap point, where plain `u32` comparisons go wrong.
+fn near_wrap() -> impl Strategy<Value = SeqNum> {
+    (-4096i64..4096).prop_map(|offset| SeqNum::new((u32::MAX as i64 + 1 + offset) as u32))
+}
+
+fn any_seq() -> impl Strategy<Value = SeqNum> {
+    prop_oneof![near_wrap(), any::<u32>().prop_map(SeqNum::new)]
+}
+
+/// Distances a single window can span.
+fn distance() -> impl Strategy<Value = u32> {
+    1..(1u32 << 31)
+}
+
+proptest! {
+    #[test]
+    fn adding_moves_forward(a in any_seq(), n in distance()) {
+        let b = a + n;
+        prop_assert!(a.is_before(b));
+        prop_assert!(b.is_after(a));
+        prop_assert!(!b.is_before(a));
+    }
+
+    #[test]
+    fn distance_undoes_addition(a in any_seq(), n in any::<u32>()) {
+        prop_assert_eq!((a + n) - a, n);
+        prop_assert_eq!(a + n - n, a);
+
+        let mut b = a;
+        b += n;
+        b -= n;
+        prop_assert_eq!(b, a);
+    }
+
+    #[test]
+    fn exactly_one_order_holds(a in any_seq(), b in any_seq()) {
+        let orders = [a == b, a.is_before(b), a.is_after(b), b - a == 1 << 31];
+        prop_assert_eq!(orders.iter().filter(|&&o| o).count(), 1);
+    }
+
+    #[test]
+    fn a_number_is_not_before_itself(a in any_seq()) {
+        prop_assert!(!a.is_before(a));
+        prop_assert!(!a.is_after(a));
+    }
+
+    #[test]
+    fn half_the_circle_away_is_unordered(a in any_seq()) {
+        let b = a + (1 << 31);
+        prop_assert!(!a.is_before(b));
+        prop_assert!(!b.is_before(a));
+    }
+
+    #[test]
+    fn windows_across_the_wrap(start in near_wrap(), len in 2u32..65536, at in 0u32..65536) {
+        let end = start + len;
+        let x = start + at;
+        prop_assert_eq!(x.is_between(start, end), at > 0 && at < len);
+    }
+
+    #[test]
+    fn wire_value_round_trips(n in any::<u32>()) {
+        prop_assert_eq!(u32::from(SeqNum::from(n)), n);
+        prop_assert_eq!(SeqNum::new(n).get(), n);
+        prop_assert_eq!(SeqNum::new(n).to_string(), n.to_string());
+    }
+}
+
+#[test]
+fn the_wrap_point() {
+    let last = SeqNum::new(u32::MAX);
+    let first = SeqNum::new(0);
+    assert!(last.is_before(first));
+    assert_eq!(last + 1, first);
+    assert_eq!(first - last, 1);
+    assert_eq!(first - 1, last);
+    assert!(first.is_between(last, first + 1));
+}
@@ -0,0 +1,182 @@
+use std::{
+    cell::RefCell,
+    net::{Ipv4Addr, SocketAddrV4},
+    rc::Rc,
+    time::Duration,
+};
+
+use thunder::{Host, Interface, InterfaceBuilder, Link, Simulator};
+
+const SERVER: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 1);
+
+fn host(addr: Ipv4Addr) -> InterfaceBuilder {
+    Interface::builder()
+        .addr(addr, 24)
+        .send_buffer(64 * 1024)
+        .recv_window(u16::MAX)
+        .delayed_ack(Duration::from_millis(1))
+        .initial_rtt(Duration::from_millis(100))
+        .rto(Duration::from_millis(200), Duration::from_secs(10))
+}
+
+fn pattern(len: usize, salt: u8) -> Vec<u8> {
+    (0..len).map(|i| (i % 251) as u8 ^ salt).collect()
+}
+
+/// Have `client` send `data` to whatever accepts it on `port`, and collect what arrives.
+fn transfer(
+    sim: &mut Simulator,
+    server: &Host,
+    port: u16,
+    client: &Host,
+    data: Vec<u8>,
+) -> Rc<RefCell<Vec<u8>>> {
+    let received = Rc::new(RefCell::new(Vec::new()));
+    let mut listener = sim.block_on(server.bind(port)).unwrap().unwrap();
+
+    let sink = received.clone();
+    sim.spawn(async move {
+        let mut stream = listener.accept_async().await.unwrap();
+        let mut buf = vec![0u8; 64 * 1024];
+        loop {
+            let n = stream.read_async(&mut buf).await.unwrap();
+            if n == 0 {
+                break;
+            }
+            sink.borrow_mut().extend_from_slice(&buf[..n]);
+        }
+    });
+
+    let (client, addr) = (client.clone(), SocketAddrV4::new(server.addr(), port));
+    sim.spawn(async move {
+        let mut stream = client.connect(addr).await.unwrap();
+        for mut chunk in data.chunks(64 * 1024) {
+            while !chunk.is_empty() {
+                let n = stream.write_async(chunk).await.unwrap();
+                chunk = &chunk[n..];
+            }
+        }
+        // dropping the stream sends FIN once everything has gone out
+    });
+
+    received
+}
+
+#[test]
+fn long_transfer_wraps_the_sequence_space() {
+    let mut sim = Simulator::new(1);
+    let server = sim.add_host(host(SERVER)).unwrap();
+    let client = sim
+        .add_host(host(Ipv4Addr::new(10, 0, 0, 2)).initial_sequence(u32::MAX - 100_000))
+        .unwrap();
+    let router = sim.add_router();
+    let link = Link::new(100_000_000, Duration::from_millis(5));
+    sim.link(client.id(), router, link.clone()).unwrap();
+    sim.link(router, server.id(), link).unwrap();
+
+    let data = pattern(4 << 20, 0);
+    let received = transfer(&mut sim, &server, 7000, &client, data.clone());
+    sim.run().unwrap();
+
+    assert!(*received.borrow() == data);
+    // one window per round trip of 20ms
+    assert!(
+        sim.elapsed() > Duration::from_secs(1),
+        "{:?}",
+        sim.elapsed()
+    );
+}
+
+/// The stack has no congestion control, so this only shows how a drop-tail queue splits the
+/// bottleneck between two flows that each send a full window, not how they converge.
+#[test]
+fn competing_flows_split_a_drop_tail_bottleneck() {
+    let mut sim = Simulator::new(2);
+    let server = sim.add_host(host(SERVER)).unwrap();
+    let a = sim.add_host(host(Ipv4Addr::new(10, 0, 0, 2))).unwrap();
+    let b = sim.add_host(host(Ipv4Addr::new(10, 0, 0, 3))).unwrap();
+    let router = sim.add_router();
+    let fast = Link::new(100_000_000, Duration::from_millis(1));
+    sim.link(a.id(), router, fast.clone()).unwrap();
+    sim.link(b.id(), router, fast).unwrap();
+    let bottleneck = Link::new(10_000_000, Duration::from_millis(10)).queue(20);
+    sim.link(router, server.id(), bottleneck).unwrap();
+
+    // recovering from a loss takes a retransmission timeout per segment, so this is generous
+    const LIMIT: Duration = Duration::from_secs(1000);
+    let (from_a, from_b) = (pattern(1 << 20, 0x55), pattern(1 << 20, 0xaa));
+    let got_a = transfer(&mut sim, &server, 7000, &a, from_a.clone());
+    let got_b = transfer(&mut sim, &server, 7001, &b, from_b.clone());
+
+    // when each flow had all of its data through
+    let mut done = [None, None];
+    while done.contains(&None) {
+        assert!(sim.elapsed() < LIMIT, "stalled: {done:?}");
+        sim.run_for(Duration::from_millis(100)).unwrap();
+        for (at, got) in done.iter_mut().zip([&got_a, &got_b]) {
+            if at.is_none() && got.borrow().len() >= 1 << 20 {
+                *at = Some(sim.elapsed());
+            }
+        }
+    }
+
+    assert!(*got_a.borrow() == from_a);
+    assert!(*got_b.borrow() == from_b);
+    // neither flow got less than half the throughput of the other
+    let [Some(a_took), Some(b_took)] = done else {
+        unreachable!()
+    };
+    assert!(a_took < 2 * b_took && b_took < 2 * a_took, "{done:?}");
+    // the bottleneck only has room for one flow's window
+    let stats = sim.link_stats(router, server.id()).unwrap();
+    assert!(stats.dropped > 0, "{stats:?}");
+    assert!(stats.bytes >= 2 << 20, "{stats:?}");
+}
+
+#[test]
+fn a_seed_reproduces_its_run() {
+    let run = |seed| {
+        let mut sim = Simulator::new(seed);
+        let server = sim.add_host(host(SERVER)).unwrap();
+        let client = sim.add_host(host(Ipv4Addr::new(10, 0, 0, 2))).unwrap();
+        let lossy = Link::new(10_000_000, Duration::from_millis(2)).loss(0.05);
+        sim.link(client.id(), server.id(), lossy).unwrap();
+
+        let data = pattern(256 * 1024, 0);
+        let received = transfer(&mut sim, &server, 7000, &client, data.clone());
+        sim.run().unwrap();
+        assert!(*received.borrow() == data);
+
+        let stats = sim.link_stats(client.id(), server.id()).unwrap();
+        (sim.elapsed(), stats)
+    };
+
+    let first = run(7);
+    assert!(first.1.lost > 0, "{first:?}");
+    assert_eq!(first, run(7));
+    assert_ne!(first, run(8));
+}
+
+#[test]
+fn blocking_calls_are_refused() {
+    let mut sim = Simulator::new(0);
+    let server = sim.add_host(host(SERVER)).unwrap();
+    let mut listener = sim.block_on(server.bind(7000)).unwrap().unwrap();
+
+    let err = listener.accept().err().expect("accept returned");
+    assert_eq!(err.kind(), std::io::ErrorKind::Unsupported);
+}
+
+#[test]
+fn waiting_for_nothing_stalls() {
+    let mut sim = Simulator::new(0);
+    let server = sim.add_host(host(SERVER)).unwrap();
+    let mut listener = sim.block_on(server.bind(7000)).unwrap().unwrap();
+
+    sim.spawn(async move {
+        let _ = listener.accept_async().await;
+    });
+    assert!(sim.run().is_err());
+    sim.run_for(Duration::from_secs(3600)).unwrap();
+    assert_eq!(sim.elapsed(), Duration::from_secs(3600));
+}